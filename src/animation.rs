@@ -0,0 +1,167 @@
+use std::path::PathBuf;
+
+use cgmath::{InnerSpace, Matrix3, Quaternion, Rotation, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::bookmarks::Bookmark;
+
+// Time between keyframes added from the viewer, in seconds
+const DEFAULT_KEY_SPACING: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds from the start of the path.
+    pub time: f32,
+    pub view: Bookmark,
+}
+
+/// Keyframed camera animation with its sequence render settings, kept in a
+/// sidecar file next to the scene.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraPath {
+    pub fps: f32,
+    /// Samples per pixel of each frame in a rendered sequence.
+    pub samples_per_frame: u32,
+    /// Directory the numbered frames are written to.
+    pub output_dir: String,
+    pub keyframes: Vec<Keyframe>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Default for CameraPath {
+    fn default() -> Self {
+        Self {
+            fps: 24.0,
+            samples_per_frame: 256,
+            output_dir: "frames".into(),
+            keyframes: Vec::new(),
+            path: PathBuf::new(),
+        }
+    }
+}
+
+impl CameraPath {
+    /// The path for `scene_path`, stored as `<scene>.path.ron`. A missing
+    /// sidecar starts an empty path.
+    pub fn load(scene_path: &str) -> Self {
+        let path = PathBuf::from(scene_path).with_extension("path.ron");
+        let mut camera_path = match std::fs::read_to_string(&path) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|e| {
+                eprintln!("Failed to parse {}: {e}", path.display());
+                CameraPath::default()
+            }),
+            Err(_) => CameraPath::default(),
+        };
+        camera_path
+            .keyframes
+            .sort_by(|a, b| a.time.total_cmp(&b.time));
+        camera_path.path = path;
+        camera_path
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, text).map_err(|e| e.to_string())
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Append a keyframe after the last one and save the path.
+    pub fn push(&mut self, view: Bookmark) -> Result<f32, String> {
+        let time = self
+            .keyframes
+            .last()
+            .map_or(0.0, |key| key.time + DEFAULT_KEY_SPACING);
+        self.keyframes.push(Keyframe { time, view });
+        self.save()?;
+        Ok(time)
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |key| key.time)
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        (self.duration() * self.fps).floor() as u32 + 1
+    }
+
+    /// The view at `time`: Catmull-Rom through positions, field of view, lens
+    /// and exposure, slerp between orientations. Clamps outside the keyed range.
+    pub fn sample(&self, time: f32) -> Option<Bookmark> {
+        let keys = &self.keyframes;
+        let last = keys.len().checked_sub(1)?;
+        let i = keys
+            .iter()
+            .rposition(|key| key.time <= time)
+            .unwrap_or(0)
+            .min(last.saturating_sub(1));
+        if last == 0 {
+            return Some(keys[0].view);
+        }
+        let span = (keys[i + 1].time - keys[i].time).max(1e-6);
+        let s = ((time - keys[i].time) / span).clamp(0.0, 1.0);
+
+        // Endpoints are repeated so the curve stops at the first and last key
+        let k0 = &keys[i.saturating_sub(1)].view;
+        let k1 = &keys[i].view;
+        let k2 = &keys[i + 1].view;
+        let k3 = &keys[(i + 2).min(last)].view;
+        let spline = |f: fn(&Bookmark) -> f32| catmull_rom(f(k0), f(k1), f(k2), f(k3), s);
+
+        let rotation = slerp(orientation(k1), orientation(k2), s);
+        let right = rotation.rotate_vector(Vector3::unit_x());
+        let up = rotation.rotate_vector(Vector3::unit_y());
+        Some(Bookmark {
+            origin: [
+                spline(|k| k.origin[0]),
+                spline(|k| k.origin[1]),
+                spline(|k| k.origin[2]),
+            ],
+            right: right.into(),
+            up: up.into(),
+            vfov: spline(|k| k.vfov).clamp(1.0, 179.0),
+            aperture_radius: spline(|k| k.aperture_radius).max(0.0),
+            focus_distance: spline(|k| k.focus_distance).max(1e-3),
+            aperture_rotation: k1.aperture_rotation
+                + (k2.aperture_rotation - k1.aperture_rotation) * s,
+            fisheye_fov: spline(|k| k.fisheye_fov).clamp(1.0, 360.0),
+            iso: spline(|k| k.iso).max(1.0),
+            shutter_time: spline(|k| k.shutter_time).max(1e-6),
+            f_number: spline(|k| k.f_number).max(0.5),
+            white_balance: spline(|k| k.white_balance).clamp(1000.0, 15000.0),
+            tint: spline(|k| k.tint),
+            // Projection, aperture, fit and white preset switch at the key
+            ..*k1
+        })
+    }
+}
+
+fn catmull_rom(p0: f32, p1: f32, p2: f32, p3: f32, s: f32) -> f32 {
+    0.5 * (2.0 * p1
+        + (p2 - p0) * s
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * s * s
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * s * s * s)
+}
+
+/// Rotation taking the x and y axes to the view's right and up vectors.
+fn orientation(view: &Bookmark) -> Quaternion<f32> {
+    let right = Vector3::from(view.right).normalize();
+    let up = Vector3::from(view.up).normalize();
+    Quaternion::from(Matrix3::from_cols(right, up, right.cross(up)))
+}
+
+/// Spherical interpolation along the shorter arc.
+fn slerp(a: Quaternion<f32>, b: Quaternion<f32>, s: f32) -> Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    let cos_theta = a.dot(b).min(1.0);
+    if cos_theta > 0.9995 {
+        return (a * (1.0 - s) + b * s).normalize();
+    }
+    let theta = cos_theta.acos();
+    (a * ((1.0 - s) * theta).sin() + b * (s * theta).sin()) / theta.sin()
+}
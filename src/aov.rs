@@ -0,0 +1,96 @@
+//! Arbitrary output variables: guide layers from the first diffuse hit of
+//! each camera path, for compositing and denoising.
+
+use crate::export;
+
+/// Per-pixel sums written by `store_aov` in the mega kernel.
+pub struct AovTextures {
+    /// Material colour, with the count of samples that found a hit in w.
+    pub albedo: wgpu::Texture,
+    /// Shading normal, with linear depth in w.
+    pub normal: wgpu::Texture,
+    pub position: wgpu::Texture,
+    /// 1 + material id and object id of the first hit, 0 for none.
+    pub ids: wgpu::Texture,
+}
+
+impl AovTextures {
+    pub fn new(device: &wgpu::Device, size: &winit::dpi::PhysicalSize<u32>) -> Self {
+        let create = |label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            })
+        };
+        Self {
+            albedo: create("aov_albedo_texture"),
+            normal: create("aov_normal_texture"),
+            position: create("aov_position_texture"),
+            ids: create("aov_id_texture"),
+        }
+    }
+
+    /// Views of the textures in binding order.
+    pub fn views(&self) -> [wgpu::TextureView; 4] {
+        [&self.albedo, &self.normal, &self.position, &self.ids]
+            .map(|texture| texture.create_view(&Default::default()))
+    }
+}
+
+/// AOVs resolved to per-pixel values, top row first.
+pub struct AovImages {
+    /// Material colour averaged over all samples, so that misses count as black.
+    pub albedo: Vec<[f32; 3]>,
+    pub normal: Vec<[f32; 3]>,
+    pub depth: Vec<f32>,
+    pub position: Vec<[f32; 3]>,
+    pub material_id: Vec<u32>,
+    pub object_id: Vec<u32>,
+    pub samples: Vec<u32>,
+}
+
+impl AovImages {
+    /// Read the AOVs back and divide out their sample counts, which come from
+    /// the luminance moments.
+    pub fn read(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        textures: &AovTextures,
+        moments: &wgpu::Texture,
+    ) -> Option<Self> {
+        let albedo = export::read_texture(device, queue, &textures.albedo)?;
+        let normal = export::read_texture(device, queue, &textures.normal)?;
+        let position = export::read_texture(device, queue, &textures.position)?;
+        let ids = export::read_texture(device, queue, &textures.ids)?;
+        let moments = export::read_texture(device, queue, moments)?;
+
+        let hits = |i: usize| albedo[i][3].max(1.0);
+        let xyz = |p: [f32; 4], n: f32| [p[0] / n, p[1] / n, p[2] / n];
+        let pixels = 0..albedo.len();
+        Some(Self {
+            albedo: pixels
+                .clone()
+                .map(|i| xyz(albedo[i], moments[i][2].max(1.0)))
+                .collect(),
+            normal: pixels.clone().map(|i| xyz(normal[i], hits(i))).collect(),
+            depth: pixels.clone().map(|i| normal[i][3] / hits(i)).collect(),
+            position: pixels.clone().map(|i| xyz(position[i], hits(i))).collect(),
+            material_id: ids.iter().map(|id| id[0] as u32).collect(),
+            object_id: ids.iter().map(|id| id[1] as u32).collect(),
+            samples: moments.iter().map(|m| m[2] as u32).collect(),
+        })
+    }
+}
@@ -0,0 +1,72 @@
+//! Command line parsing shared by the viewer and the `render` subcommand.
+
+use crate::SCENE_PATH;
+
+/// `--flag [value]` arguments, taken one flag at a time.
+pub struct Args<I> {
+    args: I,
+}
+
+impl<I: Iterator<Item = String>> Args<I> {
+    pub fn new(args: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            args: args.into_iter(),
+        }
+    }
+
+    pub fn next_flag(&mut self) -> Option<String> {
+        self.args.next()
+    }
+
+    /// The value following `flag`.
+    pub fn value(&mut self, flag: &str) -> Result<String, String> {
+        self.args
+            .next()
+            .ok_or_else(|| format!("missing value for {flag}"))
+    }
+
+    /// The value following `flag`, parsed as a number.
+    pub fn number<T: std::str::FromStr>(&mut self, flag: &str) -> Result<T, String> {
+        let value = self.value(flag)?;
+        value
+            .parse()
+            .map_err(|_| format!("invalid value for {flag}: {value}"))
+    }
+}
+
+/// Options the viewer and headless renders both take.
+#[derive(Debug, Clone)]
+pub struct CommonArgs {
+    /// OBJ mesh the demo scene is built around.
+    pub scene: String,
+    pub lut: Option<String>,
+    pub lut_log: bool,
+}
+
+impl Default for CommonArgs {
+    fn default() -> Self {
+        Self {
+            scene: SCENE_PATH.to_string(),
+            lut: None,
+            lut_log: false,
+        }
+    }
+}
+
+impl CommonArgs {
+    /// Take `flag` if it is one of the common options, reading its value from
+    /// `args`. Returns whether it was.
+    pub fn parse_flag<I: Iterator<Item = String>>(
+        &mut self,
+        flag: &str,
+        args: &mut Args<I>,
+    ) -> Result<bool, String> {
+        match flag {
+            "--scene" => self.scene = args.value(flag)?,
+            "--lut" => self.lut = Some(args.value(flag)?),
+            "--lut-log" => self.lut_log = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
@@ -1,6 +1,74 @@
 use wgpu::util::DeviceExt;
 
-use crate::tonemap::TonemapUniform;
+use crate::{
+    bloom::{BlitBuffers, Bloom, BloomSettings},
+    color::ColorUniform,
+    hdr::HdrUniform,
+    lut::{Lut, LutUniform},
+    tonemap::{TonemapUniform, Tonemapper, DEFAULT_WHITE},
+};
+
+/// What the blit shows in place of the tonemapped film.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisplayView {
+    #[default]
+    Beauty,
+    /// Relative standard error per pixel, on a log scale around the threshold.
+    Error,
+    /// Samples per pixel relative to the most any pixel has taken.
+    Samples,
+    Albedo,
+    Normal,
+    Depth,
+    Position,
+    MaterialId,
+    ObjectId,
+}
+
+impl DisplayView {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Beauty => Self::Error,
+            Self::Error => Self::Samples,
+            Self::Samples => Self::Albedo,
+            Self::Albedo => Self::Normal,
+            Self::Normal => Self::Depth,
+            Self::Depth => Self::Position,
+            Self::Position => Self::MaterialId,
+            Self::MaterialId => Self::ObjectId,
+            Self::ObjectId => Self::Beauty,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Beauty => "beauty",
+            Self::Error => "error heatmap",
+            Self::Samples => "sample count heatmap",
+            Self::Albedo => "albedo AOV",
+            Self::Normal => "normal AOV",
+            Self::Depth => "depth AOV",
+            Self::Position => "position AOV",
+            Self::MaterialId => "material id AOV",
+            Self::ObjectId => "object id AOV",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ViewUniform {
+    pub mode: u32,
+    /// Relative error drawn in the middle of the error heatmap.
+    pub error_threshold: f32,
+    /// Passes so far, the most samples any pixel can have.
+    pub iteration: u32,
+    /// Add the bloom glare before tonemapping.
+    pub glare: u32,
+}
+
+// Binding of the first AOV texture, the rest following in the order of AovTextures
+const AOV_FIRST_BINDING: u32 = 6;
 
 pub struct RenderPass {
     pub render_pipeline: wgpu::RenderPipeline,
@@ -9,13 +77,33 @@ pub struct RenderPass {
     pub sampler: wgpu::Sampler,
     pub tonemap_buffer: wgpu::Buffer,
     pub tonemap_params: TonemapUniform,
+    pub color_buffer: wgpu::Buffer,
+    pub view_buffer: wgpu::Buffer,
+    /// Auto exposure state, whose key replaces the uniform's when enabled.
+    pub exposure_buffer: wgpu::Buffer,
+    /// Grading LUT, a single unused texel when there is none.
+    pub lut_view: wgpu::TextureView,
+    pub lut_buffer: wgpu::Buffer,
+    /// Glare added to the image before tonemapping, when enabled.
+    pub bloom: Bloom,
+    /// How the image is encoded for HDR surfaces; zeroed for SDR.
+    pub hdr_buffer: wgpu::Buffer,
+    pub view: DisplayView,
 }
 
 impl RenderPass {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         device: &wgpu::Device,
         format: wgpu::TextureFormat,
         source_view: &wgpu::TextureView,
+        source_size: wgpu::Extent3d,
+        moments_view: &wgpu::TextureView,
+        aov_views: &[wgpu::TextureView; 4],
+        exposure: f32,
+        exposure_buffer: &wgpu::Buffer,
+        color_uniform: &ColorUniform,
+        bloom_settings: BloomSettings,
     ) -> Self {
         let copy_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Blit Shader"),
@@ -51,6 +139,72 @@ impl RenderPass {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                guide_layout_entry(4),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                guide_layout_entry(AOV_FIRST_BINDING),
+                guide_layout_entry(AOV_FIRST_BINDING + 1),
+                guide_layout_entry(AOV_FIRST_BINDING + 2),
+                guide_layout_entry(AOV_FIRST_BINDING + 3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                guide_layout_entry(13),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 14,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -96,10 +250,7 @@ impl RenderPass {
             ..Default::default()
         });
 
-        let tonemap_params = TonemapUniform {
-            key: 0.8,
-            saturation: 1.0,
-        };
+        let tonemap_params = TonemapUniform::new(Tonemapper::default(), exposure, 1.0, DEFAULT_WHITE);
 
         let tonemap_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("tonemap_buffer"),
@@ -107,6 +258,50 @@ impl RenderPass {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        let color_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("color_buffer"),
+            contents: bytemuck::bytes_of(color_uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let view_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("view_buffer"),
+            contents: bytemuck::bytes_of(&ViewUniform {
+                mode: 0,
+                error_threshold: 0.0,
+                iteration: 0,
+                glare: 0,
+            }),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let lut_view = empty_lut_view(device);
+        let lut_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("lut_buffer"),
+            contents: bytemuck::bytes_of(&LutUniform::none()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let hdr_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("hdr_buffer"),
+            size: std::mem::size_of::<HdrUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bloom = Bloom::new(
+            device,
+            source_view,
+            source_size,
+            BlitBuffers {
+                tonemap: tonemap_buffer.clone(),
+                exposure: exposure_buffer.clone(),
+                color: color_buffer.clone(),
+            },
+            bloom_settings,
+        );
+        let glare_view = bloom.glare().create_view(&Default::default());
+
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("blit_bind_group"),
             layout: &bind_group_layout,
@@ -123,6 +318,42 @@ impl RenderPass {
                     binding: 2,
                     resource: tonemap_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(moments_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: view_buffer.as_entire_binding(),
+                },
+                aov_entry(aov_views, 0),
+                aov_entry(aov_views, 1),
+                aov_entry(aov_views, 2),
+                aov_entry(aov_views, 3),
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::TextureView(&lut_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: lut_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: wgpu::BindingResource::TextureView(&glare_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: hdr_buffer.as_entire_binding(),
+                },
             ],
         });
 
@@ -133,6 +364,14 @@ impl RenderPass {
             sampler,
             tonemap_buffer,
             tonemap_params,
+            color_buffer,
+            view_buffer,
+            exposure_buffer: exposure_buffer.clone(),
+            lut_view,
+            lut_buffer,
+            bloom,
+            hdr_buffer,
+            view: DisplayView::default(),
         }
     }
 
@@ -158,8 +397,8 @@ impl RenderPass {
         rp.draw(0..3, 0..2);
     }
 
-    pub fn update_tonemap(&mut self, queue: &wgpu::Queue, key: f32, saturation: f32) {
-        self.tonemap_params = TonemapUniform { key, saturation };
+    pub fn update_tonemap(&mut self, queue: &wgpu::Queue, tonemap_params: TonemapUniform) {
+        self.tonemap_params = tonemap_params;
         queue.write_buffer(
             &self.tonemap_buffer,
             0,
@@ -167,7 +406,58 @@ impl RenderPass {
         );
     }
 
-    pub fn resize(&mut self, device: &wgpu::Device, source_view: &wgpu::TextureView) {
+    pub fn update_color(&mut self, queue: &wgpu::Queue, color_uniform: &ColorUniform) {
+        queue.write_buffer(&self.color_buffer, 0, bytemuck::bytes_of(color_uniform));
+    }
+
+    pub fn update_hdr(&self, queue: &wgpu::Queue, hdr_uniform: &HdrUniform) {
+        queue.write_buffer(&self.hdr_buffer, 0, bytemuck::bytes_of(hdr_uniform));
+    }
+
+    /// Grade through `lut`, or stop grading. Takes effect once the bind
+    /// group is recreated by `resize`.
+    pub fn set_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lut: Option<&Lut>) {
+        let Some(lut) = lut else {
+            self.lut_view = empty_lut_view(device);
+            queue.write_buffer(&self.lut_buffer, 0, bytemuck::bytes_of(&LutUniform::none()));
+            return;
+        };
+        let texture = device.create_texture(&lut_texture_descriptor(lut.size));
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&lut.texels()),
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(lut.size * 16),
+                rows_per_image: Some(lut.size),
+            },
+            texture.size(),
+        );
+        self.lut_view = texture.create_view(&Default::default());
+        queue.write_buffer(&self.lut_buffer, 0, bytemuck::bytes_of(&lut.uniform()));
+    }
+
+    /// Upload the display view with the state of the accumulation it depends on.
+    pub fn update_view(&self, queue: &wgpu::Queue, error_threshold: f32, iteration: u32) {
+        let uniform = ViewUniform {
+            mode: self.view as u32,
+            error_threshold,
+            iteration,
+            glare: self.bloom.settings.enabled as u32,
+        };
+        queue.write_buffer(&self.view_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        source_view: &wgpu::TextureView,
+        source_size: wgpu::Extent3d,
+        moments_view: &wgpu::TextureView,
+        aov_views: &[wgpu::TextureView; 4],
+    ) {
+        self.bloom.resize(device, source_view, source_size);
+        let glare_view = self.bloom.glare().create_view(&Default::default());
         self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("blit_bind_group"),
             layout: &self.bind_group_layout,
@@ -184,7 +474,88 @@ impl RenderPass {
                     binding: 2,
                     resource: self.tonemap_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.color_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(moments_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.view_buffer.as_entire_binding(),
+                },
+                aov_entry(aov_views, 0),
+                aov_entry(aov_views, 1),
+                aov_entry(aov_views, 2),
+                aov_entry(aov_views, 3),
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: self.exposure_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: wgpu::BindingResource::TextureView(&self.lut_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: self.lut_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: wgpu::BindingResource::TextureView(&glare_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: self.hdr_buffer.as_entire_binding(),
+                },
             ],
         });
     }
 }
+
+fn lut_texture_descriptor(size: u32) -> wgpu::TextureDescriptor<'static> {
+    wgpu::TextureDescriptor {
+        label: Some("lut_texture"),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D3,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    }
+}
+
+/// Bound while there is no LUT, as the binding cannot be left empty.
+fn empty_lut_view(device: &wgpu::Device) -> wgpu::TextureView {
+    device
+        .create_texture(&lut_texture_descriptor(1))
+        .create_view(&Default::default())
+}
+
+/// An unfilterable float texture read with `textureLoad`.
+fn guide_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+fn aov_entry(views: &[wgpu::TextureView; 4], index: usize) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding: AOV_FIRST_BINDING + index as u32,
+        resource: wgpu::BindingResource::TextureView(&views[index]),
+    }
+}
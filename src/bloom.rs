@@ -0,0 +1,526 @@
+//! Glare around bright light, from a multi-scale bloom pyramid. See
+//! bloom.wgsl.
+
+use serde::Deserialize;
+
+// Matches MAX_LEVELS in bloom.wgsl
+const MAX_LEVELS: usize = 8;
+const LEVEL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// Wavelengths standing for the red, green and blue channels, in nm. The
+// spread of diffraction grows with the wavelength, so each channel's glare
+// is scaled by its wavelength over green's.
+const CHANNEL_WAVELENGTHS: [f32; 3] = [610.0, 550.0, 465.0];
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    /// Fraction of the light above the threshold scattered into the glare.
+    pub strength: f32,
+    /// Exposed value above which light glares, where 1 is about display
+    /// white; 0 lets all light glare.
+    pub threshold: f32,
+    /// Width of the soft knee below the threshold, relative to it.
+    pub softness: f32,
+    /// Weight of each pyramid level relative to the next finer one; higher
+    /// spreads the glare wider.
+    pub spread: f32,
+    /// Spread red wider and blue narrower, as diffraction does.
+    pub spectral: bool,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            strength: 0.05,
+            threshold: 0.0,
+            softness: 0.5,
+            spread: 0.6,
+            spectral: true,
+        }
+    }
+}
+
+impl BloomSettings {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomParams {
+    threshold: f32,
+    softness: f32,
+    strength: f32,
+    level: u32,
+    levels: u32,
+    _pad: [u32; 3],
+    weights: [[f32; 4]; MAX_LEVELS],
+}
+
+/// Uniform buffers of the blit the glare shares.
+pub struct BlitBuffers {
+    pub tonemap: wgpu::Buffer,
+    pub exposure: wgpu::Buffer,
+    pub color: wgpu::Buffer,
+}
+
+pub struct Bloom {
+    pub settings: BloomSettings,
+    prefilter_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+    upsample_pipeline: wgpu::ComputePipeline,
+    composite_pipeline: wgpu::ComputePipeline,
+    layouts: BloomLayouts,
+    sampler: wgpu::Sampler,
+    blit_buffers: BlitBuffers,
+    /// One uniform per level, differing only in the level.
+    param_buffers: Vec<wgpu::Buffer>,
+    textures: BloomTextures,
+    bind_groups: BloomBindGroups,
+}
+
+struct BloomLayouts {
+    prefilter: wgpu::BindGroupLayout,
+    downsample: wgpu::BindGroupLayout,
+    upsample: wgpu::BindGroupLayout,
+    composite: wgpu::BindGroupLayout,
+}
+
+struct BloomTextures {
+    /// The light above the threshold, from half resolution down.
+    down: Vec<wgpu::Texture>,
+    /// Weighted sums of each level and the coarser ones.
+    up: Vec<wgpu::Texture>,
+    /// Light to add to each pixel before tonemapping, in the output space.
+    glare: wgpu::Texture,
+}
+
+struct BloomBindGroups {
+    prefilter: wgpu::BindGroup,
+    downsample: Vec<wgpu::BindGroup>,
+    upsample: Vec<wgpu::BindGroup>,
+    composite: wgpu::BindGroup,
+}
+
+impl Bloom {
+    pub fn new(
+        device: &wgpu::Device,
+        source_view: &wgpu::TextureView,
+        size: wgpu::Extent3d,
+        blit_buffers: BlitBuffers,
+        settings: BloomSettings,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Bloom Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("kernels/bloom.wgsl").into()),
+        });
+
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let texture = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let storage = |binding, format| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let exposure = wgpu::BindGroupLayoutEntry {
+            binding: 4,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let sampler_entry = wgpu::BindGroupLayoutEntry {
+            binding: 7,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let layout = |label, entries: &[wgpu::BindGroupLayoutEntry]| {
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some(label),
+                entries,
+            })
+        };
+        let layouts = BloomLayouts {
+            prefilter: layout(
+                "bloom_prefilter_bind_group_layout",
+                &[
+                    uniform(0),
+                    texture(1, false),
+                    storage(2, LEVEL_FORMAT),
+                    uniform(3),
+                    exposure,
+                    uniform(5),
+                ],
+            ),
+            downsample: layout(
+                "bloom_downsample_bind_group_layout",
+                &[
+                    uniform(0),
+                    storage(2, LEVEL_FORMAT),
+                    texture(6, true),
+                    sampler_entry,
+                ],
+            ),
+            upsample: layout(
+                "bloom_upsample_bind_group_layout",
+                &[
+                    uniform(0),
+                    storage(2, LEVEL_FORMAT),
+                    texture(6, true),
+                    sampler_entry,
+                    texture(8, false),
+                ],
+            ),
+            composite: layout(
+                "bloom_composite_bind_group_layout",
+                &[
+                    uniform(0),
+                    texture(1, false),
+                    uniform(3),
+                    exposure,
+                    uniform(5),
+                    texture(6, true),
+                    sampler_entry,
+                    storage(9, wgpu::TextureFormat::Rgba32Float),
+                ],
+            ),
+        };
+
+        let pipeline = |layout: &wgpu::BindGroupLayout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("bloom_pipeline_layout"),
+                bind_group_layouts: &[Some(layout)],
+                immediate_size: 0,
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::MipmapFilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let param_buffers: Vec<wgpu::Buffer> = (0..MAX_LEVELS)
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("bloom_params_buffer"),
+                    size: std::mem::size_of::<BloomParams>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+
+        let textures = BloomTextures::new(device, size);
+        let bind_groups = create_bind_groups(
+            device,
+            &layouts,
+            &sampler,
+            &blit_buffers,
+            &param_buffers,
+            &textures,
+            source_view,
+        );
+        Self {
+            settings,
+            prefilter_pipeline: pipeline(&layouts.prefilter, "cs_prefilter"),
+            downsample_pipeline: pipeline(&layouts.downsample, "cs_downsample"),
+            upsample_pipeline: pipeline(&layouts.upsample, "cs_upsample"),
+            composite_pipeline: pipeline(&layouts.composite, "cs_composite"),
+            layouts,
+            sampler,
+            blit_buffers,
+            param_buffers,
+            textures,
+            bind_groups,
+        }
+    }
+
+    /// Light to add to each pixel before tonemapping, in the output space,
+    /// as of the last run.
+    pub fn glare(&self) -> &wgpu::Texture {
+        &self.textures.glare
+    }
+
+    /// Glare the image of `source_view` from now on, reallocating the
+    /// pyramid for `size`.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        source_view: &wgpu::TextureView,
+        size: wgpu::Extent3d,
+    ) {
+        self.textures = BloomTextures::new(device, size);
+        self.bind_groups = create_bind_groups(
+            device,
+            &self.layouts,
+            &self.sampler,
+            &self.blit_buffers,
+            &self.param_buffers,
+            &self.textures,
+            source_view,
+        );
+    }
+
+    /// Compute the glare of the current image with the current settings.
+    pub fn run(&self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder) {
+        let levels = self.textures.down.len();
+        let weights = self.settings.level_weights(levels);
+        for (level, buffer) in self.param_buffers.iter().enumerate() {
+            let params = BloomParams {
+                threshold: self.settings.threshold.max(0.0),
+                softness: self.settings.softness.clamp(0.0, 1.0),
+                strength: self.settings.strength,
+                level: level as u32,
+                levels: levels as u32,
+                _pad: [0; 3],
+                weights,
+            };
+            queue.write_buffer(buffer, 0, bytemuck::bytes_of(&params));
+        }
+
+        let workgroups =
+            |texture: &wgpu::Texture| (texture.width().div_ceil(8), texture.height().div_ceil(8));
+        let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+        let (x, y) = workgroups(&self.textures.down[0]);
+        compute_pass.set_pipeline(&self.prefilter_pipeline);
+        compute_pass.set_bind_group(0, &self.bind_groups.prefilter, &[]);
+        compute_pass.dispatch_workgroups(x, y, 1);
+
+        compute_pass.set_pipeline(&self.downsample_pipeline);
+        for (bind_group, texture) in self
+            .bind_groups
+            .downsample
+            .iter()
+            .zip(&self.textures.down[1..])
+        {
+            let (x, y) = workgroups(texture);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(x, y, 1);
+        }
+
+        // Coarsest level first, each adding the sum of those below it
+        compute_pass.set_pipeline(&self.upsample_pipeline);
+        for (bind_group, texture) in self
+            .bind_groups
+            .upsample
+            .iter()
+            .zip(&self.textures.up)
+            .rev()
+        {
+            let (x, y) = workgroups(texture);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(x, y, 1);
+        }
+
+        let (x, y) = workgroups(&self.textures.glare);
+        compute_pass.set_pipeline(&self.composite_pipeline);
+        compute_pass.set_bind_group(0, &self.bind_groups.composite, &[]);
+        compute_pass.dispatch_workgroups(x, y, 1);
+    }
+}
+
+impl BloomSettings {
+    /// Weight of each of `levels` levels per channel, summing to 1 over the
+    /// levels so the glare holds as much light as was scattered.
+    fn level_weights(&self, levels: usize) -> [[f32; 4]; MAX_LEVELS] {
+        let spread = self.spread.clamp(0.0, 1.0);
+        let mut weights = [[0.0; 4]; MAX_LEVELS];
+        for channel in 0..3 {
+            let scale = if self.spectral {
+                CHANNEL_WAVELENGTHS[channel] / CHANNEL_WAVELENGTHS[1]
+            } else {
+                1.0
+            };
+            let raw: Vec<f32> = (0..levels)
+                .map(|level| spread.powf(level as f32 / scale))
+                .collect();
+            let total: f32 = raw.iter().sum();
+            for (level, w) in raw.iter().enumerate() {
+                weights[level][channel] = w / total;
+            }
+        }
+        weights
+    }
+}
+
+impl BloomTextures {
+    /// The pyramid for an image of `size`, from half resolution down to a
+    /// few pixels across, and the full resolution glare.
+    fn new(device: &wgpu::Device, size: wgpu::Extent3d) -> Self {
+        let smallest = size.width.min(size.height).max(1);
+        let levels = (smallest.ilog2().saturating_sub(2) as usize).clamp(1, MAX_LEVELS);
+        let create = |label, size, format| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        };
+        let level_size = |level: usize| wgpu::Extent3d {
+            width: (size.width >> (level + 1)).max(1),
+            height: (size.height >> (level + 1)).max(1),
+            depth_or_array_layers: 1,
+        };
+        Self {
+            down: (0..levels)
+                .map(|level| create("bloom_down_texture", level_size(level), LEVEL_FORMAT))
+                .collect(),
+            up: (0..levels)
+                .map(|level| create("bloom_up_texture", level_size(level), LEVEL_FORMAT))
+                .collect(),
+            glare: create(
+                "bloom_glare_texture",
+                size,
+                wgpu::TextureFormat::Rgba32Float,
+            ),
+        }
+    }
+}
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    layouts: &BloomLayouts,
+    sampler: &wgpu::Sampler,
+    blit_buffers: &BlitBuffers,
+    param_buffers: &[wgpu::Buffer],
+    textures: &BloomTextures,
+    source_view: &wgpu::TextureView,
+) -> BloomBindGroups {
+    let view = |texture: &wgpu::Texture| texture.create_view(&Default::default());
+    let down: Vec<_> = textures.down.iter().map(view).collect();
+    let up: Vec<_> = textures.up.iter().map(view).collect();
+    let glare = view(&textures.glare);
+    let texture = |binding, view| wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(view),
+    };
+    fn buffer(binding: u32, buffer: &wgpu::Buffer) -> wgpu::BindGroupEntry<'_> {
+        wgpu::BindGroupEntry {
+            binding,
+            resource: buffer.as_entire_binding(),
+        }
+    }
+    let sampler = wgpu::BindGroupEntry {
+        binding: 7,
+        resource: wgpu::BindingResource::Sampler(sampler),
+    };
+    let bind_group = |label, layout, entries: &[wgpu::BindGroupEntry]| {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(label),
+            layout,
+            entries,
+        })
+    };
+
+    BloomBindGroups {
+        prefilter: bind_group(
+            "bloom_prefilter_bind_group",
+            &layouts.prefilter,
+            &[
+                buffer(0, &param_buffers[0]),
+                texture(1, source_view),
+                texture(2, &down[0]),
+                buffer(3, &blit_buffers.tonemap),
+                buffer(4, &blit_buffers.exposure),
+                buffer(5, &blit_buffers.color),
+            ],
+        ),
+        downsample: (1..down.len())
+            .map(|level| {
+                bind_group(
+                    "bloom_downsample_bind_group",
+                    &layouts.downsample,
+                    &[
+                        buffer(0, &param_buffers[level]),
+                        texture(2, &down[level]),
+                        texture(6, &down[level - 1]),
+                        sampler.clone(),
+                    ],
+                )
+            })
+            .collect(),
+        upsample: (0..up.len())
+            .map(|level| {
+                // The coarsest level has nothing below it to add, but the
+                // binding still needs a texture
+                let coarser = up.get(level + 1).unwrap_or(&down[level]);
+                bind_group(
+                    "bloom_upsample_bind_group",
+                    &layouts.upsample,
+                    &[
+                        buffer(0, &param_buffers[level]),
+                        texture(2, &up[level]),
+                        texture(6, coarser),
+                        sampler.clone(),
+                        texture(8, &down[level]),
+                    ],
+                )
+            })
+            .collect(),
+        composite: bind_group(
+            "bloom_composite_bind_group",
+            &layouts.composite,
+            &[
+                buffer(0, &param_buffers[0]),
+                texture(1, source_view),
+                buffer(3, &blit_buffers.tonemap),
+                buffer(4, &blit_buffers.exposure),
+                buffer(5, &blit_buffers.color),
+                texture(6, &up[0]),
+                sampler,
+                texture(9, &glare),
+            ],
+        ),
+    }
+}
@@ -0,0 +1,222 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use cgmath::InnerSpace;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::{ApertureShape, Camera, FilmFit, Projection},
+    color::WhitePoint,
+    lens::LensSystem,
+};
+
+/// Projection of a saved view. The realistic lens is not stored; it is the
+/// prescription loaded when the view is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum SavedProjection {
+    #[default]
+    Perspective,
+    Realistic,
+    Orthographic,
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+    Equirectangular,
+}
+
+impl SavedProjection {
+    fn of(projection: &Projection) -> Self {
+        match projection {
+            Projection::Perspective => Self::Perspective,
+            Projection::Realistic(_) => Self::Realistic,
+            Projection::Orthographic => Self::Orthographic,
+            Projection::FisheyeEquidistant => Self::FisheyeEquidistant,
+            Projection::FisheyeEquisolid => Self::FisheyeEquisolid,
+            Projection::Equirectangular => Self::Equirectangular,
+        }
+    }
+}
+
+/// Aperture of a saved view; a custom mask is the one loaded when the view
+/// is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum SavedAperture {
+    #[default]
+    Circular,
+    Polygon(u32),
+    Custom,
+}
+
+impl SavedAperture {
+    fn of(aperture: &ApertureShape) -> Self {
+        match aperture {
+            ApertureShape::Circular => Self::Circular,
+            ApertureShape::Polygon(blades) => Self::Polygon(*blades),
+            ApertureShape::Custom { .. } => Self::Custom,
+        }
+    }
+}
+
+/// A saved view: position, orientation, field of view, lens, projection,
+/// exposure and white balance. Fields missing from older files keep the
+/// defaults of a new camera.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bookmark {
+    pub origin: [f32; 3],
+    pub right: [f32; 3],
+    pub up: [f32; 3],
+    pub vfov: f32,
+    pub aperture_radius: f32,
+    pub focus_distance: f32,
+    pub aperture_rotation: f32,
+    pub projection: SavedProjection,
+    pub fisheye_fov: f32,
+    pub stereo: bool,
+    pub eye_separation: f32,
+    pub fit: FilmFit,
+    pub aperture: SavedAperture,
+    pub iso: f32,
+    pub shutter_time: f32,
+    pub f_number: f32,
+    pub white_balance: f32,
+    pub tint: f32,
+    pub white_preset: Option<WhitePoint>,
+}
+
+impl Default for Bookmark {
+    fn default() -> Self {
+        let camera = Camera::new(
+            cgmath::Vector3::new(0.0, 0.0, 0.0),
+            cgmath::Vector3::unit_z(),
+            cgmath::Vector3::unit_y(),
+            75.0,
+            1.0,
+        );
+        Self::from_camera(&camera)
+    }
+}
+
+impl Bookmark {
+    pub fn from_camera(camera: &Camera) -> Self {
+        let right = camera.horizontal.normalize();
+        let up = camera.vertical.normalize();
+        Self {
+            origin: camera.origin.into(),
+            right: right.into(),
+            up: up.into(),
+            vfov: camera.vfov,
+            aperture_radius: camera.aperture_radius,
+            focus_distance: camera.focus_distance,
+            aperture_rotation: camera.aperture_rotation,
+            projection: SavedProjection::of(&camera.projection),
+            fisheye_fov: camera.fisheye_fov,
+            stereo: camera.stereo,
+            eye_separation: camera.eye_separation,
+            fit: camera.fit,
+            aperture: SavedAperture::of(&camera.aperture),
+            iso: camera.iso,
+            shutter_time: camera.shutter_time,
+            f_number: camera.f_number,
+            white_balance: camera.white_balance,
+            tint: camera.tint,
+            white_preset: camera.white_preset,
+        }
+    }
+
+    /// Restore the view. `lens` and `custom_aperture` stand in for the
+    /// realistic projection and custom aperture, which fall back to
+    /// perspective and circular when they are missing. A realistic lens is
+    /// focused at the restored focus distance.
+    pub fn apply(
+        &self,
+        camera: &mut Camera,
+        lens: Option<&LensSystem>,
+        custom_aperture: Option<&ApertureShape>,
+    ) {
+        camera.origin = self.origin.into();
+        camera.horizontal = self.right.into();
+        camera.vertical = self.up.into();
+        camera.aperture_radius = self.aperture_radius;
+        camera.focus_distance = self.focus_distance;
+        camera.aperture_rotation = self.aperture_rotation;
+        camera.projection = match self.projection {
+            SavedProjection::Perspective => Projection::Perspective,
+            SavedProjection::Realistic => match lens {
+                Some(lens) => Projection::Realistic(lens.clone()),
+                None => {
+                    eprintln!("The view needs a lens prescription, using perspective");
+                    Projection::Perspective
+                }
+            },
+            SavedProjection::Orthographic => Projection::Orthographic,
+            SavedProjection::FisheyeEquidistant => Projection::FisheyeEquidistant,
+            SavedProjection::FisheyeEquisolid => Projection::FisheyeEquisolid,
+            SavedProjection::Equirectangular => Projection::Equirectangular,
+        };
+        if let Projection::Realistic(lens) = &mut camera.projection {
+            if let Err(e) = lens.focus(self.focus_distance) {
+                eprintln!("Failed to focus lens: {e}");
+            }
+        }
+        camera.fisheye_fov = self.fisheye_fov;
+        camera.stereo = self.stereo;
+        camera.eye_separation = self.eye_separation;
+        camera.aperture = match self.aperture {
+            SavedAperture::Circular => ApertureShape::Circular,
+            SavedAperture::Polygon(blades) => ApertureShape::Polygon(blades),
+            SavedAperture::Custom => match custom_aperture {
+                Some(aperture) => aperture.clone(),
+                None => {
+                    eprintln!("The view needs an aperture mask, using a circular aperture");
+                    ApertureShape::Circular
+                }
+            },
+        };
+        camera.iso = self.iso;
+        camera.shutter_time = self.shutter_time;
+        camera.f_number = self.f_number;
+        camera.white_balance = self.white_balance;
+        camera.tint = self.tint;
+        camera.white_preset = self.white_preset;
+        camera.fit = self.fit;
+        // Rebuilds the image plane around the restored orientation and fit
+        camera.set_vfov(self.vfov);
+    }
+}
+
+/// Numbered bookmarks, kept in a sidecar file next to the scene.
+pub struct Bookmarks {
+    path: PathBuf,
+    slots: BTreeMap<u8, Bookmark>,
+}
+
+impl Bookmarks {
+    /// Bookmarks for `scene_path`, stored as `<scene>.bookmarks.ron`.
+    /// A missing sidecar starts an empty set.
+    pub fn load(scene_path: &str) -> Self {
+        let path = PathBuf::from(scene_path).with_extension("bookmarks.ron");
+        let slots = match std::fs::read_to_string(&path) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|e| {
+                eprintln!("Failed to parse {}: {e}", path.display());
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self { path, slots }
+    }
+
+    pub fn get(&self, slot: u8) -> Option<&Bookmark> {
+        self.slots.get(&slot)
+    }
+
+    /// Store a bookmark and write the sidecar file.
+    pub fn set(&mut self, slot: u8, bookmark: Bookmark) -> Result<(), String> {
+        self.slots.insert(slot, bookmark);
+        let text = ron::ser::to_string_pretty(&self.slots, ron::ser::PrettyConfig::default())
+            .map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, text).map_err(|e| e.to_string())
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
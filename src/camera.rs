@@ -1,20 +1,251 @@
-use std::f32::consts::PI;
-
 use cgmath::prelude::*;
+
+use crate::color::WhitePoint;
+use crate::lens::{GpuLensElement, LensSystem};
 use winit::{
     event::{DeviceEvent, ElementState},
     keyboard::{KeyCode, PhysicalKey},
 };
 
-const MOUSE_SCALING: f32 = 0.0000017;
+// Radians of rotation per mouse count
+const MOUSE_SCALING: f32 = 0.002;
+
+// Scene radiance is in arbitrary units; these defaults give EV100 = 0, which
+// matches the old fixed tonemap key of ~0.8.
+const DEFAULT_ISO: f32 = 100.0;
+const DEFAULT_SHUTTER_TIME: f32 = 1.0;
+const DEFAULT_F_NUMBER: f32 = 1.0;
+// 6504K at this tint is D65
+const DEFAULT_WHITE_BALANCE: f32 = 6504.0;
+const DEFAULT_TINT: f32 = 0.0032;
+// Full-frame 35mm sensor
+const DEFAULT_SENSOR_SIZE: [f32; 2] = [36.0, 24.0];
+const DEFAULT_FISHEYE_FOV: f32 = 180.0;
+// Average human interpupillary distance in metres
+const DEFAULT_EYE_SEPARATION: f32 = 0.064;
 
 pub struct Camera {
     pub origin: cgmath::Point3<f32>,
     pub horizontal: cgmath::Vector3<f32>,
     pub vertical: cgmath::Vector3<f32>,
     pub lower_left_corner: cgmath::Point3<f32>,
+    /// Vertical field of view of the sensor in degrees; see `focal_length`.
     pub vfov: f32,
+    /// Width over height of the rendered image.
     pub aspect_ratio: f32,
+    /// Sensor width and height in millimetres.
+    pub sensor_size: [f32; 2],
+    pub fit: FilmFit,
+    pub iso: f32,
+    /// Shutter time in seconds.
+    pub shutter_time: f32,
+    pub f_number: f32,
+    /// Colour temperature in kelvin that should render as neutral.
+    pub white_balance: f32,
+    /// Offset from the Planckian locus in Duv; positive is greener.
+    pub tint: f32,
+    /// Named white the output adopts instead of the white balance
+    /// temperature, which takes over once it is changed.
+    pub white_preset: Option<WhitePoint>,
+    /// Thin lens aperture radius in scene units; zero is a pinhole.
+    pub aperture_radius: f32,
+    /// Distance along the view axis to the plane in focus.
+    pub focus_distance: f32,
+    pub aperture: ApertureShape,
+    /// Rotation of polygonal apertures in radians.
+    pub aperture_rotation: f32,
+    pub projection: Projection,
+    /// Field of view across the image circle of fisheye projections, in degrees.
+    pub fisheye_fov: f32,
+    /// Render the left and right eye side by side.
+    pub stereo: bool,
+    pub eye_separation: f32,
+    /// Scene time in seconds the frame is rendered at.
+    pub time: f32,
+    /// Shutter open and close relative to `time`, in seconds. Equal values
+    /// disable motion blur; this is independent of the exposure `shutter_time`.
+    pub shutter_interval: [f32; 2],
+}
+
+/// How the sensor is mapped onto an image of a different aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum FilmFit {
+    /// The sensor height spans the image height.
+    Vertical,
+    /// The sensor width spans the image width.
+    Horizontal,
+    /// The image is covered by the sensor, cropping whichever side overhangs.
+    Fill,
+}
+
+impl FilmFit {
+    pub const ALL: [FilmFit; 3] = [FilmFit::Vertical, FilmFit::Horizontal, FilmFit::Fill];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilmFit::Vertical => "vertical",
+            FilmFit::Horizontal => "horizontal",
+            FilmFit::Fill => "fill",
+        }
+    }
+}
+
+/// How camera rays are generated from film positions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection {
+    /// Pinhole or thin lens, depending on the aperture radius.
+    Perspective,
+    /// Rays traced through a multi-element lens prescription.
+    Realistic(LensSystem),
+    /// Parallel rays; the view covers the perspective frustum at the focus distance.
+    Orthographic,
+    /// Fisheye with image radius proportional to the angle off axis.
+    FisheyeEquidistant,
+    /// Fisheye preserving solid angle, like most real fisheye lenses.
+    FisheyeEquisolid,
+    /// Full 360 by 180 degree panorama in latitude/longitude layout.
+    Equirectangular,
+}
+
+impl Projection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Projection::Perspective => "perspective",
+            Projection::Realistic(_) => "realistic lens",
+            Projection::Orthographic => "orthographic",
+            Projection::FisheyeEquidistant => "equidistant fisheye",
+            Projection::FisheyeEquisolid => "equisolid fisheye",
+            Projection::Equirectangular => "equirectangular",
+        }
+    }
+
+    /// The next of the analytic projections; the realistic lens needs a
+    /// prescription and is selected separately.
+    pub fn next(&self) -> Self {
+        match self {
+            Projection::Perspective => Projection::Orthographic,
+            Projection::Orthographic => Projection::FisheyeEquidistant,
+            Projection::FisheyeEquidistant => Projection::FisheyeEquisolid,
+            Projection::FisheyeEquisolid => Projection::Equirectangular,
+            Projection::Equirectangular | Projection::Realistic(_) => Projection::Perspective,
+        }
+    }
+
+    pub fn is_fisheye(&self) -> bool {
+        matches!(self, Projection::FisheyeEquidistant | Projection::FisheyeEquisolid)
+    }
+
+    fn gpu_kind(&self) -> u32 {
+        match self {
+            Projection::Perspective => 0,
+            Projection::Realistic(_) => 1,
+            Projection::Orthographic => 2,
+            Projection::FisheyeEquidistant => 3,
+            Projection::FisheyeEquisolid => 4,
+            Projection::Equirectangular => 5,
+        }
+    }
+
+    /// Lens elements to upload; never empty so the buffer binding stays valid.
+    pub fn lens_elements(&self) -> Vec<GpuLensElement> {
+        match self {
+            Projection::Realistic(lens) => lens.gpu_elements(),
+            _ => vec![bytemuck::Zeroable::zeroed()],
+        }
+    }
+}
+
+/// Shape of the lens aperture, which gives out-of-focus highlights their shape.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ApertureShape {
+    Circular,
+    Polygon(u32),
+    /// Points in [-1, 1]^2 covering an aperture mask, each a texel of size `texel`.
+    Custom { samples: Vec<[f32; 2]>, texel: f32 },
+}
+
+// Cap on uploaded mask points; larger masks are subsampled.
+const MAX_APERTURE_SAMPLES: usize = 16384;
+
+impl ApertureShape {
+    /// Load an aperture mask from a binary (P5) or ASCII (P2) PGM image.
+    /// Pixels brighter than half the maximum value are open.
+    pub fn from_pgm(path: &str) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        let mut pos = 0;
+        let mut token = || -> Result<String, String> {
+            loop {
+                while pos < data.len() && data[pos].is_ascii_whitespace() {
+                    pos += 1;
+                }
+                if pos < data.len() && data[pos] == b'#' {
+                    while pos < data.len() && data[pos] != b'\n' {
+                        pos += 1;
+                    }
+                    continue;
+                }
+                break;
+            }
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err("unexpected end of file".into());
+            }
+            Ok(String::from_utf8_lossy(&data[start..pos]).into_owned())
+        };
+        let magic = token()?;
+        let mut number = || -> Result<usize, String> {
+            token()?.parse::<usize>().map_err(|e| e.to_string())
+        };
+        let (width, height, max_value) = (number()?, number()?, number()?);
+        let pixels: Vec<usize> = match magic.as_str() {
+            "P2" => (0..width * height).map(|_| number()).collect::<Result<_, _>>()?,
+            "P5" if max_value < 256 => {
+                // A single whitespace byte separates the header from the raster
+                let start = pos + 1;
+                let raster = data
+                    .get(start..start + width * height)
+                    .ok_or("truncated raster")?;
+                raster.iter().map(|&v| v as usize).collect()
+            }
+            _ => return Err(format!("unsupported PGM format {magic}")),
+        };
+
+        let extent = width.max(height) as f32;
+        let mut samples: Vec<[f32; 2]> = pixels
+            .iter()
+            .enumerate()
+            .filter(|(_, &v)| 2 * v > max_value)
+            .map(|(i, _)| {
+                let (x, y) = ((i % width) as f32, (i / width) as f32);
+                // Centre the mask and flip y so the image appears upright
+                [
+                    (2.0 * x + 1.0 - width as f32) / extent,
+                    (height as f32 - 2.0 * y - 1.0) / extent,
+                ]
+            })
+            .collect();
+        if samples.is_empty() {
+            return Err("aperture mask is fully closed".into());
+        }
+        let stride = samples.len().div_ceil(MAX_APERTURE_SAMPLES);
+        samples = samples.into_iter().step_by(stride).collect();
+
+        Ok(ApertureShape::Custom {
+            samples,
+            texel: 2.0 * stride as f32 / extent,
+        })
+    }
+
+    /// Mask points to upload; never empty so the buffer binding stays valid.
+    pub fn samples(&self) -> &[[f32; 2]] {
+        match self {
+            ApertureShape::Custom { samples, .. } => samples,
+            _ => &[[0.0, 0.0]],
+        }
+    }
 }
 
 impl Camera {
@@ -25,46 +256,148 @@ impl Camera {
         vfov: f32,
         aspect_ratio: f32,
     ) -> Self {
-        let theta = vfov * PI / 180.;
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h;
-        let viewport_width = aspect_ratio * viewport_height;
-
         let w = (look_from - look_at).normalize();
         let u = w.cross(v_up).normalize();
         let v = w.cross(u);
 
         let origin = look_from;
-        let horizontal = viewport_width * u;
-        let vertical = viewport_height * v;
-        let lower_left_corner = origin - 0.5 * horizontal - 0.5 * vertical - w;
-        Camera {
+        let mut camera = Camera {
             origin: cgmath::point3(origin.x, origin.y, origin.z),
-            horizontal: cgmath::vec3(horizontal.x, horizontal.y, horizontal.z),
-            vertical: cgmath::vec3(vertical.x, vertical.y, vertical.z),
-            lower_left_corner: cgmath::point3(
-                lower_left_corner.x,
-                lower_left_corner.y,
-                lower_left_corner.z,
-            ),
+            horizontal: u,
+            vertical: v,
+            lower_left_corner: cgmath::point3(0.0, 0.0, 0.0),
             vfov,
             aspect_ratio,
-        }
+            sensor_size: DEFAULT_SENSOR_SIZE,
+            fit: FilmFit::Vertical,
+            iso: DEFAULT_ISO,
+            shutter_time: DEFAULT_SHUTTER_TIME,
+            f_number: DEFAULT_F_NUMBER,
+            white_balance: DEFAULT_WHITE_BALANCE,
+            tint: DEFAULT_TINT,
+            white_preset: Some(WhitePoint::D65),
+            aperture_radius: 0.0,
+            focus_distance: 5.0,
+            aperture: ApertureShape::Circular,
+            aperture_rotation: 0.0,
+            projection: Projection::Perspective,
+            fisheye_fov: DEFAULT_FISHEYE_FOV,
+            stereo: false,
+            eye_separation: DEFAULT_EYE_SEPARATION,
+            time: 0.0,
+            shutter_interval: [0.0, 0.0],
+        };
+        camera.update_viewport();
+        camera
+    }
+
+    /// Exposure value at ISO 100.
+    pub fn ev100(&self) -> f32 {
+        (self.f_number * self.f_number / self.shutter_time * 100.0 / self.iso).log2()
+    }
+
+    /// Scale from scene radiance to sensor exposure, using the standard
+    /// saturation-based sensitivity with a lens/vignetting factor of 0.65.
+    pub fn exposure(&self) -> f32 {
+        1.0 / (1.2 * 2f32.powf(self.ev100()))
+    }
+
+    pub fn white_point(&self) -> WhitePoint {
+        WhitePoint::from_temperature(self.white_balance, self.tint)
+    }
+
+    /// The scene white that should appear neutral in the output.
+    pub fn adopted_white(&self) -> WhitePoint {
+        self.white_preset.unwrap_or_else(|| self.white_point())
     }
 
     pub fn set_vfov(&mut self, vfov: f32) {
         self.vfov = vfov;
-        let theta = vfov * PI / 180.;
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h;
-        let viewport_width = self.aspect_ratio * viewport_height;
+        self.update_viewport();
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        self.aspect_ratio = aspect_ratio;
+        self.update_viewport();
+    }
+
+    pub fn set_fit(&mut self, fit: FilmFit) {
+        self.fit = fit;
+        self.update_viewport();
+    }
+
+    /// Focal length in millimetres for the current field of view and sensor.
+    pub fn focal_length(&self) -> f32 {
+        0.5 * self.sensor_size[1] / (0.5 * self.vfov.to_radians()).tan()
+    }
+
+    /// Half width and half height of the image on the plane one unit in
+    /// front of the camera, after fitting the sensor to the aspect ratio.
+    fn image_half_extent(&self) -> [f32; 2] {
+        let sensor_height = (0.5 * self.vfov.to_radians()).tan();
+        let sensor_width = sensor_height * self.sensor_size[0] / self.sensor_size[1];
+        let sensor_aspect = self.sensor_size[0] / self.sensor_size[1];
+        let fit_width = match self.fit {
+            FilmFit::Vertical => false,
+            FilmFit::Horizontal => true,
+            FilmFit::Fill => self.aspect_ratio > sensor_aspect,
+        };
+        if fit_width {
+            [sensor_width, sensor_width / self.aspect_ratio]
+        } else {
+            [sensor_height * self.aspect_ratio, sensor_height]
+        }
+    }
+
+    /// Film width and height in metres, the part of the sensor the image covers.
+    pub fn film_size(&self) -> [f32; 2] {
+        let [half_width, half_height] = self.image_half_extent();
+        let scale = 0.001 * self.sensor_size[1] / (0.5 * self.vfov.to_radians()).tan();
+        [scale * half_width, scale * half_height]
+    }
+
+    /// Unit vector along the view axis.
+    pub fn forward(&self) -> cgmath::Vector3<f32> {
+        self.vertical.cross(self.horizontal).normalize()
+    }
+
+    /// World position at `depth` along the view axis through film position `uv`.
+    pub fn point_at(&self, uv: [f32; 2], depth: f32) -> cgmath::Point3<f32> {
+        let direction =
+            self.lower_left_corner + self.horizontal * uv[0] + self.vertical * uv[1] - self.origin;
+        self.origin + direction * depth
+    }
+
+    /// Whether points can be projected back onto the film, which temporal
+    /// reprojection needs.
+    pub fn supports_reprojection(&self) -> bool {
+        matches!(self.projection, Projection::Perspective | Projection::Orthographic) && !self.stereo
+    }
+
+    pub fn translate(&mut self, delta: cgmath::Vector3<f32>) {
+        self.origin += delta;
+        self.lower_left_corner += delta;
+    }
+
+    /// Rotate the camera's position and orientation about `center`.
+    pub fn rotate_about(&mut self, rotation: cgmath::Quaternion<f32>, center: cgmath::Point3<f32>) {
+        self.horizontal = rotation.rotate_vector(self.horizontal);
+        self.vertical = rotation.rotate_vector(self.vertical);
+        self.origin = center + rotation.rotate_vector(self.origin - center);
+        self.lower_left_corner = center + rotation.rotate_vector(self.lower_left_corner - center);
+    }
+
+    /// Re-derive the image plane from the orientation, field of view, film
+    /// fit and aspect ratio.
+    fn update_viewport(&mut self) {
+        let [half_width, half_height] = self.image_half_extent();
 
         let u = self.horizontal.normalize();
         let v = self.vertical.normalize();
         let w = u.cross(v);
 
-        self.horizontal = viewport_width * u;
-        self.vertical = viewport_height * v;
+        self.horizontal = 2.0 * half_width * u;
+        self.vertical = 2.0 * half_height * v;
         self.lower_left_corner = self.origin - 0.5 * self.horizontal - 0.5 * self.vertical - w;
     }
 
@@ -79,6 +412,43 @@ impl Camera {
                 self.lower_left_corner.z,
                 0.0,
             ],
+            lens: [
+                self.aperture_radius,
+                self.focus_distance,
+                self.aperture_rotation,
+                match self.aperture {
+                    ApertureShape::Custom { texel, .. } => texel,
+                    _ => 0.0,
+                },
+            ],
+            aperture: match &self.aperture {
+                ApertureShape::Circular => [0, 0, 0, 0],
+                ApertureShape::Polygon(blades) => [1, *blades, 0, 0],
+                ApertureShape::Custom { samples, .. } => [2, 0, samples.len() as u32, 0],
+            },
+            projection: match &self.projection {
+                Projection::Realistic(lens) => [
+                    self.projection.gpu_kind(),
+                    lens.elements.len() as u32,
+                    lens.is_dispersive() as u32,
+                    self.stereo as u32,
+                ],
+                _ => [self.projection.gpu_kind(), 0, 0, self.stereo as u32],
+            },
+            film: match &self.projection {
+                Projection::Realistic(_) => {
+                    let [width, height] = self.film_size();
+                    [width, height, 0.0, 0.0]
+                }
+                _ => [0.0; 4],
+            },
+            view: [self.fisheye_fov.to_radians(), self.eye_separation, 0.0, 0.0],
+            shutter: [
+                self.time + self.shutter_interval[0],
+                self.time + self.shutter_interval[1],
+                0.0,
+                0.0,
+            ],
         }
     }
 }
@@ -90,174 +460,315 @@ pub struct CameraUniform {
     pub horizontal: [f32; 4],
     pub vertical: [f32; 4],
     pub lower_left_corner: [f32; 4],
+    /// Aperture radius, focus distance, aperture rotation, custom mask texel size.
+    pub lens: [f32; 4],
+    /// Aperture kind (0 circular, 1 polygon, 2 custom), blade count, mask sample count.
+    pub aperture: [u32; 4],
+    /// Projection kind (0 perspective, 1 realistic lens, 2 orthographic,
+    /// 3 equidistant fisheye, 4 equisolid fisheye, 5 equirectangular),
+    /// lens element count, dispersive, side-by-side stereo.
+    pub projection: [u32; 4],
+    /// Film width and height in metres for the realistic lens.
+    pub film: [f32; 4],
+    /// Fisheye field of view in radians, stereo eye separation.
+    pub view: [f32; 4],
+    /// Shutter open and close times in seconds.
+    pub shutter: [f32; 4],
+}
+
+/// How mouse and movement keys steer the camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NavigationMode {
+    /// First person: look around and fly along the view direction.
+    Fly,
+    /// Rotate freely around the pivot; the view may roll over the poles.
+    Orbit,
+    /// Rotate around the pivot about the world up axis, keeping the horizon level.
+    Turntable,
+}
+
+impl NavigationMode {
+    pub const ALL: [NavigationMode; 3] = [
+        NavigationMode::Fly,
+        NavigationMode::Orbit,
+        NavigationMode::Turntable,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NavigationMode::Fly => "fly",
+            NavigationMode::Orbit => "orbit",
+            NavigationMode::Turntable => "turntable",
+        }
+    }
+}
+
+/// Key bindings and sensitivities, loadable from a RON file. Missing fields
+/// keep their defaults.
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct Controls {
+    /// Radians of rotation per mouse count.
+    pub mouse_scaling: f32,
+    /// Fly speed in scene units per second.
+    pub move_speed: f32,
+    /// Time constant of the motion smoothing in seconds; zero disables it.
+    pub damping: f32,
+    /// Movement keys fly in fly mode; in orbit modes forward/backward dolly
+    /// towards the pivot and the others pan.
+    pub forward: Vec<KeyCode>,
+    pub backward: Vec<KeyCode>,
+    pub left: Vec<KeyCode>,
+    pub right: Vec<KeyCode>,
+    pub up: Vec<KeyCode>,
+    pub down: Vec<KeyCode>,
+    pub boost: Vec<KeyCode>,
+    pub cycle_mode: Vec<KeyCode>,
 }
 
+impl Default for Controls {
+    fn default() -> Self {
+        Self {
+            mouse_scaling: MOUSE_SCALING,
+            move_speed: 5.0,
+            damping: 0.05,
+            forward: vec![KeyCode::KeyW, KeyCode::ArrowUp],
+            backward: vec![KeyCode::KeyS, KeyCode::ArrowDown],
+            left: vec![KeyCode::KeyA, KeyCode::ArrowLeft],
+            right: vec![KeyCode::KeyD, KeyCode::ArrowRight],
+            up: vec![KeyCode::Space],
+            down: vec![KeyCode::ControlLeft],
+            boost: vec![KeyCode::ShiftLeft],
+            cycle_mode: vec![KeyCode::Tab],
+        }
+    }
+}
+
+impl Controls {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /// Bindings that take one of the `reserved` keys, as (action, key).
+    pub fn conflicts(&self, reserved: &[KeyCode]) -> Vec<(&'static str, KeyCode)> {
+        [
+            ("forward", &self.forward),
+            ("backward", &self.backward),
+            ("left", &self.left),
+            ("right", &self.right),
+            ("up", &self.up),
+            ("down", &self.down),
+            ("boost", &self.boost),
+            ("cycle_mode", &self.cycle_mode),
+        ]
+        .into_iter()
+        .flat_map(|(action, keys)| {
+            keys.iter()
+                .filter(|key| reserved.contains(key))
+                .map(move |&key| (action, key))
+        })
+        .collect()
+    }
+}
+
+// Pitch limit of the fly and turntable modes, in degrees from the horizon
+const MAX_PITCH: f32 = 89.0;
+// Closest a dolly may bring the camera to the pivot
+const MIN_ORBIT_DISTANCE: f32 = 0.01;
+// Below these the damped motion counts as settled
+const MOTION_EPSILON: f32 = 1e-5;
+
 pub struct CameraController {
-    default_speed: f32,
+    pub controls: Controls,
+    pub mode: NavigationMode,
+    /// Centre of the orbit modes; placed at the focus distance when unset.
+    pub pivot: Option<cgmath::Point3<f32>>,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
     is_right_pressed: bool,
     is_up_pressed: bool,
     is_down_pressed: bool,
-    is_mouse_dragged: bool,
     is_speed_boost: bool,
-    mouse_delta: cgmath::Vector2<f32>,
+    is_cycle_pressed: bool,
+    /// Smoothed velocity in camera space (right, up, forward), in units per second.
+    velocity: cgmath::Vector3<f32>,
+    /// Yaw and pitch in radians still to be applied.
+    pending_rotation: cgmath::Vector2<f32>,
 }
 
 impl CameraController {
-    pub fn new(default_speed: f32) -> Self {
+    pub fn new(controls: Controls) -> Self {
         Self {
-            default_speed,
+            controls,
+            mode: NavigationMode::Fly,
+            pivot: None,
             is_forward_pressed: false,
             is_backward_pressed: false,
             is_left_pressed: false,
             is_right_pressed: false,
-            is_mouse_dragged: false,
             is_up_pressed: false,
             is_down_pressed: false,
             is_speed_boost: false,
-            mouse_delta: cgmath::vec2(0.0, 0.0),
+            is_cycle_pressed: false,
+            velocity: cgmath::Vector3::zero(),
+            pending_rotation: cgmath::Vector2::zero(),
         }
     }
 
+    /// Forget the pivot and any motion still to come, for when the camera
+    /// has been placed by something other than the controller.
+    pub fn reset(&mut self) {
+        self.pivot = None;
+        self.velocity = cgmath::Vector3::zero();
+        self.pending_rotation = cgmath::Vector2::zero();
+    }
+
     pub fn process_events(&mut self, event: &DeviceEvent) -> bool {
         match event {
             DeviceEvent::Key(keyboard_input) => {
                 let is_pressed = keyboard_input.state == ElementState::Pressed;
-                match keyboard_input.physical_key {
-                    PhysicalKey::Code(KeyCode::KeyW) | PhysicalKey::Code(KeyCode::ArrowUp) => {
-                        self.is_forward_pressed = is_pressed;
-                        true
-                    }
-                    PhysicalKey::Code(KeyCode::KeyA) | PhysicalKey::Code(KeyCode::ArrowLeft) => {
-                        self.is_left_pressed = is_pressed;
-                        true
-                    }
-                    PhysicalKey::Code(KeyCode::KeyS) | PhysicalKey::Code(KeyCode::ArrowDown) => {
-                        self.is_backward_pressed = is_pressed;
-                        true
-                    }
-                    PhysicalKey::Code(KeyCode::KeyD) | PhysicalKey::Code(KeyCode::ArrowRight) => {
-                        self.is_right_pressed = is_pressed;
-                        true
+                let PhysicalKey::Code(code) = keyboard_input.physical_key else {
+                    return false;
+                };
+                let controls = &self.controls;
+                if controls.forward.contains(&code) {
+                    self.is_forward_pressed = is_pressed;
+                } else if controls.backward.contains(&code) {
+                    self.is_backward_pressed = is_pressed;
+                } else if controls.left.contains(&code) {
+                    self.is_left_pressed = is_pressed;
+                } else if controls.right.contains(&code) {
+                    self.is_right_pressed = is_pressed;
+                } else if controls.up.contains(&code) {
+                    self.is_up_pressed = is_pressed;
+                } else if controls.down.contains(&code) {
+                    self.is_down_pressed = is_pressed;
+                } else if controls.boost.contains(&code) {
+                    self.is_speed_boost = is_pressed;
+                } else if controls.cycle_mode.contains(&code) {
+                    if is_pressed && !self.is_cycle_pressed {
+                        self.mode = crate::color::next(&NavigationMode::ALL, self.mode);
+                        // The next orbit starts from wherever the view now points
+                        self.pivot = None;
+                        println!("Navigation: {}", self.mode.name());
                     }
-                    PhysicalKey::Code(KeyCode::Space) => {
-                        self.is_up_pressed = is_pressed;
-                        true
-                    }
-                    PhysicalKey::Code(KeyCode::ControlLeft) => {
-                        self.is_down_pressed = is_pressed;
-                        true
-                    }
-                    PhysicalKey::Code(KeyCode::ShiftLeft) => {
-                        self.is_speed_boost = is_pressed;
-                        true
-                    }
-                    _ => false,
+                    self.is_cycle_pressed = is_pressed;
+                } else {
+                    return false;
                 }
+                true
             }
             DeviceEvent::MouseMotion { delta } => {
-                self.mouse_delta = cgmath::vec2(delta.0 as f32, delta.1 as f32);
-                self.is_mouse_dragged = true;
+                // Accumulate every event of the frame
+                self.pending_rotation +=
+                    cgmath::vec2(delta.0 as f32, delta.1 as f32) * self.controls.mouse_scaling;
                 true
             }
-            _ => {
-                self.is_mouse_dragged = false;
-                false
-            }
+            _ => false,
         }
     }
 
+    /// Advance the camera by `duration` microseconds. Returns true if it moved.
     pub fn update_camera(&mut self, camera: &mut Camera, duration: u128) -> bool {
-        let was_camera_changed = self.is_left_pressed
-            | self.is_right_pressed
-            | self.is_forward_pressed
-            | self.is_backward_pressed
-            | self.is_up_pressed
-            | self.is_down_pressed
-            | self.is_mouse_dragged;
-
-        let forward = camera.vertical.cross(camera.horizontal);
-        let forward_norm = forward.normalize();
-        let forward_mag = forward.magnitude();
-
-        let right_norm = camera.horizontal.normalize();
-
-        let move_speed = if self.is_speed_boost {
-            2. * self.default_speed * duration as f32
+        let dt = duration as f32 * 1e-6;
+        // Fraction of the remaining motion to take this frame
+        let follow = if self.controls.damping > 0.0 {
+            1.0 - (-dt / self.controls.damping).exp()
         } else {
-            self.default_speed * duration as f32
+            1.0
         };
 
-        // Project forward direction onto xz plane
-        if self.is_forward_pressed && forward_mag > move_speed {
-            camera.origin += forward_norm * move_speed;
-            camera.lower_left_corner += forward_norm * move_speed;
-        }
-        if self.is_backward_pressed {
-            camera.origin -= forward_norm * move_speed;
-            camera.lower_left_corner -= forward_norm * move_speed;
-        }
-        if self.is_right_pressed {
-            camera.origin += right_norm * move_speed;
-            camera.lower_left_corner += right_norm * move_speed;
-        }
-        if self.is_left_pressed {
-            camera.origin -= right_norm * move_speed;
-            camera.lower_left_corner -= right_norm * move_speed;
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let mut target = cgmath::vec3(
+            axis(self.is_right_pressed, self.is_left_pressed),
+            axis(self.is_up_pressed, self.is_down_pressed),
+            axis(self.is_forward_pressed, self.is_backward_pressed),
+        ) * self.controls.move_speed;
+        if self.is_speed_boost {
+            target *= 2.0;
         }
-        if self.is_up_pressed {
-            camera.origin += cgmath::Vector3::unit_y() * move_speed;
-            camera.lower_left_corner += cgmath::Vector3::unit_y() * move_speed;
-        }
-        if self.is_down_pressed {
-            camera.origin -= cgmath::Vector3::unit_y() * move_speed;
-            camera.lower_left_corner -= cgmath::Vector3::unit_y() * move_speed;
+        self.velocity += (target - self.velocity) * follow;
+        let rotation = self.pending_rotation * follow;
+        self.pending_rotation -= rotation;
+
+        let moving = self.velocity.magnitude() > MOTION_EPSILON;
+        let turning = rotation.magnitude() > MOTION_EPSILON;
+        if !moving && !turning {
+            self.velocity = cgmath::Vector3::zero();
+            return false;
         }
 
-        if self.is_mouse_dragged {
-            // Our vertical rotation depends on camera.horizontal, so we apply horizontal
-            // rotations first.
-            let horizontal_rotation = cgmath::Quaternion::from_axis_angle(
-                cgmath::Vector3::unit_y(),
-                cgmath::Rad(
-                    (MOUSE_SCALING * duration as f32)
-                        * self.mouse_delta.x
-                        * std::f32::consts::FRAC_2_PI,
-                ),
-            );
-            camera.horizontal = horizontal_rotation.rotate_vector(camera.horizontal);
-            camera.vertical = horizontal_rotation.rotate_vector(camera.vertical);
-            // We need to rotate the lower left corner about the
-            // origin, so apply translations.
-            camera.lower_left_corner = horizontal_rotation
-                .rotate_point(camera.lower_left_corner - camera.origin.to_vec())
-                + camera.origin.to_vec();
-
-            let vertical_rotation = cgmath::Quaternion::from_axis_angle(
-                camera.horizontal.normalize(),
-                cgmath::Rad(
-                    (MOUSE_SCALING * duration as f32)
-                        * self.mouse_delta.y
-                        * std::f32::consts::FRAC_2_PI,
-                ),
-            )
-            .normalize();
-
-            // Clamp vertical rotation to prevent tipping backwards
-            let resulting_vertical = vertical_rotation.rotate_vector(camera.vertical);
-            if resulting_vertical.angle(cgmath::Vector3::unit_y()).tan() < 1e-10 {
-                camera.vertical = resulting_vertical;
-                camera.lower_left_corner = vertical_rotation
-                    .rotate_point(camera.lower_left_corner - camera.origin.to_vec())
-                    + camera.origin.to_vec();
+        match self.mode {
+            NavigationMode::Fly => {
+                let step = self.velocity * dt;
+                let right = camera.horizontal.normalize();
+                // Vertical movement stays along the world axis
+                camera.translate(
+                    right * step.x + cgmath::Vector3::unit_y() * step.y + camera.forward() * step.z,
+                );
+                let origin = camera.origin;
+                camera.rotate_about(
+                    cgmath::Quaternion::from_axis_angle(
+                        cgmath::Vector3::unit_y(),
+                        cgmath::Rad(rotation.x),
+                    ),
+                    origin,
+                );
+                Self::pitch_clamped(camera, rotation.y, origin);
+            }
+            NavigationMode::Orbit | NavigationMode::Turntable => {
+                let pivot = *self
+                    .pivot
+                    .get_or_insert(camera.origin + camera.forward() * camera.focus_distance);
+                let distance = (pivot - camera.origin).magnitude();
+
+                // Pan and dolly scale with the distance so they feel the same at any zoom
+                let step = self.velocity / self.controls.move_speed.max(1e-6) * distance * dt;
+                // `vertical` runs down the image, so up is its negation
+                let pan =
+                    camera.horizontal.normalize() * step.x - camera.vertical.normalize() * step.y;
+                camera.translate(pan);
+                let pivot = pivot + pan;
+                self.pivot = Some(pivot);
+                let dolly = step.z.min(distance - MIN_ORBIT_DISTANCE);
+                camera.translate(camera.forward() * dolly);
+
+                if self.mode == NavigationMode::Orbit {
+                    let up = -camera.vertical.normalize();
+                    camera.rotate_about(
+                        cgmath::Quaternion::from_axis_angle(up, cgmath::Rad(rotation.x)),
+                        pivot,
+                    );
+                    let right = camera.horizontal.normalize();
+                    camera.rotate_about(
+                        cgmath::Quaternion::from_axis_angle(right, cgmath::Rad(rotation.y)),
+                        pivot,
+                    );
+                } else {
+                    camera.rotate_about(
+                        cgmath::Quaternion::from_axis_angle(
+                            cgmath::Vector3::unit_y(),
+                            cgmath::Rad(rotation.x),
+                        ),
+                        pivot,
+                    );
+                    Self::pitch_clamped(camera, rotation.y, pivot);
+                }
             }
-            self.mouse_delta = cgmath::Vector2::zero();
-            self.is_mouse_dragged = false;
         }
+        true
+    }
 
-        // Return true if camera was changed
-        was_camera_changed
+    /// Pitch about the camera's right axis, refusing to tip past straight up or down.
+    fn pitch_clamped(camera: &mut Camera, angle: f32, center: cgmath::Point3<f32>) {
+        let rotation =
+            cgmath::Quaternion::from_axis_angle(camera.horizontal.normalize(), cgmath::Rad(angle));
+        // `vertical` runs down the image, so compare it against world down
+        let resulting_vertical = rotation.rotate_vector(camera.vertical);
+        if resulting_vertical.angle(-cgmath::Vector3::unit_y()) < cgmath::Deg(MAX_PITCH).into() {
+            camera.rotate_about(rotation, center);
+        }
     }
 }
@@ -0,0 +1,228 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bookmarks::Bookmark,
+    camera::{ApertureShape, Camera, Projection},
+    color::ColorPipeline,
+    export,
+    lens::LensSystem,
+    mega_kernel::ComputePass,
+};
+
+const MAGIC: &[u8; 4] = b"WRCK";
+// Bytes per texel of the Rgba32Float accumulation textures, and per visible point
+const TEXEL_SIZE: usize = 16;
+// The film, moments and four AOVs
+const TEXTURE_COUNT: usize = 6;
+const VISPOINT_SIZE: usize = 64;
+
+/// Progressive state that is not stored in GPU memory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointHeader {
+    pub width: u32,
+    pub height: u32,
+    pub iteration: u32,
+    pub photon_radius: f32,
+    pub seed: u32,
+    pub camera: Bookmark,
+    pub time: f32,
+    pub shutter_interval: [f32; 2],
+    /// Observer the film was accumulated with and colour space it resolves
+    /// to, which a resume must match.
+    pub observer: String,
+    pub output: String,
+    /// Lens and aperture mask the camera used, restored in place of the ones
+    /// loaded from res/ since those may have changed since.
+    pub lens: Option<LensSystem>,
+    pub aperture_mask: Option<ApertureShape>,
+}
+
+impl CheckpointHeader {
+    /// Whether the accumulation can continue with `color`.
+    pub fn check(&self, color: &ColorPipeline) -> Result<(), String> {
+        if self.observer != color.observer.name() {
+            return Err(format!(
+                "the checkpoint was accumulated for the {} observer, not {}",
+                self.observer,
+                color.observer.name()
+            ));
+        }
+        if self.output != color.output.name() {
+            return Err(format!(
+                "the checkpoint resolves to {}, not {}",
+                self.output,
+                color.output.name()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Everything needed to continue an accumulation where it stopped. Stored
+/// as the magic, the length of a RON header, the header, then the raw
+/// accumulation textures and visible points.
+pub struct Checkpoint {
+    pub header: CheckpointHeader,
+    /// Contents of the textures of `accumulation_textures`, in order.
+    textures: Vec<Vec<u8>>,
+    vispoints: Vec<u8>,
+}
+
+/// Textures that accumulate over the samples of a render.
+fn accumulation_textures<'a>(
+    compute_pass: &'a ComputePass,
+    film: &'a wgpu::Texture,
+) -> [&'a wgpu::Texture; TEXTURE_COUNT] {
+    let aovs = &compute_pass.aov_textures;
+    [
+        film,
+        &compute_pass.moments_texture,
+        &aovs.albedo,
+        &aovs.normal,
+        &aovs.position,
+        &aovs.ids,
+    ]
+}
+
+impl Checkpoint {
+    /// Checkpoint file for `scene_path`, stored as `<scene>.checkpoint`.
+    pub fn path_for(scene_path: &str) -> PathBuf {
+        PathBuf::from(scene_path).with_extension("checkpoint")
+    }
+
+    /// Read back the textures and visible points of the current accumulation.
+    pub fn capture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        compute_pass: &ComputePass,
+        film: &wgpu::Texture,
+        vispoint_buffer: &wgpu::Buffer,
+        camera: &Camera,
+        color: &ColorPipeline,
+    ) -> Option<Self> {
+        let textures = accumulation_textures(compute_pass, film)
+            .into_iter()
+            .map(|texture| {
+                export::read_texture(device, queue, texture)
+                    .map(|texels| bytemuck::cast_slice(&texels).to_vec())
+            })
+            .collect::<Option<_>>()?;
+        let vispoints = export::read_buffer(device, queue, vispoint_buffer)?;
+        Some(Self {
+            header: CheckpointHeader {
+                width: film.width(),
+                height: film.height(),
+                iteration: compute_pass.iteration,
+                photon_radius: compute_pass.photon_radius,
+                seed: compute_pass.seed,
+                camera: Bookmark::from_camera(camera),
+                time: camera.time,
+                shutter_interval: camera.shutter_interval,
+                observer: color.observer.name().to_string(),
+                output: color.output.name().to_string(),
+                lens: match &camera.projection {
+                    Projection::Realistic(lens) => Some(lens.clone()),
+                    _ => None,
+                },
+                aperture_mask: match &camera.aperture {
+                    aperture @ ApertureShape::Custom { .. } => Some(aperture.clone()),
+                    _ => None,
+                },
+            },
+            textures,
+            vispoints,
+        })
+    }
+
+    /// Write the checkpoint next to `path` and move it into place, so an
+    /// interrupted write never replaces a good checkpoint.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let header = ron::to_string(&self.header).map_err(|e| e.to_string())?;
+        let texture_bytes: usize = self.textures.iter().map(Vec::len).sum();
+        let mut data = Vec::with_capacity(8 + header.len() + texture_bytes + self.vispoints.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        data.extend_from_slice(header.as_bytes());
+        for texture in &self.textures {
+            data.extend_from_slice(texture);
+        }
+        data.extend_from_slice(&self.vispoints);
+
+        let partial = path.with_extension("checkpoint.partial");
+        std::fs::write(&partial, data).map_err(|e| e.to_string())?;
+        std::fs::rename(&partial, path).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = std::fs::read(path).map_err(|e| e.to_string())?;
+        if data.len() < 8 || &data[..4] != MAGIC {
+            return Err("not a checkpoint file".to_string());
+        }
+        let header_len = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let header_end = 8 + header_len;
+        let header = data
+            .get(8..header_end)
+            .and_then(|bytes| std::str::from_utf8(bytes).ok())
+            .ok_or_else(|| "truncated checkpoint header".to_string())?;
+        let header: CheckpointHeader = ron::from_str(header).map_err(|e| e.to_string())?;
+
+        let pixels = (header.width * header.height) as usize;
+        let textures_end = header_end + TEXTURE_COUNT * pixels * TEXEL_SIZE;
+        if pixels == 0 || data.len() != textures_end + pixels * VISPOINT_SIZE {
+            return Err(format!(
+                "checkpoint data does not match its {}x{} size",
+                header.width, header.height
+            ));
+        }
+        Ok(Self {
+            header,
+            textures: data[header_end..textures_end]
+                .chunks(pixels * TEXEL_SIZE)
+                .map(<[u8]>::to_vec)
+                .collect(),
+            vispoints: data[textures_end..].to_vec(),
+        })
+    }
+
+    /// Upload the saved state and camera. The film must already have the
+    /// checkpoint's size, and the colour pipeline must pass `check`.
+    pub fn restore(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        compute_pass: &mut ComputePass,
+        film: &wgpu::Texture,
+        vispoint_buffer: &wgpu::Buffer,
+        camera: &mut Camera,
+    ) {
+        let header = &self.header;
+        header
+            .camera
+            .apply(camera, header.lens.as_ref(), header.aperture_mask.as_ref());
+        compute_pass.update_aperture(device, camera.aperture.samples());
+        compute_pass.update_lens(device, &camera.projection.lens_elements());
+        camera.time = header.time;
+        camera.shutter_interval = header.shutter_interval;
+        // Uploading the camera resets the accumulation, so restore it afterwards
+        compute_pass.update(queue, camera.get_uniform());
+        compute_pass.iteration = header.iteration;
+        compute_pass.photon_radius = header.photon_radius;
+        compute_pass.seed = header.seed;
+        compute_pass.preview_next_frame = false;
+
+        let layout = wgpu::TexelCopyBufferLayout {
+            offset: 0,
+            bytes_per_row: Some(header.width * TEXEL_SIZE as u32),
+            rows_per_image: Some(header.height),
+        };
+        for (texture, data) in accumulation_textures(compute_pass, film)
+            .into_iter()
+            .zip(&self.textures)
+        {
+            queue.write_texture(texture.as_image_copy(), data, layout, texture.size());
+        }
+        queue.write_buffer(vispoint_buffer, 0, &self.vispoints);
+    }
+}
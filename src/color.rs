@@ -0,0 +1,194 @@
+use cgmath::{Matrix, Matrix3, SquareMatrix, Vector3};
+
+use crate::spectrum::Observer;
+
+/// CIE xy chromaticity of a reference white.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhitePoint {
+    pub name: &'static str,
+    pub x: f32,
+    pub y: f32,
+}
+
+// Presets are stored by name
+impl serde::Serialize for WhitePoint {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for WhitePoint {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Self::from_name(&name)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown white point {name}")))
+    }
+}
+
+impl WhitePoint {
+    pub const D50: WhitePoint = WhitePoint { name: "D50", x: 0.3457, y: 0.3585 };
+    pub const D60: WhitePoint = WhitePoint { name: "D60", x: 0.32168, y: 0.33767 };
+    pub const D65: WhitePoint = WhitePoint { name: "D65", x: 0.3127, y: 0.3290 };
+    pub const E: WhitePoint = WhitePoint { name: "E", x: 1.0 / 3.0, y: 1.0 / 3.0 };
+
+    pub const ALL: [WhitePoint; 4] = [Self::D65, Self::D50, Self::D60, Self::E];
+
+    pub fn from_name(name: &str) -> Option<WhitePoint> {
+        Self::ALL.into_iter().find(|white| white.name == name)
+    }
+
+    /// White on (or `duv` off) the Planckian locus at `temperature` kelvin,
+    /// using Krystek's rational approximation (valid 1000-15000K).
+    pub fn from_temperature(temperature: f32, duv: f32) -> WhitePoint {
+        let t = temperature.clamp(1000.0, 15000.0) as f64;
+        let locus = |t: f64| {
+            let u = (0.860117757 + 1.54118254e-4 * t + 1.28641212e-7 * t * t)
+                / (1.0 + 8.42420235e-4 * t + 7.08145163e-7 * t * t);
+            let v = (0.317398726 + 4.22806245e-5 * t + 4.20481691e-8 * t * t)
+                / (1.0 - 2.89741816e-5 * t + 1.61456053e-7 * t * t);
+            (u, v)
+        };
+        let (u0, v0) = locus(t);
+        // Offset along the normal of the locus, which points towards green
+        let (u1, v1) = locus(t + 1.0);
+        let (du, dv) = (u1 - u0, v1 - v0);
+        let len = (du * du + dv * dv).sqrt();
+        let u = u0 + dv / len * duv as f64;
+        let v = v0 - du / len * duv as f64;
+
+        let d = 2.0 * u - 8.0 * v + 4.0;
+        WhitePoint {
+            name: "custom",
+            x: (3.0 * u / d) as f32,
+            y: (2.0 * v / d) as f32,
+        }
+    }
+
+    /// XYZ of the white normalised to Y = 1.
+    pub fn xyz(&self) -> Vector3<f32> {
+        Vector3::new(self.x / self.y, 1.0, (1.0 - self.x - self.y) / self.y)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    DisplayP3,
+    Rec2020,
+    AcesCg,
+}
+
+impl ColorSpace {
+    pub const ALL: [ColorSpace; 4] = [
+        ColorSpace::Srgb,
+        ColorSpace::DisplayP3,
+        ColorSpace::Rec2020,
+        ColorSpace::AcesCg,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorSpace::Srgb => "sRGB",
+            ColorSpace::DisplayP3 => "Display P3",
+            ColorSpace::Rec2020 => "Rec.2020",
+            ColorSpace::AcesCg => "ACEScg",
+        }
+    }
+
+    /// Red, green and blue primaries as xy chromaticities.
+    pub fn primaries(&self) -> [[f32; 2]; 3] {
+        match self {
+            ColorSpace::Srgb => [[0.64, 0.33], [0.30, 0.60], [0.15, 0.06]],
+            ColorSpace::DisplayP3 => [[0.680, 0.320], [0.265, 0.690], [0.150, 0.060]],
+            ColorSpace::Rec2020 => [[0.708, 0.292], [0.170, 0.797], [0.131, 0.046]],
+            ColorSpace::AcesCg => [[0.713, 0.293], [0.165, 0.830], [0.128, 0.044]],
+        }
+    }
+
+    pub fn white(&self) -> WhitePoint {
+        match self {
+            ColorSpace::AcesCg => WhitePoint::D60,
+            _ => WhitePoint::D65,
+        }
+    }
+
+    pub fn rgb_to_xyz(&self) -> Matrix3<f32> {
+        let xyz = |[x, y]: [f32; 2]| Vector3::new(x / y, 1.0, (1.0 - x - y) / y);
+        let [r, g, b] = self.primaries();
+        let primaries = Matrix3::from_cols(xyz(r), xyz(g), xyz(b));
+        // Scale each primary so that RGB (1, 1, 1) lands on the white point
+        let s = primaries.invert().unwrap() * self.white().xyz();
+        Matrix3::from_cols(primaries.x * s.x, primaries.y * s.y, primaries.z * s.z)
+    }
+
+    pub fn xyz_to_rgb(&self) -> Matrix3<f32> {
+        self.rgb_to_xyz().invert().unwrap()
+    }
+}
+
+/// Bradford chromatic adaptation taking colours seen under `from` to `to`.
+pub fn bradford(from: WhitePoint, to: WhitePoint) -> Matrix3<f32> {
+    // Rows of the Bradford cone response matrix, stored column-major
+    let m = Matrix3::new(
+        0.8951, -0.7502, 0.0389, //
+        0.2664, 1.7135, -0.0685, //
+        -0.1614, 0.0367, 1.0296,
+    );
+    let src = m * from.xyz();
+    let dst = m * to.xyz();
+    let scale = Matrix3::from_diagonal(Vector3::new(dst.x / src.x, dst.y / src.y, dst.z / src.z));
+    m.invert().unwrap() * scale * m
+}
+
+/// How the XYZ film is interpreted and converted for output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorPipeline {
+    pub observer: Observer,
+    pub output: ColorSpace,
+    /// The scene white that should appear neutral in the output.
+    pub adopted_white: WhitePoint,
+}
+
+impl Default for ColorPipeline {
+    fn default() -> Self {
+        Self {
+            observer: Observer::Cie1931,
+            output: ColorSpace::Srgb,
+            adopted_white: WhitePoint::D65,
+        }
+    }
+}
+
+impl ColorPipeline {
+    pub fn xyz_to_output(&self) -> Matrix3<f32> {
+        self.output.xyz_to_rgb() * bradford(self.adopted_white, self.output.white())
+    }
+
+    pub fn get_uniform(&self) -> ColorUniform {
+        let m = self.xyz_to_output();
+        let lum = self.output.rgb_to_xyz().row(1);
+        ColorUniform {
+            xyz_to_output: [
+                [m.x.x, m.x.y, m.x.z, 0.0],
+                [m.y.x, m.y.y, m.y.z, 0.0],
+                [m.z.x, m.z.y, m.z.z, 0.0],
+            ],
+            luminance: [lum.x, lum.y, lum.z, 0.0],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ColorUniform {
+    /// Column-major, each column padded to a vec4 to match WGSL `mat3x3` layout.
+    pub xyz_to_output: [[f32; 4]; 3],
+    /// Luminance weights of the output primaries.
+    pub luminance: [f32; 4],
+}
+
+/// Cycle to the element after `current` in `all`.
+pub fn next<T: Copy + PartialEq>(all: &[T], current: T) -> T {
+    let i = all.iter().position(|c| *c == current).unwrap_or(0);
+    all[(i + 1) % all.len()]
+}
@@ -0,0 +1,316 @@
+//! Edge-avoiding à-trous denoiser guided by the AOVs. See denoise.wgsl.
+
+use crate::aov::AovTextures;
+
+pub const MAX_ITERATIONS: u32 = 8;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DenoiseSettings {
+    pub enabled: bool,
+    /// Filter passes, each twice as wide as the last.
+    pub iterations: u32,
+    /// Luminance differences tolerated, in standard deviations of the noise.
+    pub sigma_luminance: f32,
+    /// Exponent on the cosine between normals.
+    pub sigma_normal: f32,
+    /// Depth differences tolerated, relative to the depth, per step of a pass.
+    pub sigma_depth: f32,
+    pub sigma_albedo: f32,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            iterations: 5,
+            sigma_luminance: 4.0,
+            sigma_normal: 128.0,
+            sigma_depth: 0.05,
+            sigma_albedo: 0.1,
+        }
+    }
+}
+
+impl DenoiseSettings {
+    /// Metadata entry describing the filter, for exported images.
+    pub fn metadata(&self) -> (String, String) {
+        (
+            "denoiser".into(),
+            format!(
+                "a-trous {} passes, luminance {} normal {} depth {} albedo {}",
+                self.iterations,
+                self.sigma_luminance,
+                self.sigma_normal,
+                self.sigma_depth,
+                self.sigma_albedo
+            ),
+        )
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct DenoiseParams {
+    step: u32,
+    last: u32,
+    sigma_luminance: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    sigma_albedo: f32,
+    _pad0: f32,
+    _pad1: f32,
+}
+
+pub struct Denoiser {
+    prepare_pipeline: wgpu::ComputePipeline,
+    atrous_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// One uniform per pass, the preparation first.
+    param_buffers: Vec<wgpu::Buffer>,
+    /// Textures the passes ping-pong between.
+    scratch: [wgpu::Texture; 2],
+    bind_groups: Vec<wgpu::BindGroup>,
+    /// Mean XYZ of the last run, with a sample count of 1 in alpha.
+    pub output: wgpu::Texture,
+    pub output_view: wgpu::TextureView,
+    iterations: u32,
+}
+
+impl Denoiser {
+    pub fn new(
+        device: &wgpu::Device,
+        film_view: &wgpu::TextureView,
+        moments: &wgpu::Texture,
+        aovs: &AovTextures,
+        settings: &DenoiseSettings,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Denoise Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("kernels/denoise.wgsl").into()),
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("denoise_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_entry(1),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                texture_entry(3),
+                texture_entry(4),
+                texture_entry(5),
+                texture_entry(6),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("denoise_pipeline_layout"),
+            bind_group_layouts: &[Some(&bind_group_layout)],
+            immediate_size: 0,
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        let param_buffers = (0..=MAX_ITERATIONS)
+            .map(|_| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("denoise_params_buffer"),
+                    size: std::mem::size_of::<DenoiseParams>() as u64,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+
+        let size = moments.size();
+        let (scratch, output) = Self::create_textures(device, size);
+        let mut denoiser = Self {
+            prepare_pipeline: pipeline("cs_prepare"),
+            atrous_pipeline: pipeline("cs_atrous"),
+            bind_group_layout,
+            param_buffers,
+            output_view: output.create_view(&Default::default()),
+            output,
+            scratch,
+            bind_groups: Vec::new(),
+            iterations: settings.iterations,
+        };
+        denoiser.bind_groups = denoiser.create_bind_groups(device, film_view, moments, aovs);
+        denoiser
+    }
+
+    /// Rebind to the film, moments and AOVs of a resized accumulation.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        film_view: &wgpu::TextureView,
+        moments: &wgpu::Texture,
+        aovs: &AovTextures,
+    ) {
+        (self.scratch, self.output) = Self::create_textures(device, moments.size());
+        self.output_view = self.output.create_view(&Default::default());
+        self.bind_groups = self.create_bind_groups(device, film_view, moments, aovs);
+    }
+
+    /// Upload the filter parameters of every pass.
+    pub fn update(&mut self, queue: &wgpu::Queue, settings: &DenoiseSettings) {
+        self.iterations = settings.iterations.clamp(1, MAX_ITERATIONS);
+        for (pass, buffer) in self.param_buffers.iter().enumerate() {
+            let params = DenoiseParams {
+                // The preparation pass does not filter
+                step: 1 << pass.saturating_sub(1),
+                last: (pass as u32 == self.iterations) as u32,
+                sigma_luminance: settings.sigma_luminance,
+                sigma_normal: settings.sigma_normal,
+                sigma_depth: settings.sigma_depth,
+                sigma_albedo: settings.sigma_albedo,
+                _pad0: 0.0,
+                _pad1: 0.0,
+            };
+            queue.write_buffer(buffer, 0, bytemuck::bytes_of(&params));
+        }
+    }
+
+    /// Filter the current accumulation into `output`.
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder) {
+        let size = self.output.size();
+        let workgroups = (size.width.div_ceil(8), size.height.div_ceil(8));
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            compute_pass.set_pipeline(&self.prepare_pipeline);
+            compute_pass.set_bind_group(0, &self.bind_groups[0], &[]);
+            compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+            compute_pass.set_pipeline(&self.atrous_pipeline);
+            for bind_group in &self.bind_groups[1..=self.iterations as usize] {
+                compute_pass.set_bind_group(0, bind_group, &[]);
+                compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+            }
+        }
+        // Pass i writes scratch[i % 2], the preparation being pass 0
+        let result = &self.scratch[self.iterations as usize % 2];
+        encoder.copy_texture_to_texture(result.as_image_copy(), self.output.as_image_copy(), size);
+    }
+
+    fn create_textures(
+        device: &wgpu::Device,
+        size: wgpu::Extent3d,
+    ) -> ([wgpu::Texture; 2], wgpu::Texture) {
+        let create = |label, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | usage,
+                view_formats: &[],
+            })
+        };
+        let scratch_usage = wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC;
+        (
+            [
+                create("denoise_scratch_texture", scratch_usage),
+                create("denoise_scratch_texture", scratch_usage),
+            ],
+            create(
+                "denoise_output_texture",
+                wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+            ),
+        )
+    }
+
+    fn create_bind_groups(
+        &self,
+        device: &wgpu::Device,
+        film_view: &wgpu::TextureView,
+        moments: &wgpu::Texture,
+        aovs: &AovTextures,
+    ) -> Vec<wgpu::BindGroup> {
+        let scratch_views = self
+            .scratch
+            .each_ref()
+            .map(|texture| texture.create_view(&Default::default()));
+        let moments_view = moments.create_view(&Default::default());
+        let albedo_view = aovs.albedo.create_view(&Default::default());
+        let normal_view = aovs.normal.create_view(&Default::default());
+        self.param_buffers
+            .iter()
+            .enumerate()
+            .map(|(pass, params)| {
+                // Pass i reads what pass i - 1 wrote; the preparation reads
+                // only the film, but the source still has to be bound
+                let (src, dst) = (&scratch_views[(pass + 1) % 2], &scratch_views[pass % 2]);
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("denoise_bind_group"),
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: params.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(src),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(dst),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(film_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: wgpu::BindingResource::TextureView(&moments_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 5,
+                            resource: wgpu::BindingResource::TextureView(&albedo_view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 6,
+                            resource: wgpu::BindingResource::TextureView(&normal_view),
+                        },
+                    ],
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,458 @@
+use std::io::Write;
+
+use crate::{
+    aov::AovImages,
+    camera::Camera,
+    color::{ColorPipeline, WhitePoint},
+    lut::Lut,
+    tonemap::TonemapUniform,
+};
+
+/// Copy an `Rgba32Float` texture back to the CPU, top row first.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Option<Vec<[f32; 4]>> {
+    let (width, height) = (texture.width(), texture.height());
+    let row_bytes = width * 16;
+    // Buffer rows must be aligned to COPY_BYTES_PER_ROW_ALIGNMENT
+    let padded_row_bytes =
+        row_bytes.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback_buffer"),
+        size: (padded_row_bytes * height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: Some(height),
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(Some(encoder.finish()));
+
+    map_read(device, &buffer)?;
+    let view = buffer.slice(..).get_mapped_range();
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for row in view.chunks(padded_row_bytes as usize) {
+        pixels.extend_from_slice(bytemuck::cast_slice(&row[..row_bytes as usize]));
+    }
+    Some(pixels)
+}
+
+/// Copy a buffer created with `COPY_SRC` back to the CPU.
+pub fn read_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    source: &wgpu::Buffer,
+) -> Option<Vec<u8>> {
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback_buffer"),
+        size: source.size(),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&Default::default());
+    encoder.copy_buffer_to_buffer(source, 0, &buffer, 0, source.size());
+    queue.submit(Some(encoder.finish()));
+
+    map_read(device, &buffer)?;
+    let data = buffer.slice(..).get_mapped_range().to_vec();
+    Some(data)
+}
+
+/// Map a readback buffer and wait for it, reporting why it failed.
+fn map_read(device: &wgpu::Device, buffer: &wgpu::Buffer) -> Option<()> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::PollType::wait_indefinitely()).ok()?;
+    if let Err(e) = receiver.recv().ok()? {
+        eprintln!("Failed to map the readback buffer: {e}");
+        return None;
+    }
+    Some(())
+}
+
+/// Average the accumulated XYZ by the sample count in alpha and convert it
+/// to the output colour space.
+pub fn resolve(pixels: &[[f32; 4]], xyz_to_output: cgmath::Matrix3<f32>) -> Vec<[f32; 3]> {
+    pixels
+        .iter()
+        .map(|p| {
+            let xyz = cgmath::vec3(p[0], p[1], p[2]) / p[3].max(1.0);
+            (xyz_to_output * xyz).into()
+        })
+        .collect()
+}
+
+/// Everything besides the pixels that goes into an exported image.
+pub struct ExportSettings {
+    /// Exposure and curve for PNGs; the floating point formats stay linear.
+    pub tonemap: TonemapUniform,
+    /// Grade applied to PNGs along with the curve.
+    pub lut: Option<Lut>,
+    /// Bloom glare added to PNGs before the curve, in the output space.
+    pub glare: Option<Vec<[f32; 3]>>,
+    /// Output space the pixels were resolved to.
+    pub color: ColorPipeline,
+    /// 8 or 16 bits per PNG channel.
+    pub png_bits: u8,
+    /// Key-value pairs embedded in formats that carry them.
+    pub metadata: Vec<(String, String)>,
+    /// Extra layers for EXRs; the other formats only hold the beauty pass.
+    pub aovs: Option<AovImages>,
+}
+
+/// Describe how an image was rendered.
+pub fn render_metadata(
+    camera: &Camera,
+    adopted_white: WhitePoint,
+    samples: u32,
+    seconds: f32,
+) -> Vec<(String, String)> {
+    let point = |p: [f32; 3]| format!("{} {} {}", p[0], p[1], p[2]);
+    vec![
+        ("software".into(), "wgpu-raytracer".into()),
+        ("samples".into(), samples.to_string()),
+        ("renderTime".into(), format!("{seconds:.2}s")),
+        ("cameraOrigin".into(), point(camera.origin.into())),
+        ("cameraForward".into(), point(camera.forward().into())),
+        ("projection".into(), camera.projection.name().into()),
+        ("verticalFov".into(), camera.vfov.to_string()),
+        ("focalLength".into(), format!("{:.1}mm", camera.focal_length())),
+        ("exposure".into(), format!(
+            "ISO {} {}s f/{}",
+            camera.iso, camera.shutter_time, camera.f_number
+        )),
+        ("focusDistance".into(), camera.focus_distance.to_string()),
+        ("apertureRadius".into(), camera.aperture_radius.to_string()),
+        // A named preset, unless the white balance was set by temperature
+        ("whiteBalance".into(), match adopted_white.name {
+            "custom" => format!("{}K {:+}", camera.white_balance, camera.tint),
+            name => name.to_string(),
+        }),
+    ]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Exr,
+    Pfm,
+    /// Radiance RGBE
+    Hdr,
+    Png,
+}
+
+impl ImageFormat {
+    /// Format named by the file extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "exr" => Some(Self::Exr),
+            "pfm" => Some(Self::Pfm),
+            "hdr" => Some(Self::Hdr),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Write RGB rows, top to bottom, in the format given by the file extension.
+pub fn write_image(
+    path: &str,
+    width: u32,
+    height: u32,
+    rgb: &[[f32; 3]],
+    settings: &ExportSettings,
+) -> Result<(), String> {
+    match ImageFormat::from_path(path) {
+        Some(ImageFormat::Exr) => write_exr(path, width, height, rgb, settings),
+        // PFM has no room for metadata
+        Some(ImageFormat::Pfm) => write_pfm(path, width, height, rgb),
+        Some(ImageFormat::Hdr) => write_hdr(path, width, height, rgb, settings),
+        Some(ImageFormat::Png) => write_png(path, width, height, rgb, settings),
+        None => Err(format!("unsupported image format: {path}")),
+    }
+}
+
+fn write_exr(
+    path: &str,
+    width: u32,
+    height: u32,
+    rgb: &[[f32; 3]],
+    settings: &ExportSettings,
+) -> Result<(), String> {
+    use exr::{meta::attribute::Chromaticities, prelude::*};
+
+    let size = (width as usize, height as usize);
+    let channel = |name: &str, samples: FlatSamples| AnyChannel::new(name, samples);
+    let component = |pixels: &[[f32; 3]], i: usize| {
+        FlatSamples::F32(pixels.iter().map(|p| p[i]).collect())
+    };
+    let layer = |attributes: LayerAttributes, channels: SmallVec<[AnyChannel<FlatSamples>; 4]>| {
+        Layer::new(size, attributes, Encoding::SMALL_LOSSLESS, AnyChannels::sort(channels))
+    };
+    let vector = |name: &str, pixels: &[[f32; 3]], names: [&str; 3]| {
+        layer(
+            LayerAttributes::named(name),
+            names
+                .iter()
+                .enumerate()
+                .map(|(i, &c)| channel(c, component(pixels, i)))
+                .collect(),
+        )
+    };
+
+    let mut attributes = LayerAttributes::named("beauty");
+    for (key, value) in &settings.metadata {
+        // EXR reserves a standard attribute for the software name
+        if key == "software" {
+            attributes.software_name = Some(Text::from(value.as_str()));
+        } else {
+            attributes.other.insert(
+                Text::from(key.as_str()),
+                AttributeValue::Text(Text::from(value.as_str())),
+            );
+        }
+    }
+    let mut layers = vec![layer(
+        attributes,
+        ["R", "G", "B"]
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| channel(c, component(rgb, i)))
+            .collect(),
+    )];
+    if let Some(aovs) = &settings.aovs {
+        let scalar = |name: &str, channel_name: &str, samples: FlatSamples| {
+            layer(
+                LayerAttributes::named(name),
+                SmallVec::from_elem(channel(channel_name, samples), 1),
+            )
+        };
+        layers.push(vector("albedo", &aovs.albedo, ["R", "G", "B"]));
+        layers.push(vector("normal", &aovs.normal, ["X", "Y", "Z"]));
+        layers.push(scalar("depth", "Z", FlatSamples::F32(aovs.depth.clone())));
+        layers.push(vector("position", &aovs.position, ["X", "Y", "Z"]));
+        layers.push(scalar("materialId", "id", FlatSamples::U32(aovs.material_id.clone())));
+        layers.push(scalar("objectId", "id", FlatSamples::U32(aovs.object_id.clone())));
+        layers.push(scalar("samples", "count", FlatSamples::U32(aovs.samples.clone())));
+    }
+
+    let space = settings.color.output;
+    let [red, green, blue] = space.primaries().map(|[x, y]| Vec2(x, y));
+    let white = space.white();
+    let mut image_attributes = ImageAttributes::new(IntegerBounds::from_dimensions(size));
+    image_attributes.chromaticities = Some(Chromaticities {
+        red,
+        green,
+        blue,
+        white: Vec2(white.x, white.y),
+    });
+    Image::from_layers(image_attributes, layers)
+        .write()
+        .to_file(path)
+        .map_err(|e| e.to_string())
+}
+
+/// Write a colour PFM from rows ordered top to bottom, as the film is laid out.
+pub fn write_pfm(path: &str, width: u32, height: u32, rgb: &[[f32; 3]]) -> Result<(), String> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path).map_err(|e| e.to_string())?);
+    // A negative scale marks little-endian data
+    write!(file, "PF\n{width} {height}\n-1.0\n").map_err(|e| e.to_string())?;
+    // PFM stores the bottom row first
+    for row in rgb.chunks(width as usize).rev() {
+        for channel in row.iter().flatten() {
+            file.write_all(&channel.to_le_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    file.flush().map_err(|e| e.to_string())
+}
+
+/// Shared exponent encoding of a linear colour.
+fn rgbe(rgb: [f32; 3]) -> [u8; 4] {
+    let rgb = rgb.map(|v| v.max(0.0));
+    let max = rgb[0].max(rgb[1]).max(rgb[2]);
+    if max < 1e-32 {
+        return [0; 4];
+    }
+    // max = m * 2^exponent with m in [0.5, 1)
+    let exponent = max.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f32.powi(exponent);
+    let [r, g, b] = rgb.map(|v| (v * scale).min(255.0) as u8);
+    [r, g, b, (exponent + 128) as u8]
+}
+
+fn write_hdr(
+    path: &str,
+    width: u32,
+    height: u32,
+    rgb: &[[f32; 3]],
+    settings: &ExportSettings,
+) -> Result<(), String> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path).map_err(|e| e.to_string())?);
+    let space = settings.color.output;
+    let [[rx, ry], [gx, gy], [bx, by]] = space.primaries();
+    let white = space.white();
+    let mut header = String::from("#?RADIANCE\n");
+    for (key, value) in &settings.metadata {
+        header += &format!("# {key}: {value}\n");
+    }
+    header += &format!(
+        "PRIMARIES={rx} {ry} {gx} {gy} {bx} {by} {} {}\n",
+        white.x, white.y
+    );
+    header += &format!("FORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n");
+    file.write_all(header.as_bytes()).map_err(|e| e.to_string())?;
+
+    for row in rgb.chunks(width as usize) {
+        let pixels: Vec<[u8; 4]> = row.iter().map(|&p| rgbe(p)).collect();
+        file.write_all(&hdr_scanline(&pixels)).map_err(|e| e.to_string())?;
+    }
+    file.flush().map_err(|e| e.to_string())
+}
+
+/// One row of RGBE pixels as stored in a Radiance file.
+fn hdr_scanline(pixels: &[[u8; 4]]) -> Vec<u8> {
+    let width = pixels.len();
+    // Adaptive run-length scanlines only exist for these widths
+    if !(8..0x8000).contains(&width) {
+        return pixels.as_flattened().to_vec();
+    }
+    // Each component is stored separately, here as uncompressed runs,
+    // which keeps readers from mistaking the first pixel for a marker
+    let mut scanline = vec![2, 2, (width >> 8) as u8, width as u8];
+    for component in 0..4 {
+        let values: Vec<u8> = pixels.iter().map(|p| p[component]).collect();
+        for run in values.chunks(128) {
+            scanline.push(run.len() as u8);
+            scanline.extend_from_slice(run);
+        }
+    }
+    scanline
+}
+
+/// The sRGB transfer function the viewer's surface format applies.
+pub fn srgb_encode(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn write_png(
+    path: &str,
+    width: u32,
+    height: u32,
+    rgb: &[[f32; 3]],
+    settings: &ExportSettings,
+) -> Result<(), String> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path).map_err(|e| e.to_string())?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    let sixteen = settings.png_bits == 16;
+    encoder.set_depth(if sixteen { png::BitDepth::Sixteen } else { png::BitDepth::Eight });
+
+    let space = settings.color.output;
+    if space == crate::color::ColorSpace::Srgb {
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    } else {
+        // Other primaries are encoded with the sRGB curve, as on screen
+        let [r, g, b] = space.primaries().map(|[x, y]| (x, y));
+        let white = space.white();
+        encoder.set_source_chromaticities(png::SourceChromaticities::new(
+            (white.x, white.y),
+            r,
+            g,
+            b,
+        ));
+        encoder.set_source_gamma(png::ScaledFloat::new(1.0 / 2.2));
+    }
+    for (key, value) in &settings.metadata {
+        encoder
+            .add_text_chunk(key.clone(), value.clone())
+            .map_err(|e| e.to_string())?;
+    }
+
+    let luminance = settings.color.get_uniform().luminance;
+    let luminance = [luminance[0], luminance[1], luminance[2]];
+    let mut data = Vec::with_capacity(rgb.len() * if sixteen { 6 } else { 3 });
+    for (i, &pixel) in rgb.iter().enumerate() {
+        let pixel = match &settings.glare {
+            Some(glare) => std::array::from_fn(|c| (pixel[c] + glare[i][c]).max(0.0)),
+            None => pixel,
+        };
+        let display = match &settings.lut {
+            Some(lut) => lut.grade(&settings.tonemap, pixel, luminance),
+            None => settings.tonemap.apply(pixel, luminance),
+        };
+        for v in display {
+            let v = srgb_encode(v);
+            if sixteen {
+                data.extend_from_slice(&((v * 65535.0).round() as u16).to_be_bytes());
+            } else {
+                data.push((v * 255.0).round() as u8);
+            }
+        }
+    }
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&data).map_err(|e| e.to_string())?;
+    writer.finish().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgbe_shares_the_largest_exponent() {
+        assert_eq!(rgbe([1.0, 1.0, 1.0]), [128, 128, 128, 129]);
+        assert_eq!(rgbe([0.5, 0.25, 0.0]), [128, 64, 0, 128]);
+        assert_eq!(rgbe([3.0, 1.0, 0.5]), [192, 64, 32, 130]);
+        assert_eq!(rgbe([0.0; 3]), [0; 4]);
+        // Negative components are clipped rather than wrapped
+        assert_eq!(rgbe([-1.0, 1.0, 0.0]), [0, 128, 0, 129]);
+    }
+
+    #[test]
+    fn scanlines_store_components_as_runs() {
+        let pixels: Vec<[u8; 4]> = (0..8).map(|i| [i, 10 + i, 20 + i, 128]).collect();
+        let scanline = hdr_scanline(&pixels);
+        assert_eq!(scanline[..4], [2, 2, 0, 8]);
+        assert_eq!(scanline.len(), 4 + 4 * (1 + 8));
+        assert_eq!(scanline[4..13], [8, 0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(scanline[13..22], [8, 10, 11, 12, 13, 14, 15, 16, 17]);
+        assert_eq!(scanline[31..], [8, 128, 128, 128, 128, 128, 128, 128, 128]);
+    }
+
+    #[test]
+    fn long_scanlines_split_into_runs_of_128() {
+        let pixels = vec![[1, 2, 3, 4]; 200];
+        let scanline = hdr_scanline(&pixels);
+        assert_eq!(scanline[..4], [2, 2, 0, 200]);
+        assert_eq!(scanline.len(), 4 + 4 * (2 + 200));
+        assert_eq!(scanline[4], 128);
+        assert_eq!(scanline[4 + 1 + 128], 72);
+    }
+
+    #[test]
+    fn narrow_scanlines_are_flat() {
+        let pixels = [[1, 2, 3, 4], [5, 6, 7, 8]];
+        assert_eq!(hdr_scanline(&pixels), [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+}
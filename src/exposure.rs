@@ -0,0 +1,336 @@
+//! Auto exposure from a luminance histogram of the displayed image. See
+//! exposure.wgsl.
+
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+// Matches BINS in exposure.wgsl
+const HISTOGRAM_BINS: u64 = 64;
+// Range of log2 luminance the histogram covers
+const MIN_LOG_LUMINANCE: f32 = -16.0;
+const LOG_LUMINANCE_RANGE: f32 = 32.0;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct AutoExposureSettings {
+    pub enabled: bool,
+    /// Rate of adaptation, per second; higher reacts faster.
+    pub speed: f32,
+    /// Stops added to the metered exposure.
+    pub compensation: f32,
+    /// Cumulative bounds of the metered pixels, as fractions of all of them
+    /// ordered by luminance: the pixels below `low_percentile` and above
+    /// `high_percentile` are left out. 0 <= low < high <= 1.
+    pub low_percentile: f32,
+    pub high_percentile: f32,
+}
+
+impl Default for AutoExposureSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            speed: 2.0,
+            compensation: 0.0,
+            low_percentile: 0.5,
+            high_percentile: 0.95,
+        }
+    }
+}
+
+impl AutoExposureSettings {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let settings: Self = ron::from_str(&text).map_err(|e| e.to_string())?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Check that the percentiles bound a non-empty range of pixels.
+    pub fn validate(&self) -> Result<(), String> {
+        let (low, high) = (self.low_percentile, self.high_percentile);
+        if !(0.0 <= low && low < high && high <= 1.0) {
+            return Err(format!(
+                "percentiles {low} and {high} must satisfy 0 <= low < high <= 1"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureParams {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    low_percentile: f32,
+    high_percentile: f32,
+    compensation: f32,
+    adaptation: f32,
+    _pad0: f32,
+    _pad1: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureState {
+    key: f32,
+    log_luminance: f32,
+    _pad0: f32,
+    _pad1: f32,
+}
+
+pub struct AutoExposure {
+    pub settings: AutoExposureSettings,
+    histogram_pipeline: wgpu::ComputePipeline,
+    adapt_pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    params_buffer: wgpu::Buffer,
+    histogram_buffer: wgpu::Buffer,
+    /// Key in use, read by the blit in place of the camera's.
+    pub state_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    source_size: wgpu::Extent3d,
+    /// Jump straight to the metered key on the next run.
+    snap: bool,
+}
+
+impl AutoExposure {
+    pub fn new(
+        device: &wgpu::Device,
+        source_view: &wgpu::TextureView,
+        source_size: wgpu::Extent3d,
+        settings: AutoExposureSettings,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Exposure Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("kernels/exposure.wgsl").into()),
+        });
+
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("exposure_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                storage_entry(2),
+                storage_entry(3),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("exposure_pipeline_layout"),
+            bind_group_layouts: &[Some(&bind_group_layout)],
+            immediate_size: 0,
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("exposure_params_buffer"),
+            size: std::mem::size_of::<ExposureParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("exposure_histogram_buffer"),
+            size: HISTOGRAM_BINS * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let state_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("exposure_state_buffer"),
+            contents: bytemuck::bytes_of(&ExposureState {
+                key: 1.0,
+                log_luminance: 0.0,
+                _pad0: 0.0,
+                _pad1: 0.0,
+            }),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        Self {
+            settings,
+            histogram_pipeline: pipeline("cs_histogram"),
+            adapt_pipeline: pipeline("cs_adapt"),
+            bind_group: create_bind_group(
+                device,
+                &bind_group_layout,
+                [&params_buffer, &histogram_buffer, &state_buffer],
+                source_view,
+            ),
+            bind_group_layout,
+            params_buffer,
+            histogram_buffer,
+            state_buffer,
+            source_size,
+            snap: true,
+        }
+    }
+
+    /// Meter the source of `source_view` from now on, which holds XYZ sums
+    /// with the sample count in alpha.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        source_view: &wgpu::TextureView,
+        source_size: wgpu::Extent3d,
+    ) {
+        self.source_size = source_size;
+        self.bind_group = create_bind_group(
+            device,
+            &self.bind_group_layout,
+            [
+                &self.params_buffer,
+                &self.histogram_buffer,
+                &self.state_buffer,
+            ],
+            source_view,
+        );
+    }
+
+    /// Skip the adaptation on the next run, for a fresh start.
+    pub fn reset(&mut self) {
+        self.snap = true;
+    }
+
+    /// Meter the source and adapt the key to it over `seconds` since the last run.
+    pub fn run(&mut self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, seconds: f32) {
+        let adaptation = if self.snap {
+            1.0
+        } else {
+            1.0 - (-seconds.max(0.0) * self.settings.speed).exp()
+        };
+        self.snap = false;
+        let params = ExposureParams {
+            min_log_luminance: MIN_LOG_LUMINANCE,
+            log_luminance_range: LOG_LUMINANCE_RANGE,
+            low_percentile: self.settings.low_percentile,
+            high_percentile: self.settings.high_percentile,
+            compensation: self.settings.compensation,
+            adaptation,
+            _pad0: 0.0,
+            _pad1: 0.0,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&params));
+
+        let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+        compute_pass.set_bind_group(0, &self.bind_group, &[]);
+        compute_pass.set_pipeline(&self.histogram_pipeline);
+        compute_pass.dispatch_workgroups(
+            self.source_size.width.div_ceil(16),
+            self.source_size.height.div_ceil(16),
+            1,
+        );
+        compute_pass.set_pipeline(&self.adapt_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// Read back the key in use, for images tonemapped on the CPU.
+    pub fn key(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Option<f32> {
+        let data = crate::export::read_buffer(device, queue, &self.state_buffer)?;
+        Some(bytemuck::pod_read_unaligned::<ExposureState>(&data).key)
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    [params, histogram, state]: [&wgpu::Buffer; 3],
+    source_view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("exposure_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(source_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: histogram.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: state.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn percentiles(low_percentile: f32, high_percentile: f32) -> AutoExposureSettings {
+        AutoExposureSettings {
+            low_percentile,
+            high_percentile,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accepts_ordered_percentiles() {
+        assert!(AutoExposureSettings::default().validate().is_ok());
+        assert!(percentiles(0.0, 1.0).validate().is_ok());
+        assert!(percentiles(0.1, 0.2).validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_reversed_empty_and_out_of_range_percentiles() {
+        assert!(percentiles(0.95, 0.5).validate().is_err());
+        assert!(percentiles(0.5, 0.5).validate().is_err());
+        assert!(percentiles(-0.1, 0.5).validate().is_err());
+        assert!(percentiles(0.5, 1.5).validate().is_err());
+        assert!(percentiles(f32::NAN, 0.5).validate().is_err());
+    }
+
+    #[test]
+    fn parsed_settings_fill_in_defaults() {
+        let settings: AutoExposureSettings =
+            ron::from_str("(enabled: true, high_percentile: 0.9)").unwrap();
+        assert!(settings.enabled);
+        assert_eq!(settings.low_percentile, 0.5);
+        assert!(settings.validate().is_ok());
+    }
+}
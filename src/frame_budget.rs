@@ -0,0 +1,85 @@
+use serde::Deserialize;
+
+// Weight of each new frame time in the running estimate
+const SMOOTHING: f32 = 0.2;
+
+/// How much work the viewer puts into each frame, tuned from measured frame
+/// times: coarser pixels while the camera moves, more passes while it rests.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FrameBudget {
+    pub enabled: bool,
+    /// Frame time to aim for, in milliseconds.
+    pub target_ms: f32,
+    /// Largest side, in pixels, of the blocks traced as one while moving.
+    pub max_stride: u32,
+    /// Most compute passes per frame while the camera rests.
+    pub max_passes: u32,
+    #[serde(skip)]
+    stride: u32,
+    #[serde(skip)]
+    passes: u32,
+    /// Smoothed time of one full resolution pass, in seconds.
+    #[serde(skip)]
+    pass_seconds: f32,
+}
+
+impl Default for FrameBudget {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            target_ms: 33.0,
+            max_stride: 8,
+            max_passes: 64,
+            stride: 1,
+            passes: 1,
+            pass_seconds: 0.0,
+        }
+    }
+}
+
+impl FrameBudget {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /// Pixel stride to trace at while the camera moves.
+    pub fn stride(&self) -> u32 {
+        if self.enabled {
+            self.stride
+        } else {
+            1
+        }
+    }
+
+    /// Compute passes to run in a frame while the camera rests.
+    pub fn passes(&self) -> u32 {
+        if self.enabled {
+            self.passes
+        } else {
+            1
+        }
+    }
+
+    /// Adjust to the time the last frame took, which ran `passes` compute
+    /// passes at `stride`.
+    pub fn update(&mut self, frame_seconds: f32, stride: u32, passes: u32) {
+        if !self.enabled || frame_seconds <= 0.0 {
+            return;
+        }
+        // Work is roughly proportional to the traced pixels times the passes,
+        // so one estimate of a full resolution pass serves both settings
+        let pass_seconds = frame_seconds * (stride * stride) as f32 / passes.max(1) as f32;
+        self.pass_seconds = if self.pass_seconds > 0.0 {
+            self.pass_seconds + SMOOTHING * (pass_seconds - self.pass_seconds)
+        } else {
+            pass_seconds
+        };
+        let target = self.target_ms * 1e-3;
+        let stride = (self.pass_seconds / target).sqrt().ceil() as u32;
+        self.stride = stride.clamp(1, self.max_stride.max(1));
+        let passes = (target / self.pass_seconds).floor() as u32;
+        self.passes = passes.clamp(1, self.max_passes.max(1));
+    }
+}
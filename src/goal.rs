@@ -0,0 +1,124 @@
+use serde::Deserialize;
+
+// Luminance below which noise is judged in absolute rather than relative terms
+const NOISE_FLOOR: f32 = 1e-2;
+/// Per-pixel relative error adaptive sampling stops at unless the goal sets one.
+pub const DEFAULT_ADAPTIVE_THRESHOLD: f32 = 0.02;
+
+/// When a progressive render is done, and what to write once it is.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RenderGoal {
+    /// Stop at this many samples per pixel.
+    pub samples: Option<u32>,
+    /// Stop after this many seconds of accumulation.
+    pub time_limit: Option<f32>,
+    /// Stop once the mean relative standard error drops below this.
+    pub noise_threshold: Option<f32>,
+    /// Stop tracing pixels whose neighbourhood is below this relative error.
+    pub adaptive_threshold: Option<f32>,
+    /// Seconds between noise estimates and counts of the pixels adaptive
+    /// sampling still traces, both of which read back from the GPU.
+    pub noise_check_interval: f32,
+    /// Images written when the goal is reached, with `{scene}`, `{spp}` and
+    /// `{stamp}` (Unix seconds) substituted. The extension picks the format.
+    pub outputs: Vec<String>,
+}
+
+impl Default for RenderGoal {
+    fn default() -> Self {
+        Self {
+            samples: None,
+            time_limit: None,
+            noise_threshold: None,
+            adaptive_threshold: None,
+            noise_check_interval: 2.0,
+            outputs: vec![
+                "renders/{scene}_{spp}spp.exr".to_string(),
+                "renders/{scene}_{spp}spp.png".to_string(),
+            ],
+        }
+    }
+}
+
+impl RenderGoal {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /// Whether any stopping criterion is set; without one the render runs forever.
+    pub fn is_set(&self) -> bool {
+        self.samples.is_some()
+            || self.time_limit.is_some()
+            || self.noise_threshold.is_some()
+            || self.adaptive_threshold.is_some()
+    }
+
+    /// The criterion that has been met, if any. `noise` is the latest
+    /// estimate and `active_pixels` the latest count of pixels still traced.
+    pub fn reached(
+        &self,
+        samples: u32,
+        seconds: f32,
+        noise: Option<f32>,
+        active_pixels: Option<u32>,
+    ) -> Option<String> {
+        if active_pixels == Some(0) {
+            return Some("every pixel converged".to_string());
+        }
+        if self.samples.is_some_and(|target| samples >= target) {
+            return Some(format!("{samples} spp"));
+        }
+        if self.time_limit.is_some_and(|limit| seconds >= limit) {
+            return Some(format!("{seconds:.1}s"));
+        }
+        match (self.noise_threshold, noise) {
+            (Some(threshold), Some(noise)) if noise <= threshold => {
+                Some(format!("noise {:.2}%", noise * 100.0))
+            }
+            _ => None,
+        }
+    }
+
+    /// Output files for a render of `scene_path` with `samples` per pixel.
+    pub fn output_paths(&self, scene_path: &str, samples: u32) -> Vec<String> {
+        let scene = std::path::Path::new(scene_path)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("render");
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.outputs
+            .iter()
+            .map(|pattern| {
+                pattern
+                    .replace("{scene}", scene)
+                    .replace("{spp}", &samples.to_string())
+                    .replace("{stamp}", &stamp.to_string())
+            })
+            .collect()
+    }
+}
+
+/// Mean relative standard error of the pixel means, from per-pixel sums of
+/// luminance, squared luminance and sample count.
+pub fn relative_error(moments: &[[f32; 4]]) -> f32 {
+    let mut total = 0.0;
+    let mut pixels = 0;
+    for &[sum, sum_sq, n, _] in moments {
+        if n < 2.0 {
+            continue;
+        }
+        let mean = sum / n;
+        let variance = (sum_sq / n - mean * mean).max(0.0) * n / (n - 1.0);
+        total += (variance / n).sqrt() / (mean.abs() + NOISE_FLOOR);
+        pixels += 1;
+    }
+    if pixels == 0 {
+        f32::INFINITY
+    } else {
+        total / pixels as f32
+    }
+}
@@ -0,0 +1,121 @@
+//! HDR display output: the surface formats it needs and the mapping of
+//! exposed scene light to display nits. See `display_encode` in blit.wgsl.
+//!
+//! HDR output shows the exposed scene light as it is, so the tonemapper and
+//! the saturation control only apply to SDR output and to display LUTs.
+
+use serde::Deserialize;
+
+use crate::color::{bradford, ColorSpace};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum HdrMode {
+    /// Tonemapped to [0, 1] on an sRGB surface.
+    #[default]
+    Sdr,
+    /// Linear Rec.709 primaries on an `Rgba16Float` surface, 1 being 80
+    /// nits, as Windows' scRGB and macOS' extended dynamic range take it.
+    ExtendedLinear,
+    /// Rec.2020 primaries through the SMPTE ST 2084 curve on an
+    /// `Rgb10a2Unorm` surface. wgpu cannot tag the swapchain as HDR10 yet,
+    /// and an untagged surface is shown as SDR, so the viewer falls back to
+    /// extended linear output until it can.
+    Pq,
+}
+
+impl HdrMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sdr => "sdr",
+            Self::ExtendedLinear => "extended-linear",
+            Self::Pq => "pq",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Sdr, Self::ExtendedLinear, Self::Pq]
+            .into_iter()
+            .find(|mode| mode.name() == name)
+    }
+
+    /// Whether the surface can be told to take this output. See `Pq`.
+    pub fn is_taggable(self) -> bool {
+        self != Self::Pq
+    }
+
+    /// Format the surface must offer for this output, or `None` for the
+    /// usual sRGB one.
+    pub fn surface_format(self) -> Option<wgpu::TextureFormat> {
+        match self {
+            Self::Sdr => None,
+            Self::ExtendedLinear => Some(wgpu::TextureFormat::Rgba16Float),
+            Self::Pq => Some(wgpu::TextureFormat::Rgb10a2Unorm),
+        }
+    }
+
+    /// Primaries of the signal the surface takes.
+    fn primaries(self) -> ColorSpace {
+        match self {
+            Self::Pq => ColorSpace::Rec2020,
+            _ => ColorSpace::Srgb,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct HdrSettings {
+    pub mode: HdrMode,
+    /// Nits of diffuse white, where an exposed scene value of 1 lands.
+    pub paper_white: f32,
+    /// Brightest the display shows, in nits; highlights roll off towards it
+    /// from half of it.
+    pub peak: f32,
+}
+
+impl Default for HdrSettings {
+    fn default() -> Self {
+        Self {
+            mode: HdrMode::Sdr,
+            // Reference diffuse white of ITU-R BT.2408
+            paper_white: 203.0,
+            peak: 1000.0,
+        }
+    }
+}
+
+impl HdrSettings {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        ron::from_str(&text).map_err(|e| e.to_string())
+    }
+
+    /// The settings for the blit, which works in the `output` space.
+    pub fn uniform(&self, output: ColorSpace) -> HdrUniform {
+        let display = self.mode.primaries();
+        let m =
+            display.xyz_to_rgb() * bradford(output.white(), display.white()) * output.rgb_to_xyz();
+        HdrUniform {
+            mode: self.mode as u32,
+            paper_white: self.paper_white,
+            peak: self.peak,
+            _pad: 0,
+            output_to_display: [
+                [m.x.x, m.x.y, m.x.z, 0.0],
+                [m.y.x, m.y.y, m.y.z, 0.0],
+                [m.z.x, m.z.y, m.z.z, 0.0],
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct HdrUniform {
+    pub mode: u32,
+    pub paper_white: f32,
+    pub peak: f32,
+    _pad: u32,
+    /// Column-major, each column padded to a vec4 to match WGSL `mat3x3` layout.
+    pub output_to_display: [[f32; 4]; 3],
+}
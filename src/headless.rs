@@ -0,0 +1,505 @@
+//! Offline rendering without a window, for render servers and CI.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    aov::AovImages,
+    args::{Args, CommonArgs},
+    bloom::{BlitBuffers, Bloom, BloomSettings},
+    bookmarks::Bookmarks,
+    camera::{ApertureShape, Camera},
+    checkpoint::Checkpoint,
+    color::ColorPipeline,
+    denoise::{DenoiseSettings, Denoiser},
+    export,
+    exposure::{AutoExposure, AutoExposureSettings},
+    goal::RenderGoal,
+    lens::LensSystem,
+    lut::Lut,
+    mega_kernel::ComputePass,
+    request_device,
+    tonemap::{TonemapUniform, Tonemapper, DEFAULT_WHITE},
+    Scene, CHECKPOINT_INTERVAL,
+};
+
+pub const USAGE: &str = "\
+usage: wgpu-raytracer render [options]
+    --scene <path>      OBJ mesh to build the scene around (default
+                        res/glass.obj); scene description files are not
+                        supported
+    --bookmark <slot>   view from the scene's camera bookmarks
+    --spp <n>           samples per pixel (default 256)
+    --time-limit <s>    stop after this many seconds
+    --noise <fraction>  stop once the mean relative error drops below this
+    --adaptive <fraction>
+                        stop tracing pixels below this relative error
+    --width <px>        image width (default 1280)
+    --height <px>       image height (default 720)
+    --out <path>        output image: .exr, .pfm, .hdr or .png (default render.exr)
+    --png-bits <8|16>   bits per PNG channel (default 8)
+    --aovs              add albedo, normal, depth, position, id and sample
+                        count layers to an EXR
+    --denoise           filter the result with the AOV-guided denoiser
+    --tonemap <name>    PNG tonemapper: reinhard, reinhard-extended, aces,
+                        agx, hable or linear (default reinhard)
+    --white <value>     white point of reinhard-extended (default 4)
+    --auto-exposure     expose PNGs from the luminance histogram instead of
+                        the camera settings
+    --exposure-compensation <stops>
+                        added to the auto exposure (default 0)
+    --lut <path>        grade PNGs through a .cube 3D LUT
+    --lut-log           feed the LUT log encoded scene values instead of
+                        the tonemapped image, for HDR LUTs
+    --bloom <strength>  add this fraction of the bright light to PNGs as
+                        glare
+    --bloom-threshold <value>
+                        exposed value above which light glares (default 0)
+    --checkpoint <path> resume from and periodically save to a checkpoint
+    --fallback          use the software fallback adapter";
+
+pub struct RenderArgs {
+    pub common: CommonArgs,
+    pub bookmark: Option<u8>,
+    pub spp: u32,
+    pub time_limit: Option<f32>,
+    pub noise: Option<f32>,
+    pub adaptive: Option<f32>,
+    pub width: u32,
+    pub height: u32,
+    pub out: String,
+    pub png_bits: u8,
+    pub aovs: bool,
+    pub denoise: bool,
+    pub tonemapper: Tonemapper,
+    pub white: f32,
+    pub auto_exposure: bool,
+    pub exposure_compensation: f32,
+    pub bloom: Option<f32>,
+    pub bloom_threshold: f32,
+    pub checkpoint: Option<String>,
+    pub fallback: bool,
+}
+
+impl Default for RenderArgs {
+    fn default() -> Self {
+        Self {
+            common: CommonArgs::default(),
+            bookmark: None,
+            spp: 256,
+            time_limit: None,
+            noise: None,
+            adaptive: None,
+            width: 1280,
+            height: 720,
+            out: "render.exr".to_string(),
+            png_bits: 8,
+            aovs: false,
+            denoise: false,
+            tonemapper: Tonemapper::default(),
+            white: DEFAULT_WHITE,
+            auto_exposure: false,
+            exposure_compensation: 0.0,
+            bloom: None,
+            bloom_threshold: BloomSettings::default().threshold,
+            checkpoint: None,
+            fallback: false,
+        }
+    }
+}
+
+impl RenderArgs {
+    /// Parse the options following the `render` subcommand.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            if parsed.common.parse_flag(&flag, &mut args)? {
+                continue;
+            }
+            match flag.as_str() {
+                "--fallback" => parsed.fallback = true,
+                "--aovs" => parsed.aovs = true,
+                "--denoise" => parsed.denoise = true,
+                "--auto-exposure" => parsed.auto_exposure = true,
+                "--bookmark" => parsed.bookmark = Some(args.number(&flag)?),
+                "--spp" => parsed.spp = args.number(&flag)?,
+                "--time-limit" => parsed.time_limit = Some(args.number(&flag)?),
+                "--noise" => parsed.noise = Some(args.number(&flag)?),
+                "--adaptive" => parsed.adaptive = Some(args.number(&flag)?),
+                "--width" => parsed.width = args.number(&flag)?,
+                "--height" => parsed.height = args.number(&flag)?,
+                "--out" => parsed.out = args.value(&flag)?,
+                "--png-bits" => parsed.png_bits = args.number(&flag)?,
+                "--checkpoint" => parsed.checkpoint = Some(args.value(&flag)?),
+                "--tonemap" => {
+                    let name = args.value(&flag)?;
+                    parsed.tonemapper = Tonemapper::from_name(&name)
+                        .ok_or_else(|| format!("unknown tonemapper: {name}"))?
+                }
+                "--white" => parsed.white = args.number(&flag)?,
+                "--exposure-compensation" => parsed.exposure_compensation = args.number(&flag)?,
+                "--bloom" => parsed.bloom = Some(args.number(&flag)?),
+                "--bloom-threshold" => parsed.bloom_threshold = args.number(&flag)?,
+                _ => return Err(format!("unknown option {flag}")),
+            }
+        }
+        if parsed.width == 0 || parsed.height == 0 || parsed.spp == 0 {
+            return Err("width, height and spp must be positive".to_string());
+        }
+        if parsed.png_bits != 8 && parsed.png_bits != 16 {
+            return Err("png bits must be 8 or 16".to_string());
+        }
+        if export::ImageFormat::from_path(&parsed.out).is_none() {
+            return Err(format!("unsupported image format: {}", parsed.out));
+        }
+        Ok(parsed)
+    }
+}
+
+/// Render the scene at a fixed sample count and write the result.
+pub async fn render(args: &RenderArgs) -> Result<(), String> {
+    if !std::path::Path::new(&args.common.scene).exists() {
+        return Err(format!("scene not found: {}", args.common.scene));
+    }
+    // Read the LUT up front so a bad file fails before the render
+    let lut = args
+        .common
+        .lut
+        .as_ref()
+        .map(|path| {
+            Lut::load(Path::new(path))
+                .map(|lut| Lut {
+                    log_shaper: args.common.lut_log,
+                    ..lut
+                })
+                .map_err(|e| format!("failed to load {path}: {e}"))
+        })
+        .transpose()?;
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+        backends: wgpu::Backends::all(),
+        ..wgpu::InstanceDescriptor::new_without_display_handle()
+    });
+    let mut options = wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::HighPerformance,
+        force_fallback_adapter: args.fallback,
+        compatible_surface: None,
+    };
+    let adapter = match instance.request_adapter(&options).await {
+        Ok(adapter) => adapter,
+        // Machines without a GPU may still have a software adapter
+        Err(_) if !args.fallback => {
+            options.force_fallback_adapter = true;
+            instance
+                .request_adapter(&options)
+                .await
+                .map_err(|e| format!("no adapter available: {e}"))?
+        }
+        Err(e) => return Err(format!("no fallback adapter available: {e}")),
+    };
+    let info = adapter.get_info();
+    println!("Adapter: {} ({:?})", info.name, info.backend);
+    let (device, queue) = request_device(&adapter)
+        .await
+        .map_err(|e| format!("failed to open device: {e}"))?;
+
+    let size = winit::dpi::PhysicalSize::new(args.width, args.height);
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("compute_texture"),
+        size: wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&Default::default());
+
+    // The optional lens and aperture mask of the viewer, which bookmarks and
+    // checkpoints may refer to
+    let custom_aperture = if Path::new("res/aperture.pgm").exists() {
+        ApertureShape::from_pgm("res/aperture.pgm")
+            .map_err(|e| eprintln!("Failed to load res/aperture.pgm: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    let lens_system = if Path::new("res/lens.dat").exists() {
+        LensSystem::load("res/lens.dat")
+            .map_err(|e| eprintln!("Failed to load res/lens.dat: {e}"))
+            .ok()
+    } else {
+        None
+    };
+
+    let mut camera = Camera::new(
+        (0.0, 0.0, 0.0).into(),
+        (0.0, 0.0, 1.0).into(),
+        cgmath::Vector3::unit_y(),
+        75.0,
+        size.width as f32 / size.height as f32,
+    );
+    if let Some(slot) = args.bookmark {
+        let bookmarks = Bookmarks::load(&args.common.scene);
+        let bookmark = bookmarks.get(slot).ok_or_else(|| {
+            format!(
+                "bookmark {slot} not found in {}",
+                bookmarks.path().display()
+            )
+        })?;
+        bookmark.apply(&mut camera, lens_system.as_ref(), custom_aperture.as_ref());
+    }
+    let mut color_pipeline = ColorPipeline {
+        adopted_white: camera.adopted_white(),
+        ..Default::default()
+    };
+
+    let scene = Scene::new(&device, &size, &args.common.scene).await;
+    let mut compute_pass = ComputePass::new(
+        &device,
+        &size,
+        &view,
+        &camera,
+        &scene,
+        color_pipeline.observer,
+    );
+
+    let checkpoint_path = args.checkpoint.as_deref().map(Path::new);
+    if let Some(path) = checkpoint_path.filter(|path| path.exists()) {
+        let checkpoint = Checkpoint::load(path)
+            .map_err(|e| format!("failed to load {}: {e}", path.display()))?;
+        let header = &checkpoint.header;
+        if (header.width, header.height) != (size.width, size.height) {
+            return Err(format!(
+                "{} is {}x{}, not {}x{}",
+                path.display(),
+                header.width,
+                header.height,
+                size.width,
+                size.height
+            ));
+        }
+        header
+            .check(&color_pipeline)
+            .map_err(|e| format!("cannot resume {}: {e}", path.display()))?;
+        checkpoint.restore(
+            &device,
+            &queue,
+            &mut compute_pass,
+            &texture,
+            &scene.vispoint_buffer,
+            &mut camera,
+        );
+        color_pipeline.adopted_white = camera.adopted_white();
+        println!("Resuming {} at {} spp", path.display(), header.iteration);
+    }
+    let save_checkpoint = |compute_pass: &ComputePass| {
+        let Some(path) = checkpoint_path else {
+            return;
+        };
+        let saved = Checkpoint::capture(
+            &device,
+            &queue,
+            compute_pass,
+            &texture,
+            &scene.vispoint_buffer,
+            &camera,
+            &color_pipeline,
+        )
+        .ok_or_else(|| "failed to read back the render".to_string())
+        .and_then(|checkpoint| checkpoint.save(path));
+        if let Err(e) = saved {
+            eprintln!("Failed to save {}: {e}", path.display());
+        }
+    };
+
+    let goal = RenderGoal {
+        samples: Some(args.spp),
+        time_limit: args.time_limit,
+        noise_threshold: args.noise,
+        adaptive_threshold: args.adaptive,
+        ..Default::default()
+    };
+    compute_pass.set_adaptive(goal.adaptive_threshold);
+    let start = Instant::now();
+    let mut last_checkpoint = start;
+    let mut last_noise_check = start;
+    compute_pass.accumulate(&device, &queue, &texture, &size, &scene, args.spp, |pass| {
+        // Waiting here keeps the progress honest rather than queueing every pass
+        device.poll(wgpu::PollType::wait_indefinitely()).ok();
+        let seconds = start.elapsed().as_secs_f32();
+        let mut noise = None;
+        let mut active_pixels = None;
+        if last_noise_check.elapsed().as_secs_f32() >= goal.noise_check_interval {
+            if goal.noise_threshold.is_some() {
+                noise = pass.noise_estimate(&device, &queue);
+            }
+            active_pixels = pass.active_pixels(&device, &queue);
+            last_noise_check = Instant::now();
+        }
+        let mut line = format!("{}/{} spp ({seconds:.1}s", pass.iteration, args.spp);
+        if let Some(noise) = noise {
+            line += &format!(", noise {:.2}%", noise * 100.0);
+        }
+        if let Some(active) = active_pixels {
+            let fraction = active as f32 / (size.width * size.height) as f32;
+            line += &format!(", {:.1}% of pixels active", fraction * 100.0);
+        }
+        println!("{line})");
+        if last_checkpoint.elapsed() >= Duration::from_secs(CHECKPOINT_INTERVAL) {
+            save_checkpoint(pass);
+            last_checkpoint = Instant::now();
+        }
+        match goal.reached(pass.iteration, seconds, noise, active_pixels) {
+            Some(reason) if pass.iteration < args.spp => {
+                println!("Stopping at {reason}");
+                false
+            }
+            _ => true,
+        }
+    });
+    save_checkpoint(&compute_pass);
+    let samples = compute_pass.iteration;
+
+    let seconds = start.elapsed().as_secs_f32();
+    let mut metadata =
+        export::render_metadata(&camera, color_pipeline.adopted_white, samples, seconds);
+    let denoiser = args.denoise.then(|| {
+        let settings = DenoiseSettings::default();
+        let mut denoiser = Denoiser::new(
+            &device,
+            &view,
+            &compute_pass.moments_texture,
+            &compute_pass.aov_textures,
+            &settings,
+        );
+        denoiser.update(&queue, &settings);
+        let mut encoder = device.create_command_encoder(&Default::default());
+        denoiser.run(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+        metadata.push(settings.metadata());
+        denoiser
+    });
+    let source = denoiser
+        .as_ref()
+        .map_or(&texture, |denoiser| &denoiser.output);
+    let pixels = export::read_texture(&device, &queue, source)
+        .ok_or_else(|| "failed to read back the render".to_string())?;
+    let rgb = export::resolve(&pixels, color_pipeline.xyz_to_output());
+
+    let mut tonemap = TonemapUniform::new(args.tonemapper, camera.exposure(), 1.0, args.white);
+    if args.auto_exposure {
+        let settings = AutoExposureSettings {
+            enabled: true,
+            compensation: args.exposure_compensation,
+            ..Default::default()
+        };
+        let mut exposure = AutoExposure::new(
+            &device,
+            &source.create_view(&Default::default()),
+            source.size(),
+            settings,
+        );
+        let mut encoder = device.create_command_encoder(&Default::default());
+        exposure.run(&queue, &mut encoder, 0.0);
+        queue.submit(Some(encoder.finish()));
+        tonemap.key = exposure
+            .key(&device, &queue)
+            .ok_or_else(|| "failed to read back the exposure".to_string())?;
+        metadata.push((
+            "exposure".into(),
+            format!("auto {:+} EV", args.exposure_compensation),
+        ));
+    }
+    metadata.push(("tonemapper".into(), args.tonemapper.name().into()));
+    if let Some(path) = &args.common.lut {
+        metadata.push(("lut".into(), path.clone()));
+    }
+    let glare = match args.bloom {
+        Some(strength) => {
+            let settings = BloomSettings {
+                enabled: true,
+                strength,
+                threshold: args.bloom_threshold,
+                ..Default::default()
+            };
+            let buffer = |label, contents: &[u8], usage| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(label),
+                    contents,
+                    usage,
+                })
+            };
+            let color = color_pipeline.get_uniform();
+            let bloom = Bloom::new(
+                &device,
+                &source.create_view(&Default::default()),
+                source.size(),
+                BlitBuffers {
+                    tonemap: buffer(
+                        "tonemap_buffer",
+                        bytemuck::bytes_of(&tonemap),
+                        wgpu::BufferUsages::UNIFORM,
+                    ),
+                    // Any auto exposure key is already in the tonemap uniform
+                    exposure: buffer(
+                        "exposure_state_buffer",
+                        &[0; 16],
+                        wgpu::BufferUsages::STORAGE,
+                    ),
+                    color: buffer(
+                        "color_buffer",
+                        bytemuck::bytes_of(&color),
+                        wgpu::BufferUsages::UNIFORM,
+                    ),
+                },
+                settings,
+            );
+            let mut encoder = device.create_command_encoder(&Default::default());
+            bloom.run(&queue, &mut encoder);
+            queue.submit(Some(encoder.finish()));
+            let pixels = export::read_texture(&device, &queue, bloom.glare())
+                .ok_or_else(|| "failed to read back the bloom glare".to_string())?;
+            metadata.push((
+                "bloom".into(),
+                format!("strength {strength} threshold {}", args.bloom_threshold),
+            ));
+            Some(pixels.iter().map(|p| [p[0], p[1], p[2]]).collect())
+        }
+        None => None,
+    };
+    let settings = export::ExportSettings {
+        tonemap,
+        lut,
+        glare,
+        color: color_pipeline,
+        png_bits: args.png_bits,
+        metadata,
+        aovs: args
+            .aovs
+            .then(|| {
+                AovImages::read(
+                    &device,
+                    &queue,
+                    &compute_pass.aov_textures,
+                    &compute_pass.moments_texture,
+                )
+            })
+            .flatten(),
+    };
+    export::write_image(&args.out, size.width, size.height, &rgb, &settings)?;
+    println!("Wrote {} ({samples} spp in {seconds:.1}s)", args.out);
+    Ok(())
+}
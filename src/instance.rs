@@ -1,5 +1,7 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
 use bytemuck::Zeroable;
-use cgmath::{Rotation3, Point3, Vector3, Vector4, Matrix4, ElementWise, Deg};
+use cgmath::{InnerSpace, Rotation, Rotation3, Point3, Quaternion, Vector3, Vector4, Matrix4, ElementWise, Deg};
 use tobj::{self, LoadOptions};
 
 #[repr(C)]
@@ -7,7 +9,8 @@ use tobj::{self, LoadOptions};
 pub struct Sphere {
     material_id: u32,
     scale: f32,
-    _padding: [f32; 2],
+    motion_first: u32,
+    motion_count: u32,
     transform_matrix: [[f32; 4]; 4],
 }
 
@@ -26,10 +29,143 @@ impl Sphere {
         Self {
             material_id,
             scale,
-            _padding: [0.0, 0.0],
+            motion_first: 0,
+            motion_count: 0,
             transform_matrix: transform_matrix.into(),
         }
     }
+
+    /// Move the sphere by `count` keys of the shared motion buffer starting at `first`.
+    pub fn set_motion(&mut self, first: u32, count: u32) {
+        self.motion_first = first;
+        self.motion_count = count;
+    }
+}
+
+/// Rigid offset of an instance from its rest pose at a point in time. The
+/// rotation is about the instance's pivot.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MotionKey {
+    /// xyz translation, w the key's time in seconds
+    translation: [f32; 4],
+    /// Quaternion (x, y, z, w)
+    rotation: [f32; 4],
+}
+
+impl MotionKey {
+    pub fn new(time: f32, translation: Vector3<f32>, rotation: Quaternion<f32>) -> Self {
+        let rotation = rotation.normalize();
+        Self {
+            translation: [translation.x, translation.y, translation.z, time],
+            rotation: [rotation.v.x, rotation.v.y, rotation.v.z, rotation.s],
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.translation[3]
+    }
+
+    fn offset(&self) -> (Vector3<f32>, Quaternion<f32>) {
+        let [x, y, z, _] = self.translation;
+        let [qx, qy, qz, qw] = self.rotation;
+        (Vector3::new(x, y, z), Quaternion::new(qw, qx, qy, qz))
+    }
+
+    /// Offset at `time` between sorted keys, matching `sample_motion` in the
+    /// kernel: the translation is interpolated linearly and the rotation by
+    /// slerp along the shorter arc.
+    pub fn sample(keys: &[MotionKey], time: f32) -> (Vector3<f32>, Quaternion<f32>) {
+        if keys.is_empty() {
+            return (Vector3::new(0.0, 0.0, 0.0), Quaternion::new(1.0, 0.0, 0.0, 0.0));
+        }
+        let i = keys[1..].iter().take_while(|k| k.time() <= time).count();
+        let (k0, k1) = (keys[i], keys[(i + 1).min(keys.len() - 1)]);
+        let span = k1.time() - k0.time();
+        let s = if span > 0.0 { ((time - k0.time()) / span).clamp(0.0, 1.0) } else { 0.0 };
+        let ((t0, q0), (t1, q1)) = (k0.offset(), k1.offset());
+        (t0 + (t1 - t0) * s, slerp(q0, q1, s))
+    }
+}
+
+/// Spherical interpolation between unit quaternions along the shorter arc,
+/// like `quat_slerp` in the kernel.
+fn slerp(q0: Quaternion<f32>, q1: Quaternion<f32>, s: f32) -> Quaternion<f32> {
+    let cos_theta = q0.dot(q1);
+    let (q1, cos_theta) = if cos_theta < 0.0 { (-q1, -cos_theta) } else { (q1, cos_theta) };
+    // Nearly parallel keys divide by a vanishing sine, where the lerp is exact enough
+    if cos_theta > 0.9995 {
+        return (q0 * (1.0 - s) + q1 * s).normalize();
+    }
+    let theta = cos_theta.acos();
+    (q0 * ((1.0 - s) * theta).sin() + q1 * (s * theta).sin()) / theta.sin()
+}
+
+/// Keyed motion of the scene's instances, read from `<scene>.motion.ron`
+/// next to the mesh. Instances without keys stay in their loaded pose.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct SceneMotion {
+    /// Keys of the mesh, pivoting about its translation.
+    pub mesh: Vec<MotionKeyframe>,
+    /// Keys of the spheres by their index in the scene.
+    pub spheres: BTreeMap<usize, Vec<MotionKeyframe>>,
+}
+
+/// Offset of an instance at `time` as written in the motion file: moved by
+/// `translation` and turned `angle` degrees about `axis`.
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(default)]
+pub struct MotionKeyframe {
+    pub time: f32,
+    pub translation: [f32; 3],
+    pub axis: [f32; 3],
+    pub angle: f32,
+}
+
+impl Default for MotionKeyframe {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            translation: [0.0; 3],
+            axis: [0.0, 1.0, 0.0],
+            angle: 0.0,
+        }
+    }
+}
+
+impl SceneMotion {
+    /// Motion for the mesh at `scene_path`, stored as `<scene>.motion.ron`.
+    /// A missing file leaves the scene still.
+    pub fn load(scene_path: &str) -> Self {
+        let path = PathBuf::from(scene_path).with_extension("motion.ron");
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => return Self::default(),
+        };
+        ron::from_str(&text).unwrap_or_else(|e| {
+            eprintln!("Failed to parse {}: {e}", path.display());
+            Self::default()
+        })
+    }
+
+    /// `keyframes` as motion keys sorted by time.
+    pub fn keys(keyframes: &[MotionKeyframe]) -> Vec<MotionKey> {
+        let mut keys: Vec<MotionKey> = keyframes
+            .iter()
+            .map(|k| {
+                let axis = Vector3::from(k.axis);
+                let rotation = if axis.magnitude2() > 0.0 {
+                    Quaternion::from_axis_angle(axis.normalize(), Deg(k.angle))
+                } else {
+                    Quaternion::new(1.0, 0.0, 0.0, 0.0)
+                };
+                MotionKey::new(k.time, k.translation.into(), rotation)
+            })
+            .collect();
+        keys.sort_by(|a, b| a.time().total_cmp(&b.time()));
+        keys
+    }
 }
 
 pub struct Mesh {
@@ -39,6 +175,8 @@ pub struct Mesh {
     pub translation: Vector3<f32>,
     pub rotation: Deg<f32>,
     pub scale: f32,
+    /// Keyed offsets from the loaded pose, pivoting about `translation`.
+    pub motion: Vec<MotionKey>,
 }
 
 impl Mesh {
@@ -50,6 +188,7 @@ impl Mesh {
             translation: Vector3::new(0.0, 0.0, 0.0),
             rotation: Deg(0.0),
             scale: 1.0,
+            motion: vec![],
         }
     }
 
@@ -308,3 +447,61 @@ impl BVH {
         }
     }
 }
+
+/// Placement of the mesh over time, with bounds covering its whole motion so
+/// rays can skip the BVH before transforming into the rest pose.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuMeshInstance {
+    pivot: [f32; 4],
+    bounds_min: [f32; 4],
+    bounds_max: [f32; 4],
+    /// First key in the shared motion buffer, key count
+    motion: [u32; 4],
+}
+
+impl GpuMeshInstance {
+    /// Steps taken through each pair of keys when bounding rotations.
+    const BOUND_STEPS: u32 = 16;
+
+    pub fn new(mesh: &Mesh, bvh: &BVH, motion_first: u32) -> Self {
+        let root = bvh.nodes[0];
+        let pivot = Point3::new(mesh.translation.x, mesh.translation.y, mesh.translation.z);
+        let corners: Vec<Point3<f32>> = (0..8)
+            .map(|i| {
+                let corner = |axis: usize| {
+                    if i & (1 << axis) == 0 { root.bbox_min[axis] } else { root.bbox_max[axis] }
+                };
+                Point3::new(corner(0), corner(1), corner(2))
+            })
+            .collect();
+
+        let mut times = vec![];
+        for pair in mesh.motion.windows(2) {
+            for step in 0..Self::BOUND_STEPS {
+                let s = step as f32 / Self::BOUND_STEPS as f32;
+                times.push(pair[0].time() + (pair[1].time() - pair[0].time()) * s);
+            }
+        }
+        times.push(mesh.motion.last().map_or(0.0, |k| k.time()));
+
+        let mut bounds = AABB {
+            min_point: Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            max_point: Point3::new(f32::MIN, f32::MIN, f32::MIN),
+        };
+        for time in times {
+            let (translation, rotation) = MotionKey::sample(&mesh.motion, time);
+            for corner in &corners {
+                let p = pivot + rotation.rotate_vector(corner - pivot) + translation;
+                bounds = AABB::union(bounds, AABB { min_point: p, max_point: p });
+            }
+        }
+
+        Self {
+            pivot: [pivot.x, pivot.y, pivot.z, 0.0],
+            bounds_min: [bounds.min_point.x, bounds.min_point.y, bounds.min_point.z, 0.0],
+            bounds_max: [bounds.max_point.x, bounds.max_point.y, bounds.max_point.z, 0.0],
+            motion: [motion_first, mesh.motion.len() as u32, 0, 0],
+        }
+    }
+}
@@ -6,8 +6,118 @@ struct VertexOutput {
 struct TonemapParams {
     key: f32,
     saturation: f32,
+    tonemapper: u32,
+    white: f32,
+    auto_exposure: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
 };
 
+// Written by cs_adapt in exposure.wgsl
+struct ExposureState {
+    key: f32,
+    log_luminance: f32,
+    _pad0: f32,
+    _pad1: f32,
+};
+
+struct LutParams {
+    mode: u32,
+    size: u32,
+    shaper_min_ev: f32,
+    shaper_max_ev: f32,
+    domain_min: vec4<f32>,
+    domain_max: vec4<f32>,
+};
+
+struct ColorParams {
+    xyz_to_output: mat3x3<f32>,
+    luminance: vec4<f32>,
+};
+
+struct ViewParams {
+    mode: u32,
+    error_threshold: f32,
+    iteration: u32,
+    // Add the bloom glare before tonemapping
+    glare: u32,
+};
+
+struct HdrParams {
+    mode: u32,
+    // Nits of diffuse white, where an exposed scene value of 1 lands
+    paper_white: f32,
+    // Brightest the display shows, in nits
+    peak: f32,
+    _pad: u32,
+    output_to_display: mat3x3<f32>,
+};
+
+// Matches the order of DisplayView
+const VIEW_ERROR: u32 = 1u;
+const VIEW_SAMPLES: u32 = 2u;
+const VIEW_ALBEDO: u32 = 3u;
+const VIEW_NORMAL: u32 = 4u;
+const VIEW_DEPTH: u32 = 5u;
+const VIEW_POSITION: u32 = 6u;
+const VIEW_MATERIAL_ID: u32 = 7u;
+// Matches NOISE_FLOOR in goal.rs
+const NOISE_FLOOR: f32 = 1e-2;
+
+// Matches the order of Tonemapper
+const TONEMAP_REINHARD_EXTENDED: u32 = 1u;
+const TONEMAP_ACES: u32 = 2u;
+const TONEMAP_AGX: u32 = 3u;
+const TONEMAP_HABLE: u32 = 4u;
+const TONEMAP_LINEAR: u32 = 5u;
+
+// Stephen Hill's fit of the ACES RRT and sRGB ODT
+const ACES_INPUT = mat3x3<f32>(
+    0.59719, 0.07600, 0.02840,
+    0.35458, 0.90834, 0.13383,
+    0.04823, 0.01566, 0.83777,
+);
+const ACES_OUTPUT = mat3x3<f32>(
+    1.60475, -0.10208, -0.00327,
+    -0.53108, 1.10813, -0.07276,
+    -0.07367, -0.00605, 1.07602,
+);
+
+const AGX_INSET = mat3x3<f32>(
+    0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+    0.0784335999999992, 0.878468636469772, 0.0784336,
+    0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+);
+const AGX_OUTSET = mat3x3<f32>(
+    1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+    -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+    -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+);
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+const HABLE_EXPOSURE_BIAS: f32 = 2.0;
+const HABLE_WHITE: f32 = 11.2;
+
+// Matches the MODE_ constants in lut.rs
+const LUT_DISPLAY: u32 = 1u;
+const LUT_LOG: u32 = 2u;
+const MIDDLE_GREY: f32 = 0.18;
+
+// Matches the order of HdrMode
+const HDR_SDR: u32 = 0u;
+const HDR_PQ: u32 = 2u;
+// Nits of 1 in extended linear sRGB
+const SCRGB_WHITE_NITS: f32 = 80.0;
+// Nits of 1 in PQ, and its SMPTE ST 2084 constants
+const PQ_MAX_NITS: f32 = 10000.0;
+const PQ_M1: f32 = 0.1593017578125;
+const PQ_M2: f32 = 78.84375;
+const PQ_C1: f32 = 0.8359375;
+const PQ_C2: f32 = 18.8515625;
+const PQ_C3: f32 = 18.6875;
+
 @vertex
 fn vs_main(
     @builtin(vertex_index) in_vertex_index: u32,
@@ -24,18 +134,229 @@ fn vs_main(
 @group(0) @binding(0) var r_color: texture_2d<f32>;
 @group(0) @binding(1) var r_sampler: sampler;
 @group(0) @binding(2) var<uniform> tonemap_params: TonemapParams;
+@group(0) @binding(3) var<uniform> color_params: ColorParams;
+@group(0) @binding(4) var moments_tex: texture_2d<f32>;
+@group(0) @binding(5) var<uniform> view_params: ViewParams;
+@group(0) @binding(6) var aov_albedo_tex: texture_2d<f32>;
+@group(0) @binding(7) var aov_normal_tex: texture_2d<f32>;
+@group(0) @binding(8) var aov_position_tex: texture_2d<f32>;
+@group(0) @binding(9) var aov_id_tex: texture_2d<f32>;
+@group(0) @binding(10) var<storage, read> exposure: ExposureState;
+@group(0) @binding(11) var lut_tex: texture_3d<f32>;
+@group(0) @binding(12) var<uniform> lut_params: LutParams;
+// Light scattered by the bloom, in the output space
+@group(0) @binding(13) var glare_tex: texture_2d<f32>;
+@group(0) @binding(14) var<uniform> hdr_params: HdrParams;
+
+fn aces(v: vec3<f32>) -> vec3<f32> {
+    let x = ACES_INPUT * v;
+    let a = x * (x + 0.0245786) - 0.000090537;
+    let b = x * (0.983729 * x + 0.432951) + 0.238081;
+    return clamp(ACES_OUTPUT * (a / b), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn agx(v: vec3<f32>) -> vec3<f32> {
+    let log_v = clamp(log2(max(AGX_INSET * v, vec3<f32>(1e-10))), vec3<f32>(AGX_MIN_EV), vec3<f32>(AGX_MAX_EV));
+    let x = (log_v - AGX_MIN_EV) / (AGX_MAX_EV - AGX_MIN_EV);
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    let c = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+    // The curve ends in display encoding; undo its 2.2 gamma for the output
+    return pow(max(AGX_OUTSET * c, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn hable_partial(x: vec3<f32>) -> vec3<f32> {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f;
+}
 
 fn tonemap(col: vec3<f32>, key: f32, sat: f32) -> vec3<f32> {
-    var c = col * key;
-    c = c / (1.0 + c);
-    let lum = dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+    let v = col * key;
+    var c: vec3<f32>;
+    switch (tonemap_params.tonemapper) {
+        case TONEMAP_REINHARD_EXTENDED: {
+            let w2 = tonemap_params.white * tonemap_params.white;
+            c = min(v * (1.0 + v / w2) / (1.0 + v), vec3<f32>(1.0));
+        }
+        case TONEMAP_ACES: {
+            c = aces(v);
+        }
+        case TONEMAP_AGX: {
+            c = agx(v);
+        }
+        case TONEMAP_HABLE: {
+            c = hable_partial(v * HABLE_EXPOSURE_BIAS) / hable_partial(vec3<f32>(HABLE_WHITE));
+        }
+        case TONEMAP_LINEAR: {
+            c = min(v, vec3<f32>(1.0));
+        }
+        default: {
+            c = v / (1.0 + v);
+        }
+    }
+    let lum = dot(c, color_params.luminance.xyz);
     return mix(vec3<f32>(lum), c, sat);
 }
 
+fn srgb_encode(v: vec3<f32>) -> vec3<f32> {
+    let c = saturate(v);
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, 12.92 * c, c <= vec3<f32>(0.0031308));
+}
+
+fn srgb_decode(v: vec3<f32>) -> vec3<f32> {
+    let c = saturate(v);
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+// Exposed scene values to the LUT input of the log shaper
+fn log_shape(v: vec3<f32>) -> vec3<f32> {
+    let stops = log2(max(v, vec3<f32>(1e-10)) / MIDDLE_GREY);
+    return saturate((stops - lut_params.shaper_min_ev) / (lut_params.shaper_max_ev - lut_params.shaper_min_ev));
+}
+
+// Trilinear lookup over the domain, by hand as float textures need not filter
+fn lut_lookup(input: vec3<f32>) -> vec3<f32> {
+    let n = i32(lut_params.size);
+    let t = saturate((input - lut_params.domain_min.xyz) / (lut_params.domain_max.xyz - lut_params.domain_min.xyz));
+    let coords = t * f32(n - 1);
+    let base = min(vec3<i32>(floor(coords)), vec3<i32>(n - 2));
+    let f = coords - vec3<f32>(base);
+    var out = vec3<f32>(0.0);
+    for (var corner = 0; corner < 8; corner++) {
+        let offset = vec3<i32>(corner & 1, (corner >> 1u) & 1, corner >> 2u);
+        let w = select(1.0 - f, f, offset == vec3<i32>(1));
+        out += w.x * w.y * w.z * textureLoad(lut_tex, base + offset, 0).rgb;
+    }
+    return out;
+}
+
+// The LUT output is display encoded, like the input of a display LUT
+fn grade(input: vec3<f32>) -> vec3<f32> {
+    return srgb_decode(lut_lookup(input));
+}
+
+// Highlights above half the peak approach it smoothly instead of clipping,
+// scaling all channels alike to keep the hue
+fn roll_off(nits: vec3<f32>) -> vec3<f32> {
+    let knee = 0.5 * hdr_params.peak;
+    let m = max(nits.r, max(nits.g, nits.b));
+    if (m <= knee) { return nits; }
+    let headroom = hdr_params.peak - knee;
+    return nits * (knee + headroom * (1.0 - exp((knee - m) / headroom))) / m;
+}
+
+fn pq_encode(nits: vec3<f32>) -> vec3<f32> {
+    let y = pow(saturate(nits / PQ_MAX_NITS), vec3<f32>(PQ_M1));
+    return pow((PQ_C1 + PQ_C2 * y) / (1.0 + PQ_C3 * y), vec3<f32>(PQ_M2));
+}
+
+// Linear light in the output space, 1 being paper white, in the encoding the
+// surface takes. SDR surfaces apply the sRGB curve themselves.
+fn display_encode(c: vec3<f32>) -> vec3<f32> {
+    if (hdr_params.mode == HDR_SDR) { return c; }
+    let nits = roll_off(hdr_params.output_to_display * c * hdr_params.paper_white);
+    if (hdr_params.mode == HDR_PQ) {
+        return pq_encode(nits);
+    }
+    // Extended linear keeps what falls outside the Rec.709 gamut as negatives
+    return nits / SCRGB_WHITE_NITS;
+}
+
+// Blue through green to red as t goes from 0 to 1
+fn heat(t: f32) -> vec3<f32> {
+    let x = 4.0 * clamp(t, 0.0, 1.0);
+    return clamp(vec3<f32>(1.5) - abs(vec3<f32>(x) - vec3<f32>(3.0, 2.0, 1.0)), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn heatmap(moments: vec4<f32>) -> vec3<f32> {
+    let n = moments.z;
+    if (view_params.mode == VIEW_SAMPLES) {
+        return heat(log2(max(n, 1.0)) / log2(max(f32(view_params.iteration), 2.0)));
+    }
+    if (n < 2.0) {
+        return vec3<f32>(0.5);
+    }
+    let mean = moments.x / n;
+    let variance = max(moments.y / n - mean * mean, 0.0) * n / (n - 1.0);
+    let error = sqrt(variance / n) / (abs(mean) + NOISE_FLOOR);
+    // Three stops either side of the threshold, which sits in the middle
+    return heat(0.5 + log2(max(error, 1e-8) / view_params.error_threshold) / 6.0);
+}
+
+// A stable, well spread colour per id, black for none
+fn id_color(id: f32) -> vec3<f32> {
+    if (id == 0.0) { return vec3<f32>(0.0); }
+    var h = u32(id) * 747796405u + 2891336453u;
+    h = ((h >> ((h >> 28u) + 4u)) ^ h) * 277803737u;
+    return vec3<f32>(vec3<u32>(h, h >> 8u, h >> 16u) & vec3<u32>(255u)) / 255.0;
+}
+
+fn aov_view(pixel: vec2<i32>) -> vec3<f32> {
+    let albedo = textureLoad(aov_albedo_tex, pixel, 0);
+    // Sums over the samples that hit a diffuse surface, counted in albedo.w
+    let hits = max(albedo.w, 1.0);
+    switch (view_params.mode) {
+        case VIEW_ALBEDO: {
+            return albedo.rgb / max(textureLoad(moments_tex, pixel, 0).z, 1.0);
+        }
+        case VIEW_NORMAL: {
+            return textureLoad(aov_normal_tex, pixel, 0).xyz / hits * 0.5 + 0.5;
+        }
+        case VIEW_DEPTH: {
+            // Near surfaces bright, falling off with distance
+            let depth = textureLoad(aov_normal_tex, pixel, 0).w / hits;
+            return vec3<f32>(select(0.0, exp(-0.1 * depth), albedo.w > 0.0));
+        }
+        case VIEW_POSITION: {
+            let p = textureLoad(aov_position_tex, pixel, 0).xyz / hits;
+            return abs(p) / (1.0 + abs(p));
+        }
+        case VIEW_MATERIAL_ID: {
+            return id_color(textureLoad(aov_id_tex, pixel, 0).x);
+        }
+        default: {
+            return id_color(textureLoad(aov_id_tex, pixel, 0).y);
+        }
+    }
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    if (view_params.mode != 0u) {
+        let size = vec2<f32>(textureDimensions(moments_tex));
+        let pixel = vec2<i32>(min(in.tex_coord * size, size - 1.0));
+        if (view_params.mode >= VIEW_ALBEDO) {
+            return vec4<f32>(display_encode(aov_view(pixel)), 1.0);
+        }
+        return vec4<f32>(display_encode(heatmap(textureLoad(moments_tex, pixel, 0))), 1.0);
+    }
     let tex = textureSample(r_color, r_sampler, in.tex_coord);
-    let avg = tex.rgb / max(tex.a, 1.0);
-    let tm = tonemap(avg, tonemap_params.key, tonemap_params.saturation);
-    return vec4<f32>(tm, 1.0);
+    let avg_xyz = tex.rgb / max(tex.a, 1.0);
+    // Out-of-gamut colours are clipped to the output primaries
+    var avg = max(color_params.xyz_to_output * avg_xyz, vec3<f32>(0.0));
+    if (view_params.glare != 0u) {
+        let size = vec2<f32>(textureDimensions(glare_tex));
+        let pixel = vec2<i32>(min(in.tex_coord * size, size - 1.0));
+        avg = max(avg + textureLoad(glare_tex, pixel, 0).rgb, vec3<f32>(0.0));
+    }
+    let key = select(tonemap_params.key, exposure.key, tonemap_params.auto_exposure != 0u);
+    if (lut_params.mode == LUT_LOG) {
+        // The LUT takes the place of the tonemapper
+        return vec4<f32>(display_encode(grade(log_shape(avg * key))), 1.0);
+    }
+    if (lut_params.mode == LUT_DISPLAY) {
+        // Graded for SDR, so shown at paper white on HDR displays
+        let tm = tonemap(avg, key, tonemap_params.saturation);
+        return vec4<f32>(display_encode(grade(srgb_encode(tm))), 1.0);
+    }
+    if (hdr_params.mode != HDR_SDR) {
+        // The display has room for the highlights the tonemapper compresses
+        return vec4<f32>(display_encode(avg * key), 1.0);
+    }
+    return vec4<f32>(tonemap(avg, key, tonemap_params.saturation), 1.0);
 }
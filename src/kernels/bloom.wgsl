@@ -0,0 +1,132 @@
+// Glare from a multi-scale bloom pyramid. cs_prefilter keeps the light above
+// the threshold at half resolution, cs_downsample halves it level by level,
+// and cs_upsample sums the levels back up, each weighted per channel so that
+// longer wavelengths spread wider. cs_composite scatters `strength` of the
+// light above the threshold into the glare, taking it out of the pixels it
+// came from so the image keeps its energy.
+
+struct Params {
+    // In exposed values, where 1 is about display white
+    threshold: f32,
+    // Width of the soft knee below the threshold, relative to it
+    softness: f32,
+    strength: f32,
+    level: u32,
+    levels: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+    // Weight of each level per channel, summing to 1 over the levels
+    weights: array<vec4<f32>, MAX_LEVELS>,
+};
+
+// Matches TonemapParams in blit.wgsl
+struct TonemapParams {
+    key: f32,
+    saturation: f32,
+    tonemapper: u32,
+    white: f32,
+    auto_exposure: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
+};
+
+struct ExposureState {
+    key: f32,
+    log_luminance: f32,
+    _pad0: f32,
+    _pad1: f32,
+};
+
+struct ColorParams {
+    xyz_to_output: mat3x3<f32>,
+    luminance: vec4<f32>,
+};
+
+// Matches MAX_LEVELS in bloom.rs
+const MAX_LEVELS: u32 = 8u;
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var source_tex: texture_2d<f32>;
+@group(0) @binding(2) var level_out: texture_storage_2d<rgba16float, write>;
+@group(0) @binding(3) var<uniform> tonemap_params: TonemapParams;
+@group(0) @binding(4) var<storage, read> exposure: ExposureState;
+@group(0) @binding(5) var<uniform> color_params: ColorParams;
+@group(0) @binding(6) var blur_tex: texture_2d<f32>;
+@group(0) @binding(7) var linear_sampler: sampler;
+@group(0) @binding(8) var level_tex: texture_2d<f32>;
+@group(0) @binding(9) var glare_out: texture_storage_2d<rgba32float, write>;
+
+// Mean of the accumulation in the output space, as the blit shows it
+fn scene_color(pixel: vec2<i32>) -> vec3<f32> {
+    let tex = textureLoad(source_tex, pixel, 0);
+    return max(color_params.xyz_to_output * (tex.rgb / max(tex.a, 1.0)), vec3<f32>(0.0));
+}
+
+// The part of `c` above the threshold, with a quadratic knee below it
+fn bright(c: vec3<f32>) -> vec3<f32> {
+    let key = select(tonemap_params.key, exposure.key, tonemap_params.auto_exposure != 0u);
+    let brightness = max(c.r, max(c.g, c.b)) * key;
+    let knee = params.threshold * params.softness;
+    var soft = clamp(brightness - params.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 1e-5);
+    let w = max(soft, brightness - params.threshold) / max(brightness, 1e-5);
+    return c * w;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_prefilter(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = textureDimensions(level_out);
+    if (any(global_id.xy >= size)) { return; }
+    let source_size = vec2<i32>(textureDimensions(source_tex));
+    var sum = vec3<f32>(0.0);
+    for (var i = 0; i < 4; i++) {
+        let pixel = min(vec2<i32>(global_id.xy) * 2 + vec2<i32>(i & 1, i >> 1u), source_size - 1);
+        sum += bright(scene_color(pixel));
+    }
+    textureStore(level_out, global_id.xy, vec4<f32>(sum * 0.25, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_downsample(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = textureDimensions(level_out);
+    if (any(global_id.xy >= size)) { return; }
+    let uv = (vec2<f32>(global_id.xy) + 0.5) / vec2<f32>(size);
+    let texel = 1.0 / vec2<f32>(textureDimensions(blur_tex));
+    // Four bilinear taps cover a 4x4 box of the finer level
+    var sum = vec3<f32>(0.0);
+    for (var i = 0; i < 4; i++) {
+        let offset = vec2<f32>(f32(i & 1), f32(i >> 1u)) * 2.0 - 1.0;
+        sum += textureSampleLevel(blur_tex, linear_sampler, uv + offset * texel, 0.0).rgb;
+    }
+    textureStore(level_out, global_id.xy, vec4<f32>(sum * 0.25, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_upsample(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = textureDimensions(level_out);
+    if (any(global_id.xy >= size)) { return; }
+    var sum = params.weights[params.level].rgb * textureLoad(level_tex, vec2<i32>(global_id.xy), 0).rgb;
+    if (params.level + 1u < params.levels) {
+        // 3x3 tent over the coarser level's sum
+        let uv = (vec2<f32>(global_id.xy) + 0.5) / vec2<f32>(size);
+        let texel = 1.0 / vec2<f32>(size);
+        for (var i = 0; i < 9; i++) {
+            let offset = vec2<i32>(i % 3, i / 3) - 1;
+            let w = f32((2 - abs(offset.x)) * (2 - abs(offset.y))) / 16.0;
+            sum += w * textureSampleLevel(blur_tex, linear_sampler, uv + vec2<f32>(offset) * texel, 0.0).rgb;
+        }
+    }
+    textureStore(level_out, global_id.xy, vec4<f32>(sum, 1.0));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_composite(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = textureDimensions(glare_out);
+    if (any(global_id.xy >= size)) { return; }
+    let uv = (vec2<f32>(global_id.xy) + 0.5) / vec2<f32>(size);
+    let bloom = textureSampleLevel(blur_tex, linear_sampler, uv, 0.0).rgb;
+    let scattered = bright(scene_color(vec2<i32>(global_id.xy)));
+    textureStore(glare_out, global_id.xy, vec4<f32>(params.strength * (bloom - scattered), 0.0));
+}
@@ -0,0 +1,118 @@
+// Edge-avoiding a-trous wavelet filter in the style of SVGF. cs_prepare
+// resolves the film to mean XYZ with the variance of the mean luminance in
+// alpha, then each cs_atrous pass widens a 5x5 B3-spline kernel by `step`,
+// stopping at edges in the AOV guides and at luminance differences beyond
+// what the variance explains.
+
+struct DenoiseParams {
+    step: u32,
+    // Non-zero on the final pass, which writes a sample count of 1 for the blit
+    last: u32,
+    sigma_luminance: f32,
+    sigma_normal: f32,
+    sigma_depth: f32,
+    sigma_albedo: f32,
+    _pad0: f32,
+    _pad1: f32,
+};
+
+struct Guide {
+    normal: vec3<f32>,
+    depth: f32,
+    albedo: vec3<f32>,
+    hit: bool,
+};
+
+@group(0) @binding(0) var<uniform> params: DenoiseParams;
+@group(0) @binding(1) var src_tex: texture_2d<f32>;
+@group(0) @binding(2) var dst_tex: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(3) var film_tex: texture_2d<f32>;
+@group(0) @binding(4) var moments_tex: texture_2d<f32>;
+@group(0) @binding(5) var aov_albedo_tex: texture_2d<f32>;
+@group(0) @binding(6) var aov_normal_tex: texture_2d<f32>;
+
+// Variance given to pixels with too few samples to estimate one, which
+// switches the luminance edge stop off
+const UNKNOWN_VARIANCE: f32 = 1e6;
+
+fn load_guide(pixel: vec2<i32>) -> Guide {
+    let albedo = textureLoad(aov_albedo_tex, pixel, 0);
+    let normal = textureLoad(aov_normal_tex, pixel, 0);
+    // The AOVs are sums over the samples that found a diffuse hit
+    let hits = max(albedo.w, 1.0);
+    let samples = max(textureLoad(moments_tex, pixel, 0).z, 1.0);
+    var n = normal.xyz / hits;
+    if (dot(n, n) > 0.0) { n = normalize(n); }
+    return Guide(n, normal.w / hits, albedo.rgb / samples, albedo.w > 0.0);
+}
+
+fn guide_weight(center: Guide, other: Guide, step: u32) -> f32 {
+    if (center.hit != other.hit) { return 0.0; }
+    // Background pixels are told apart by luminance alone
+    if (!center.hit) { return 1.0; }
+    let w_normal = pow(max(dot(center.normal, other.normal), 0.0), params.sigma_normal);
+    // Depth tolerance grows with distance and with the spacing of the taps
+    let depth_scale = params.sigma_depth * f32(step) * max(abs(center.depth), 1e-3);
+    let w_depth = exp(-abs(center.depth - other.depth) / depth_scale);
+    let w_albedo = exp(-length(center.albedo - other.albedo) / params.sigma_albedo);
+    return w_normal * w_depth * w_albedo;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_prepare(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = textureDimensions(film_tex);
+    if (global_id.x >= size.x || global_id.y >= size.y) { return; }
+    let pixel = vec2<i32>(global_id.xy);
+    let film = textureLoad(film_tex, pixel, 0);
+    let moments = textureLoad(moments_tex, pixel, 0);
+    var variance = UNKNOWN_VARIANCE;
+    if (moments.z >= 2.0) {
+        let mean = moments.x / moments.z;
+        variance = max(moments.y / moments.z - mean * mean, 0.0) / (moments.z - 1.0);
+    }
+    textureStore(dst_tex, pixel, vec4<f32>(film.rgb / max(film.a, 1.0), variance));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_atrous(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(src_tex));
+    if (i32(global_id.x) >= size.x || i32(global_id.y) >= size.y) { return; }
+    let pixel = vec2<i32>(global_id.xy);
+    let center = textureLoad(src_tex, pixel, 0);
+    let guide = load_guide(pixel);
+
+    // A 3x3 blur of the variance steadies the luminance edge stop
+    var variance = 0.0;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let q = clamp(pixel + vec2<i32>(dx, dy), vec2<i32>(0), size - 1);
+            let k = select(0.5, 1.0, dx == 0) * select(0.5, 1.0, dy == 0) * 0.25;
+            variance += k * textureLoad(src_tex, q, 0).a;
+        }
+    }
+    let luminance_scale = params.sigma_luminance * sqrt(variance) + 1e-6;
+
+    let kernel = array<f32, 3>(0.375, 0.25, 0.0625);
+    var sum = vec3<f32>(0.0);
+    var sum_variance = 0.0;
+    var weight_sum = 0.0;
+    for (var dy = -2; dy <= 2; dy++) {
+        for (var dx = -2; dx <= 2; dx++) {
+            let q = pixel + vec2<i32>(dx, dy) * i32(params.step);
+            if (any(q < vec2<i32>(0)) || any(q >= size)) { continue; }
+            let tap = textureLoad(src_tex, q, 0);
+            var w = kernel[abs(dx)] * kernel[abs(dy)];
+            if (dx != 0 || dy != 0) {
+                w *= guide_weight(guide, load_guide(q), params.step);
+                w *= exp(-abs(center.y - tap.y) / luminance_scale);
+            }
+            sum += w * tap.rgb;
+            sum_variance += w * w * tap.a;
+            weight_sum += w;
+        }
+    }
+    // The centre tap always contributes, so the weight sum is positive
+    var out = vec4<f32>(sum / weight_sum, sum_variance / (weight_sum * weight_sum));
+    if (params.last != 0u) { out.a = 1.0; }
+    textureStore(dst_tex, pixel, out);
+}
@@ -0,0 +1,111 @@
+// Auto exposure. cs_histogram counts the displayed pixels into bins of log2
+// luminance; cs_adapt averages the bins between two percentiles, moves the
+// key towards the one exposing that average as middle grey, and clears the
+// histogram for the next frame.
+
+struct Params {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    // Cumulative fractions of the pixels, ordered by luminance, between which
+    // the metering averages; low < high
+    low_percentile: f32,
+    high_percentile: f32,
+    // In stops
+    compensation: f32,
+    // Fraction of the way to the target key covered this frame
+    adaptation: f32,
+    _pad0: f32,
+    _pad1: f32,
+};
+
+// Read as the exposure by the blit
+struct ExposureState {
+    key: f32,
+    log_luminance: f32,
+    _pad0: f32,
+    _pad1: f32,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var source_tex: texture_2d<f32>;
+@group(0) @binding(2) var<storage, read_write> histogram: array<atomic<u32>, BINS>;
+@group(0) @binding(3) var<storage, read_write> state: ExposureState;
+
+// Matches HISTOGRAM_BINS in exposure.rs
+const BINS: u32 = 64u;
+const MIDDLE_GREY: f32 = 0.18;
+
+var<workgroup> local_bins: array<atomic<u32>, BINS>;
+var<workgroup> counts: array<u32, BINS>;
+
+fn bin_of(luminance: f32) -> u32 {
+    let t = (log2(luminance) - params.min_log_luminance) / params.log_luminance_range;
+    return u32(clamp(t * f32(BINS), 0.0, f32(BINS - 1u)));
+}
+
+fn bin_log_luminance(bin: u32) -> f32 {
+    return params.min_log_luminance + (f32(bin) + 0.5) / f32(BINS) * params.log_luminance_range;
+}
+
+@compute @workgroup_size(16, 16, 1)
+fn cs_histogram(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    if (local_index < BINS) {
+        atomicStore(&local_bins[local_index], 0u);
+    }
+    workgroupBarrier();
+
+    let size = textureDimensions(source_tex);
+    if (all(global_id.xy < size)) {
+        let texel = textureLoad(source_tex, vec2<i32>(global_id.xy), 0);
+        // Y of the mean XYZ; pixels without light or samples are left out
+        let luminance = texel.y / max(texel.a, 1.0);
+        if (luminance > 0.0) {
+            atomicAdd(&local_bins[bin_of(luminance)], 1u);
+        }
+    }
+    workgroupBarrier();
+
+    if (local_index < BINS) {
+        let n = atomicLoad(&local_bins[local_index]);
+        if (n > 0u) {
+            atomicAdd(&histogram[local_index], n);
+        }
+    }
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn cs_adapt(@builtin(local_invocation_index) local_index: u32) {
+    counts[local_index] = atomicLoad(&histogram[local_index]);
+    atomicStore(&histogram[local_index], 0u);
+    workgroupBarrier();
+    if (local_index != 0u) { return; }
+
+    var total = 0u;
+    for (var bin = 0u; bin < BINS; bin++) {
+        total += counts[bin];
+    }
+    let low = f32(total) * params.low_percentile;
+    let high = f32(total) * params.high_percentile;
+    var below = 0.0;
+    var sum = 0.0;
+    var weight = 0.0;
+    for (var bin = 0u; bin < BINS; bin++) {
+        let n = f32(counts[bin]);
+        // Part of the bin between the percentiles
+        let inside = max(min(below + n, high) - max(below, low), 0.0);
+        sum += inside * bin_log_luminance(bin);
+        weight += inside;
+        below += n;
+    }
+    // Keep the key of the last lit frame
+    if (weight <= 0.0) { return; }
+
+    let log_luminance = sum / weight;
+    let target_log_key = log2(MIDDLE_GREY) - log_luminance + params.compensation;
+    let log_key = log2(state.key);
+    state.key = exp2(log_key + (target_log_key - log_key) * params.adaptation);
+    state.log_luminance = log_luminance;
+}
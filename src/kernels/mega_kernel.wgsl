@@ -5,8 +5,14 @@ struct Params {
     seed: u32,
     photon_radius: f32,
     iteration: u32,
-    _pad0: f32,
-    _pad1: f32,
+    // Relative error below which a pixel stops being traced, or 0 to trace all
+    adaptive_threshold: f32,
+    adaptive_min_samples: u32,
+    // Side of the pixel blocks that share one sample, for coarse previews
+    pixel_stride: u32,
+    _pad0: u32,
+    _pad1: u32,
+    _pad2: u32,
 };
 
 struct Camera {
@@ -14,24 +20,79 @@ struct Camera {
     horizontal: vec4<f32>,
     vertical: vec4<f32>,
     lower_left_corner: vec4<f32>,
+    // aperture radius, focus distance, aperture rotation, custom mask texel size
+    lens: vec4<f32>,
+    // aperture kind (0 circular, 1 polygon, 2 custom), blade count, mask sample count
+    aperture: vec4<u32>,
+    // projection kind (0 perspective, 1 realistic lens, 2 orthographic, 3 equidistant fisheye,
+    // 4 equisolid fisheye, 5 equirectangular), lens element count, dispersive, stereo
+    projection: vec4<u32>,
+    // film width and height in metres for the realistic lens
+    film: vec4<f32>,
+    // fisheye field of view in radians, stereo eye separation
+    view: vec4<f32>,
+    // shutter open and close times in seconds
+    shutter: vec4<f32>,
+};
+
+// One surface of a lens prescription, in metres. A zero radius is the aperture stop.
+struct LensElement {
+    curvature_radius: f32,
+    thickness: f32,
+    aperture_radius: f32,
+    // Refractive index behind the surface is cauchy_a + cauchy_b / lambda_um^2
+    cauchy_a: f32,
+    cauchy_b: f32,
+    pad0: f32,
+    pad1: f32,
+    pad2: f32,
+};
+
+struct FocusProbe {
+    pixel: vec2<u32>,
+    depth: f32,
+    pad: f32,
 };
 
 struct Ray {
     origin: vec3<f32>,
     direction: vec3<f32>,
+    // Scene time in seconds, sampled over the shutter interval
+    time: f32,
 };
 
 struct SphereInstance {
     material_id: u32,
     scale: f32,
+    // Range of the sphere's keys in motion_keys; zero keys is static
+    motion_first: u32,
+    motion_count: u32,
     transform: mat4x4<f32>,
 };
+
+// Rigid offset from an instance's rest pose, rotating about its pivot
+struct MotionKey {
+    // xyz translation, w the key's time
+    translation: vec4<f32>,
+    // Rotation quaternion (x, y, z, w)
+    rotation: vec4<f32>,
+};
+
+struct MeshInstance {
+    pivot: vec4<f32>,
+    // World space bounds over the whole motion
+    bounds_min: vec4<f32>,
+    bounds_max: vec4<f32>,
+    // first motion key, key count
+    motion: vec4<u32>,
+};
 struct SphereInstanceArray {
     contents: array<SphereInstance>,
 };
 
 struct GpuMaterial {
     color: vec4<f32>,
+    spectrum: vec4<f32>,
     roughness: f32,
     ior: f32,
     material_type: u32,
@@ -47,7 +108,8 @@ struct Triangle {
 struct Hit {
     distance: f32,
     material_id: u32,
-    pad2: f32,
+    // 1 + sphere index, one past the spheres for the mesh
+    object_id: u32,
     pad3: f32,
     location: vec3<f32>,
     normal: vec3<f32>,
@@ -65,15 +127,39 @@ struct BVHNode {
 struct Light {
     position: vec4<f32>,
     color: vec4<f32>,
+    spectrum: vec4<f32>,
     color_temp: f32,
     light_type: u32,
     pad1: f32,
     pad2: f32,
 };
 
+struct SampledWavelengths {
+    lambda: vec4<f32>,
+    pdf: vec4<f32>,
+};
+
+// Pixels still being traced, rebuilt from the moments by cs_sample_map
+struct SampleMap {
+    active_count: atomic<u32>,
+    pixels: array<u32>,
+};
+
+// Guide values of the first diffuse hit along a camera path
+struct Aov {
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    position: vec3<f32>,
+    depth: f32,
+    material_id: u32,
+    object_id: u32,
+    found: bool,
+};
+
 struct Vispoint {
     position: vec4<f32>,
     normal: vec4<f32>,
+    // w is 1 once the camera path carries the hero wavelength alone
     wo: vec4<f32>,
     throughput: vec4<f32>,
 };
@@ -81,12 +167,33 @@ struct Vispoint {
 @group(0) @binding(0) var<uniform> params: Params;
 @group(0) @binding(1) var output_tex: texture_storage_2d<rgba32float, read_write>;
 @group(0) @binding(2) var<storage, read_write> vispoints: array<Vispoint>;
+// Colour matching functions of the selected observer, 380-780nm at 5nm steps
+@group(0) @binding(3) var<storage, read> cie_table: array<vec4<f32>>;
+
+@group(0) @binding(4) var<storage, read_write> focus_probe: FocusProbe;
+// Per-pixel sums of luminance, squared luminance and sample count, for noise estimates
+@group(0) @binding(5) var moments_tex: texture_storage_2d<rgba32float, read_write>;
+
+@group(0) @binding(6) var<storage, read_write> sample_map: SampleMap;
+
+// Sums over the samples that found a diffuse hit, the count of which is in albedo.w
+@group(0) @binding(7) var aov_albedo_tex: texture_storage_2d<rgba32float, read_write>;
+// Normal, and linear depth in w
+@group(0) @binding(8) var aov_normal_tex: texture_storage_2d<rgba32float, read_write>;
+@group(0) @binding(9) var aov_position_tex: texture_storage_2d<rgba32float, read_write>;
+// 1 + material id and object id of the first sample to find a hit, 0 for none
+@group(0) @binding(10) var aov_id_tex: texture_storage_2d<rgba32float, read_write>;
 
 @group(1) @binding(0) var<uniform> camera: Camera;
+@group(1) @binding(1) var<storage, read> aperture_samples: array<vec2<f32>>;
+@group(1) @binding(2) var<storage, read> lens_elements: array<LensElement>;
 
 @group(2) @binding(0) var<storage, read> sphere_instances: SphereInstanceArray;
+@group(2) @binding(1) var<storage, read> motion_keys: array<MotionKey>;
+// Mesh positions are in the instance's rest pose
 @group(3) @binding(0) var<storage, read> mesh_positions: array<vec3<f32>>;
 @group(3) @binding(1) var<storage, read> mesh_indices: array<vec4<u32>>;
+@group(3) @binding(2) var<uniform> mesh_instance: MeshInstance;
 @group(4) @binding(0) var<storage, read> materials: array<GpuMaterial>;
 @group(5) @binding(0) var<storage, read> bvh_nodes: array<BVHNode>;
 @group(5) @binding(1) var<storage, read> bvh_triangle_indices: array<u32>;
@@ -101,67 +208,11 @@ const DISPERSION_B: f32 = 0.004;
 const K_PHOTONS: u32 = 4u;
 const MAX_PHOTON_BOUNCES: u32 = 8u;
 const PHOTON_CONE_COS: f32 = 0.707;
-
-// ----- CIE spectral data (embedded, replaces cie_table buffer) -----
-const CIE_X: array<f32, 81> = array<f32, 81>(
-    0.001368000, 0.002236000, 0.004243000, 0.007650000, 0.01431000,
-    0.02319000,  0.04351000,  0.07763000,  0.1343800,   0.2147700,
-    0.2839000,   0.3285000,   0.3482800,   0.3480600,   0.3362000,
-    0.3187000,   0.2908000,   0.2511000,   0.1953600,   0.1421000,
-    0.09564000,  0.05795001,  0.03201000,  0.01470000,  0.004900000,
-    0.002400000, 0.009300000, 0.02910000,  0.06327000,  0.1096000,
-    0.1655000,   0.2257499,   0.2904000,   0.3597000,   0.4334499,
-    0.5120501,   0.5945000,   0.6784000,   0.7621000,   0.8425000,
-    0.9163000,   0.9786000,   1.0263000,   1.0567000,   1.0622000,
-    1.0456000,   1.0026000,   0.9384000,   0.8544499,   0.7514000,
-    0.6424000,   0.5419000,   0.4479000,   0.3608000,   0.2835000,
-    0.2187000,   0.1649000,   0.1212000,   0.08740000,  0.06360000,
-    0.04677000,  0.03290000,  0.02270000,  0.01584000,  0.01135916,
-    0.008110916, 0.005790346, 0.004109457, 0.002899327, 0.002049190,
-    0.001439971, 0.0009999493,0.0006900786,0.0004760213,0.0003323011,
-    0.0002348261,0.0001661505,0.0001174130,0.00008307527,0.00005870652,
-    0.00004150994,
-);
-
-const CIE_Y: array<f32, 81> = array<f32, 81>(
-    0.00003900000, 0.00006400000, 0.0001200000, 0.0002170000, 0.0003960000,
-    0.0006400000,  0.001210000,  0.002180000,  0.004000000,  0.007300000,
-    0.01160000,   0.01684000,   0.02300000,   0.02980000,   0.03800000,
-    0.04800000,   0.06000000,   0.07390000,   0.09098000,   0.1126000,
-    0.1390200,    0.1693000,    0.2080200,    0.2586000,    0.3230000,
-    0.4073000,    0.5030000,    0.6082000,    0.7100000,    0.7932000,
-    0.8620000,    0.9148501,    0.9540000,    0.9803000,    0.9949501,
-    1.0000000,    0.9950000,    0.9786000,    0.9520000,    0.9154000,
-    0.8700000,    0.8163000,    0.7570000,    0.6949000,    0.6310000,
-    0.5668000,    0.5030000,    0.4412000,    0.3810000,    0.3210000,
-    0.2650000,    0.2170000,    0.1750000,    0.1382000,    0.1070000,
-    0.08160000,   0.06100000,   0.04458000,   0.03200000,   0.02320000,
-    0.01700000,   0.01192000,   0.008210000,  0.005723000,  0.004102000,
-    0.002929000,  0.002091000,  0.001484000,  0.001047000,  0.0007400000,
-    0.0005200000, 0.0003611000, 0.0002492000, 0.0001719000, 0.0001200000,
-    0.00008480000,0.00006000000,0.00004240000,0.00003000000,0.00002120000,
-    0.00001499000,
-);
-
-const CIE_Z: array<f32, 81> = array<f32, 81>(
-    0.006450001, 0.01054999,  0.02005001,  0.03621000,  0.06785001,
-    0.1102000,   0.2074000,   0.3713000,   0.6456000,   1.0390501,
-    1.3856000,   1.6229600,   1.7470600,   1.7826000,   1.7721100,
-    1.7441000,   1.6692000,   1.5281000,   1.2876400,   1.0419000,
-    0.8129501,   0.6162000,   0.4651800,   0.3533000,   0.2720000,
-    0.2123000,   0.1582000,   0.1117000,   0.07824999,  0.05725001,
-    0.04216000,  0.02984000,  0.02030000,  0.01340000,  0.008749999,
-    0.005749999, 0.003900000, 0.002749999, 0.002100000, 0.001800000,
-    0.001650001, 0.001400000, 0.001100000, 0.0008000000,0.0006000000,
-    0.0003400000,0.0002400000,0.0001900000,0.0001000000,0.00004999999,
-    0.00003000000,0.00002000000,0.00001000000,0.000000000000,0.000000000000,
-    0.000000000000,0.000000000000,0.000000000000,0.000000000000,0.000000000000,
-    0.000000000000,0.000000000000,0.000000000000,0.000000000000,0.000000000000,
-    0.000000000000,0.000000000000,0.000000000000,0.000000000000,0.000000000000,
-    0.000000000000,0.000000000000,0.000000000000,0.000000000000,0.000000000000,
-    0.000000000000,0.000000000000,0.000000000000,0.000000000000,0.000000000000,
-    0.000000000000,
-);
+const N_WAVELENGTHS: f32 = 4.0;
+const LUMINANCE_PEAK: f32 = 538.0;
+const LUMINANCE_FALLOFF: f32 = 0.0072;
+// Matches NOISE_FLOOR in goal.rs
+const NOISE_FLOOR: f32 = 1e-2;
 
 // ----- Spherical geometry helpers -----
 
@@ -179,10 +230,10 @@ fn same_hemisphere(wo: vec3<f32>, wi: vec3<f32>) -> bool {
 
 // ----- Oren-Nayar diffuse BRDF -----
 
-fn oren_nayar_f(wo: vec3<f32>, wi: vec3<f32>, n: vec3<f32>, albedo: vec3<f32>, sigma: f32) -> vec3<f32> {
+fn oren_nayar_f(wo: vec3<f32>, wi: vec3<f32>, n: vec3<f32>, albedo: vec4<f32>, sigma: f32) -> vec4<f32> {
     let ndotv = max(dot(n, wo), 0.0);
     let ndotl = max(dot(n, wi), 0.0);
-    if (ndotv < 1e-6 || ndotl < 1e-6) { return vec3<f32>(0.0); }
+    if (ndotv < 1e-6 || ndotl < 1e-6) { return vec4<f32>(0.0); }
 
     let sig2 = sigma * sigma;
     let A = 1.0 - 0.5 * sig2 / (sig2 + 0.33);
@@ -257,27 +308,316 @@ fn tr_sample_wm(wo: vec3<f32>, u: vec2<f32>, alpha: f32) -> vec3<f32> {
 
 // ----- Cauchy spectral IOR -----
 
-fn cauchy_ior(base_ior: f32, lambda_nm: f32) -> f32 {
+fn cauchy_ior(base_ior: f32, lambda_nm: vec4<f32>) -> vec4<f32> {
     let lambda_um = lambda_nm * 1e-3;
     return base_ior + DISPERSION_B / (lambda_um * lambda_um);
 }
 
 // ----- Ray generation -----
 
-fn get_ray(u: f32, v: f32) -> Ray {
+fn sample_concentric_disk(u: vec2<f32>) -> vec2<f32> {
+    let o = 2.0 * u - 1.0;
+    if (o.x == 0.0 && o.y == 0.0) { return vec2<f32>(0.0); }
+    if (abs(o.x) > abs(o.y)) {
+        let theta = 0.25 * PI * (o.y / o.x);
+        return o.x * vec2<f32>(cos(theta), sin(theta));
+    }
+    let theta = 0.5 * PI - 0.25 * PI * (o.x / o.y);
+    return o.y * vec2<f32>(cos(theta), sin(theta));
+}
+
+// Uniform point on a regular polygon inscribed in the unit circle
+fn sample_polygon(blades: u32, rotation: f32, rng: ptr<function, u32>) -> vec2<f32> {
+    let n = f32(max(blades, 3u));
+    let i = min(floor(rand_1f(rng) * n), n - 1.0);
+    let a0 = rotation + 2.0 * PI * i / n;
+    let a1 = rotation + 2.0 * PI * (i + 1.0) / n;
+    var u = rand_2f(rng);
+    if (u.x + u.y > 1.0) { u = 1.0 - u; }
+    return u.x * vec2<f32>(cos(a0), sin(a0)) + u.y * vec2<f32>(cos(a1), sin(a1));
+}
+
+fn sample_aperture(rng: ptr<function, u32>) -> vec2<f32> {
+    switch (camera.aperture.x) {
+        case 1u: {
+            return sample_polygon(camera.aperture.y, camera.lens.z, rng);
+        }
+        case 2u: {
+            let n = camera.aperture.z;
+            let i = min(u32(rand_1f(rng) * f32(n)), n - 1u);
+            let jitter = (rand_2f(rng) - 0.5) * camera.lens.w;
+            return aperture_samples[i] + jitter;
+        }
+        default: {
+            return sample_concentric_disk(rand_2f(rng));
+        }
+    }
+}
+
+fn lens_ior(element: LensElement, lambda_nm: f32) -> f32 {
+    let lambda_um = lambda_nm * 1e-3;
+    return element.cauchy_a + element.cauchy_b / (lambda_um * lambda_um);
+}
+
+// Trace a ray leaving the film through every lens element, after pbrt's
+// RealisticCamera. Lens space mirrors z so the elements lie along -z.
+// Returns false if the ray is blocked by an element or totally reflected.
+fn trace_lenses_from_film(r: ptr<function, Ray>, lambda_nm: f32) -> bool {
+    let n = camera.projection.y;
+    var o = vec3<f32>((*r).origin.xy, -(*r).origin.z);
+    var d = vec3<f32>((*r).direction.xy, -(*r).direction.z);
+    var element_z = 0.0;
+    for (var k = 0u; k < n; k = k + 1u) {
+        let i = n - 1u - k;
+        let element = lens_elements[i];
+        element_z -= element.thickness;
+
+        var t: f32;
+        var normal = vec3<f32>(0.0, 0.0, 1.0);
+        let is_stop = element.curvature_radius == 0.0;
+        if (is_stop) {
+            if (d.z >= 0.0) { return false; }
+            t = (element_z - o.z) / d.z;
+        } else {
+            let radius = element.curvature_radius;
+            let oc = o - vec3<f32>(0.0, 0.0, element_z + radius);
+            let a = dot(d, d);
+            let b = 2.0 * dot(d, oc);
+            let c = dot(oc, oc) - radius * radius;
+            let disc = b * b - 4.0 * a * c;
+            if (disc < 0.0) { return false; }
+            let t0 = (-b - sqrt(disc)) / (2.0 * a);
+            let t1 = (-b + sqrt(disc)) / (2.0 * a);
+            let use_closer = (d.z > 0.0) != (radius < 0.0);
+            t = select(max(t0, t1), min(t0, t1), use_closer);
+            normal = normalize(oc + t * d);
+            if (dot(normal, d) > 0.0) { normal = -normal; }
+        }
+        if (t < 0.0) { return false; }
+
+        let p = o + t * d;
+        if (dot(p.xy, p.xy) > element.aperture_radius * element.aperture_radius) { return false; }
+        o = p;
+
+        if (!is_stop) {
+            var eta_t = 1.0;
+            if (i > 0u) { eta_t = lens_ior(lens_elements[i - 1u], lambda_nm); }
+            let eta = lens_ior(element, lambda_nm) / eta_t;
+            let wi = -normalize(d);
+            let cos_i = dot(normal, wi);
+            let sin2_t = eta * eta * max(0.0, 1.0 - cos_i * cos_i);
+            if (sin2_t >= 1.0) { return false; }
+            d = -eta * wi + (eta * cos_i - sqrt(1.0 - sin2_t)) * normal;
+        }
+    }
+    (*r).origin = vec3<f32>(o.xy, -o.z);
+    (*r).direction = vec3<f32>(d.xy, -d.z);
+    return true;
+}
+
+// Film sits at z = 0 in camera space with the lens towards +z. The lens
+// inverts the image, so film positions are mirrored to keep it upright.
+fn get_realistic_ray(u: f32, v: f32, lambda_nm: vec4<f32>, rng: ptr<function, u32>,
+                     weight: ptr<function, vec4<f32>>) -> Ray {
+    let rear = lens_elements[camera.projection.y - 1u];
+    let p_film = vec3<f32>((0.5 - u) * camera.film.x, (0.5 - v) * camera.film.y, 0.0);
+    let p_rear = vec3<f32>(rear.aperture_radius * sample_concentric_disk(rand_2f(rng)), rear.thickness);
+
+    var ray = Ray(p_film, normalize(p_rear - p_film), 0.0);
+    // Natural vignetting of the light arriving at the film
+    let cos_theta = ray.direction.z;
+    *weight = vec4<f32>(cos_theta * cos_theta * cos_theta * cos_theta);
+    if (!trace_lenses_from_film(&ray, lambda_nm.x)) {
+        *weight = vec4<f32>(0.0);
+        return ray;
+    }
+    // Every wavelength would take its own path through dispersive glass
+    if (camera.projection.z != 0u) {
+        *weight = collapse_to_hero(*weight);
+    }
+
+    let right = normalize(camera.horizontal.xyz);
+    let up = normalize(camera.vertical.xyz);
+    let forward = cross(up, right);
+    ray.origin = camera.origin.xyz + ray.origin.x * right + ray.origin.y * up + ray.origin.z * forward;
+    ray.direction = ray.direction.x * right + ray.direction.y * up + ray.direction.z * forward;
+    return ray;
+}
+
+fn get_perspective_ray(u: f32, v: f32, rng: ptr<function, u32>) -> Ray {
     var ray: Ray;
     ray.origin = camera.origin.xyz;
+    // The image plane sits one unit along the view axis
     ray.direction = (camera.lower_left_corner
                     + camera.horizontal * u
                     + camera.vertical * v
                     - camera.origin).xyz;
+
+    let aperture_radius = camera.lens.x;
+    if (aperture_radius > 0.0) {
+        let focus_point = ray.origin + ray.direction * camera.lens.y;
+        let lens = aperture_radius * sample_aperture(rng);
+        ray.origin += lens.x * normalize(camera.horizontal.xyz) + lens.y * normalize(camera.vertical.xyz);
+        ray.direction = focus_point - ray.origin;
+    }
     return ray;
 }
 
+// Parallel rays from a plane through the camera, sized to the perspective
+// frustum at the focus distance
+fn get_orthographic_ray(u: f32, v: f32) -> Ray {
+    let forward = normalize(cross(camera.vertical.xyz, camera.horizontal.xyz));
+    let offset = (camera.horizontal.xyz * (u - 0.5) + camera.vertical.xyz * (v - 0.5)) * camera.lens.y;
+    return Ray(camera.origin.xyz + offset, forward, 0.0);
+}
+
+// The image circle touches the top and bottom of the frame. Returns a zero
+// direction outside it.
+fn fisheye_direction(u: f32, v: f32) -> vec3<f32> {
+    let right = normalize(camera.horizontal.xyz);
+    let up = normalize(camera.vertical.xyz);
+    let forward = cross(up, right);
+    let aspect = length(camera.horizontal.xyz) / length(camera.vertical.xyz);
+    // Offset from the centre, with 1 at the edge of the image circle
+    let p = 2.0 * vec2<f32>((u - 0.5) * aspect, v - 0.5);
+    let r = length(p);
+    let theta_max = 0.5 * camera.view.x;
+    var theta: f32;
+    if (camera.projection.x == 4u) {
+        let s = r * sin(0.5 * theta_max);
+        if (s > 1.0) { return vec3<f32>(0.0); }
+        theta = 2.0 * asin(s);
+    } else {
+        theta = r * theta_max;
+    }
+    if (r > 1.0 || theta > PI) { return vec3<f32>(0.0); }
+    let dir_2d = select(vec2<f32>(0.0), p / r, r > 0.0);
+    return cos(theta) * forward + sin(theta) * (dir_2d.x * right + dir_2d.y * up);
+}
+
+// Longitude runs across the frame with the view direction in the middle
+fn equirectangular_direction(u: f32, v: f32) -> vec3<f32> {
+    let right = normalize(camera.horizontal.xyz);
+    let up = normalize(camera.vertical.xyz);
+    let forward = cross(up, right);
+    let phi = 2.0 * PI * (u - 0.5);
+    let lat = PI * (v - 0.5);
+    return cos(lat) * (sin(phi) * right + cos(phi) * forward) + sin(lat) * up;
+}
+
+// Camera ray through film position (u, v); `weight` is the per-wavelength
+// throughput the camera itself contributes.
+fn get_ray(u_film: f32, v: f32, lambda_nm: vec4<f32>, rng: ptr<function, u32>,
+           weight: ptr<function, vec4<f32>>) -> Ray {
+    let kind = camera.projection.x;
+    var u = u_film;
+    // Side by side stereo: each eye gets half of the frame. Panoramas keep
+    // their full 360 degrees per eye, the others the central half of the view.
+    var eye = 0.0;
+    if (camera.projection.w != 0u) {
+        eye = select(-0.5, 0.5, u_film >= 0.5);
+        u = 2.0 * u_film - select(0.0, 1.0, u_film >= 0.5);
+        if (kind != 5u) { u = 0.25 + 0.5 * u; }
+    }
+
+    *weight = vec4<f32>(1.0);
+    var ray: Ray;
+    switch (kind) {
+        case 1u: {
+            ray = get_realistic_ray(u, v, lambda_nm, rng, weight);
+        }
+        case 2u: {
+            ray = get_orthographic_ray(u, v);
+        }
+        case 3u, 4u: {
+            ray = Ray(camera.origin.xyz, fisheye_direction(u, v), 0.0);
+            if (all(ray.direction == vec3<f32>(0.0))) { *weight = vec4<f32>(0.0); }
+        }
+        case 5u: {
+            ray = Ray(camera.origin.xyz, equirectangular_direction(u, v), 0.0);
+        }
+        default: {
+            ray = get_perspective_ray(u, v, rng);
+        }
+    }
+
+    if (eye != 0.0) {
+        let right = normalize(camera.horizontal.xyz);
+        var baseline = right;
+        if (kind == 5u) {
+            // Omni-directional stereo: eyes sit on a circle, offset
+            // perpendicular to each viewing direction
+            let forward = cross(normalize(camera.vertical.xyz), right);
+            let phi = 2.0 * PI * (u - 0.5);
+            baseline = cos(phi) * right - sin(phi) * forward;
+        }
+        ray.origin += eye * camera.view.y * baseline;
+    }
+    ray.time = mix(camera.shutter.x, camera.shutter.y, rand_1f(rng));
+    return ray;
+}
+
+// ----- Motion -----
+
+struct MotionSample {
+    translation: vec3<f32>,
+    rotation: vec4<f32>,
+};
+
+// Offset of an instance at `time`, interpolating linearly between keys and
+// holding the first and last key outside them
+fn sample_motion(first: u32, count: u32, time: f32) -> MotionSample {
+    if (count == 0u) { return MotionSample(vec3<f32>(0.0), vec4<f32>(0.0, 0.0, 0.0, 1.0)); }
+    var i = first;
+    let last = first + count - 1u;
+    while (i < last && motion_keys[i + 1u].translation.w <= time) { i = i + 1u; }
+    let k0 = motion_keys[i];
+    let k1 = motion_keys[min(i + 1u, last)];
+    let span = k1.translation.w - k0.translation.w;
+    var s = 0.0;
+    if (span > 0.0) { s = clamp((time - k0.translation.w) / span, 0.0, 1.0); }
+    return MotionSample(mix(k0.translation.xyz, k1.translation.xyz, s), quat_slerp(k0.rotation, k1.rotation, s));
+}
+
+// Spherical interpolation along the shorter arc, like `slerp` in instance.rs
+fn quat_slerp(q0: vec4<f32>, q1: vec4<f32>, s: f32) -> vec4<f32> {
+    let flip = dot(q0, q1) < 0.0;
+    let q = select(q1, -q1, flip);
+    let cos_theta = abs(dot(q0, q1));
+    // Nearly parallel keys divide by a vanishing sine, where the lerp is exact enough
+    if (cos_theta > 0.9995) { return normalize(mix(q0, q, s)); }
+    let theta = acos(cos_theta);
+    return (sin((1.0 - s) * theta) * q0 + sin(s * theta) * q) / sin(theta);
+}
+
+fn quat_rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+fn sphere_center(sphere: SphereInstance, time: f32) -> vec3<f32> {
+    let rest = (sphere.transform * vec4<f32>(0.0, 0.0, 0.0, 1.0)).xyz;
+    // Rotating a sphere about its own centre does not move it
+    return rest + sample_motion(sphere.motion_first, sphere.motion_count, time).translation;
+}
+
+// The ray in the mesh's rest pose at the ray's time. Rigid motion keeps
+// hit distances unchanged.
+fn ray_to_mesh_rest(r: Ray, motion: MotionSample) -> Ray {
+    let inverse = vec4<f32>(-motion.rotation.xyz, motion.rotation.w);
+    let pivot = mesh_instance.pivot.xyz;
+    return Ray(pivot + quat_rotate(inverse, r.origin - motion.translation - pivot),
+               quat_rotate(inverse, r.direction), r.time);
+}
+
+fn mesh_motion(time: f32) -> MotionSample {
+    return sample_motion(mesh_instance.motion.x, mesh_instance.motion.y, time);
+}
+
 // ----- Sphere intersection -----
 
 fn hit_sphere(r: Ray, sphere: SphereInstance) -> Hit {
-    let center = (sphere.transform * vec4<f32>(0.0, 0.0, 0.0, 1.0)).xyz;
+    let center = sphere_center(sphere, r.time);
     let radius = sphere.scale;
     let oc: vec3<f32> = r.origin - center;
     let a: f32 = dot(r.direction, r.direction);
@@ -346,6 +686,7 @@ fn closest_sphere_hit(r: Ray) -> Hit {
     best_hit.distance = -10000000.0;
     for (var i: i32 = 0; i < num_instances; i=i+1) {
         current_hit = hit_sphere(r, sphere_instances.contents[i]);
+        current_hit.object_id = u32(i) + 1u;
         if (current_hit.distance > 0.0 && abs(current_hit.distance) < abs(best_hit.distance)) {
             best_hit = current_hit;
         };
@@ -392,12 +733,15 @@ fn ray_aabb_intersect(r: Ray, bmin: vec4<f32>, bmax: vec4<f32>) -> bool {
     return tmax >= max(tmin, 0.0);
 }
 
-fn intersect_bvh(r: Ray) -> Hit {
+fn intersect_bvh(world_ray: Ray) -> Hit {
     var best_hit: Hit;
     best_hit.distance = -10000000.0;
 
     let num_nodes = arrayLength(&bvh_nodes);
     if (num_nodes == 0u) { return best_hit; }
+    if (!ray_aabb_intersect(world_ray, mesh_instance.bounds_min, mesh_instance.bounds_max)) { return best_hit; }
+    let motion = mesh_motion(world_ray.time);
+    let r = ray_to_mesh_rest(world_ray, motion);
 
     var stack: array<u32, 64>;
     var sp: u32 = 0u;
@@ -424,43 +768,103 @@ fn intersect_bvh(r: Ray) -> Hit {
             sp = sp + 1u;
         }
     }
+    if (best_hit.distance > 0.0) {
+        let pivot = mesh_instance.pivot.xyz;
+        best_hit.location = pivot + quat_rotate(motion.rotation, best_hit.location - pivot) + motion.translation;
+        best_hit.normal = quat_rotate(motion.rotation, best_hit.normal);
+    }
     return best_hit;
 }
 
-fn closest_triangle_hit(r: Ray) -> Hit { return intersect_bvh(r); }
+fn closest_triangle_hit(r: Ray) -> Hit {
+    var hit = intersect_bvh(r);
+    hit.object_id = arrayLength(&sphere_instances.contents) + 1u;
+    return hit;
+}
 
 // ----- Spectral functions -----
 
-fn blackbody(lambda_nm: f32, temp: f32) -> f32 {
+fn blackbody(lambda_nm: vec4<f32>, temp: f32) -> vec4<f32> {
     let h = 6.62607015e-34;
     let c = 2.99792458e8;
     let k = 1.380649e-23;
     let c1 = 2.0 * h * c * c;
     let c2 = h * c / k;
     let l = lambda_nm * 1e-9;
-    return c1 / (pow(l, 5.0) * (exp(c2 / (l * temp)) - 1.0)) * 1e-14;
+    return c1 / (pow(l, vec4<f32>(5.0)) * (exp(c2 / (l * temp)) - 1.0)) * 1e-14;
+}
+
+// Jakob-Hanika sigmoid polynomial, coefficients fitted on the host over
+// wavelengths normalised to [0, 1]
+fn sigmoid_spectrum(coeffs: vec4<f32>, lambda_nm: vec4<f32>) -> vec4<f32> {
+    let x = (lambda_nm - VISIBLE_MIN) / VISIBLE_RANGE;
+    let z = (coeffs.x * x + coeffs.y) * x + coeffs.z;
+    return 0.5 + z / (2.0 * sqrt(1.0 + z * z));
+}
+
+fn albedo_at(mat: GpuMaterial, lambda_nm: vec4<f32>) -> vec4<f32> {
+    return sigmoid_spectrum(mat.spectrum, lambda_nm);
 }
 
-fn cie_to_rgb(lambda_nm: f32) -> vec3<f32> {
+fn light_emission(light: Light, lambda_nm: vec4<f32>) -> vec4<f32> {
+    var spd: vec4<f32>;
+    if (light.color_temp > 0.0) { spd = blackbody(lambda_nm, light.color_temp); }
+    else { spd = vec4<f32>(1.0); }
+    return light.color.w * light.spectrum.w * sigmoid_spectrum(light.spectrum, lambda_nm) * spd;
+}
+
+fn cie_xyz(lambda_nm: f32) -> vec3<f32> {
     let t = (lambda_nm - VISIBLE_MIN) / 5.0;
     let i = u32(t);
     let f = t - f32(i);
     let a = min(i, 80u);
     let b = min(i + 1u, 80u);
-    let x = mix(CIE_X[a], CIE_X[b], f);
-    let y = mix(CIE_Y[a], CIE_Y[b], f);
-    let z = mix(CIE_Z[a], CIE_Z[b], f);
-    return vec3<f32>(
-        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
-        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
-        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
-    );
+    return mix(cie_table[a].xyz, cie_table[b].xyz, f);
+}
+
+// ----- Hero wavelength sampling -----
+
+// Visible wavelengths are importance sampled proportional to
+// sech^2(b * (lambda - 538)), a close fit to the CIE luminance curve
+fn visible_wavelength_pdf(lambda_nm: vec4<f32>) -> vec4<f32> {
+    let t0 = tanh(LUMINANCE_FALLOFF * (VISIBLE_MIN - LUMINANCE_PEAK));
+    let t1 = tanh(LUMINANCE_FALLOFF * (VISIBLE_MIN + VISIBLE_RANGE - LUMINANCE_PEAK));
+    let c = cosh(LUMINANCE_FALLOFF * (lambda_nm - LUMINANCE_PEAK));
+    return vec4<f32>(LUMINANCE_FALLOFF / (t1 - t0)) / (c * c);
+}
+
+fn sample_visible_wavelength(u: vec4<f32>) -> vec4<f32> {
+    let t0 = tanh(LUMINANCE_FALLOFF * (VISIBLE_MIN - LUMINANCE_PEAK));
+    let t1 = tanh(LUMINANCE_FALLOFF * (VISIBLE_MIN + VISIBLE_RANGE - LUMINANCE_PEAK));
+    return LUMINANCE_PEAK + atanh(t0 + u * (t1 - t0)) / LUMINANCE_FALLOFF;
+}
+
+// The hero wavelength sits in .x, the other three are stratified
+// evenly around it in sample space
+fn sample_wavelengths(u: f32) -> SampledWavelengths {
+    let us = fract(vec4<f32>(u) + vec4<f32>(0.0, 0.25, 0.5, 0.75));
+    let lambda = sample_visible_wavelength(us);
+    return SampledWavelengths(lambda, visible_wavelength_pdf(lambda));
+}
+
+// After a wavelength-dependent direction is chosen only the hero stays valid,
+// so it is reweighted to estimate the spectrum on its own
+fn collapse_to_hero(t: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(t.x * N_WAVELENGTHS, 0.0, 0.0, 0.0);
+}
+
+fn spectrum_to_xyz(l: vec4<f32>, wl: SampledWavelengths) -> vec3<f32> {
+    var xyz = vec3<f32>(0.0);
+    for (var i = 0; i < 4; i = i + 1) {
+        xyz += l[i] * cie_xyz(wl.lambda[i]) / wl.pdf[i];
+    }
+    return xyz / N_WAVELENGTHS;
 }
 
 // ----- Shadow rays -----
 
 fn hit_sphere_shadow(r: Ray, sphere: SphereInstance, t_max: f32) -> bool {
-    let center = (sphere.transform * vec4<f32>(0.0, 0.0, 0.0, 1.0)).xyz;
+    let center = sphere_center(sphere, r.time);
     let radius = sphere.scale;
     let oc = r.origin - center;
     let a = dot(r.direction, r.direction);
@@ -508,17 +912,17 @@ fn sphere_roots(origin: vec3<f32>, dir: vec3<f32>, center: vec3<f32>, radius: f3
     return vec2<f32>((-half_b - sqrt_disc) / a, (-half_b + sqrt_disc) / a);
 }
 
-fn shadow_attenuation(r: Ray, t_max: f32, lambda_nm: f32) -> f32 {
-    var atten = 1.0;
+fn shadow_attenuation(r: Ray, t_max: f32, lambda_nm: vec4<f32>) -> vec4<f32> {
+    var atten = vec4<f32>(1.0);
     let num_spheres = arrayLength(&sphere_instances.contents);
     for (var i = 0u; i < num_spheres; i = i + 1u) {
         let sphere = sphere_instances.contents[i];
-        let center = (sphere.transform * vec4<f32>(0.0, 0.0, 0.0, 1.0)).xyz;
+        let center = sphere_center(sphere, r.time);
         let ts = sphere_roots(r.origin, r.direction, center, sphere.scale);
         if (ts.y <= 0.0 || ts.x >= t_max) { continue; }
 
         let mat = materials[sphere.material_id];
-        if (mat.material_type == 0u) { return 0.0; }
+        if (mat.material_type == 0u) { return vec4<f32>(0.0); }
 
         let t_entry = max(ts.x, 0.0);
         let t_exit = min(ts.y, t_max);
@@ -527,18 +931,19 @@ fn shadow_attenuation(r: Ray, t_max: f32, lambda_nm: f32) -> f32 {
         let p1 = r.origin + r.direction * t_entry;
         let n1 = normalize(p1 - center);
         let cos_1 = -dot(n1, r.direction);
-        let R1 = fr_dielectric(cos_1, cauchy_ior(mat.ior, lambda_nm));
+        let R1 = fr_dielectric4(cos_1, cauchy_ior(mat.ior, lambda_nm));
 
         let p2 = r.origin + r.direction * t_exit;
         let n2 = normalize(p2 - center);
         let cos_2 = -dot(n2, r.direction);
-        let R2 = fr_dielectric(cos_2, cauchy_ior(mat.ior, lambda_nm));
+        let R2 = fr_dielectric4(cos_2, cauchy_ior(mat.ior, lambda_nm));
 
         atten *= (1.0 - R1) * (1.0 - R2);
     }
 
     let num_nodes = arrayLength(&bvh_nodes);
-    if (num_nodes > 0u) {
+    if (num_nodes > 0u && ray_aabb_intersect(r, mesh_instance.bounds_min, mesh_instance.bounds_max)) {
+        let local = ray_to_mesh_rest(r, mesh_motion(r.time));
         var stack: array<u32, 64>;
         var sp: u32 = 0u;
         stack[sp] = 0u;
@@ -547,10 +952,10 @@ fn shadow_attenuation(r: Ray, t_max: f32, lambda_nm: f32) -> f32 {
             sp = sp - 1u;
             let node_idx = stack[sp];
             let node = bvh_nodes[node_idx];
-            if (!ray_aabb_intersect(r, node.bbox_min, node.bbox_max)) { continue; }
+            if (!ray_aabb_intersect(local, node.bbox_min, node.bbox_max)) { continue; }
             if (node.n_triangles > 0u) {
                 for (var ti = 0u; ti < node.n_triangles; ti = ti + 1u) {
-                    if (hit_triangle_shadow(r, bvh_triangle_indices[node.first_triangle + ti], t_max)) { return 0.0; }
+                    if (hit_triangle_shadow(local, bvh_triangle_indices[node.first_triangle + ti], t_max)) { return vec4<f32>(0.0); }
                 }
             } else {
                 stack[sp] = node.right_child;
@@ -565,17 +970,13 @@ fn shadow_attenuation(r: Ray, t_max: f32, lambda_nm: f32) -> f32 {
 
 // ----- Direct lighting -----
 
-fn sample_direct_lighting(pos: vec3<f32>, norm: vec3<f32>, lambda_nm: f32, rng: ptr<function, u32>) -> vec3<f32> {
-    var result = vec3<f32>(0.0);
+fn sample_direct_lighting(pos: vec3<f32>, norm: vec3<f32>, lambda_nm: vec4<f32>, time: f32,
+                          rng: ptr<function, u32>) -> vec4<f32> {
+    var result = vec4<f32>(0.0);
     let num_lights = arrayLength(&scene_lights);
     for (var i = 0u; i < num_lights; i = i + 1u) {
         let light = scene_lights[i];
-
-        var spd: f32;
-        if (light.color_temp > 0.0) { spd = blackbody(lambda_nm, light.color_temp); }
-        else { spd = 1.0; }
-        let cie_factor = cie_to_rgb(lambda_nm);
-        let light_rgb = light.color.rgb * light.color.w * spd * cie_factor * VISIBLE_RANGE;
+        let emission = light_emission(light, lambda_nm);
 
         if (light.light_type == 0u) {
             // Point light
@@ -585,10 +986,10 @@ fn sample_direct_lighting(pos: vec3<f32>, norm: vec3<f32>, lambda_nm: f32, rng:
             let light_dir = to_light / dist;
             let ndotl = dot(norm, light_dir);
             if (ndotl <= 0.0) { continue; }
-            let shadow_ray = Ray(pos + norm * EPS, light_dir);
+            let shadow_ray = Ray(pos + norm * EPS, light_dir, time);
             let atten = shadow_attenuation(shadow_ray, dist - EPS, lambda_nm);
-            if (atten <= 0.0) { continue; }
-            result += light_rgb * ndotl * atten / (dist * dist);
+            if (all(atten <= vec4<f32>(0.0))) { continue; }
+            result += emission * ndotl * atten / (dist * dist);
         } else {
             // Square area light
             let hw = light.position.w;
@@ -604,19 +1005,19 @@ fn sample_direct_lighting(pos: vec3<f32>, norm: vec3<f32>, lambda_nm: f32, rng:
             let l_normal = light_normal(light);
             let cos_light = max(0.0, dot(l_normal, -light_dir));
             if (cos_light <= 0.0) { continue; }
-            let shadow_ray = Ray(pos + norm * EPS, light_dir);
+            let shadow_ray = Ray(pos + norm * EPS, light_dir, time);
             let atten = shadow_attenuation(shadow_ray, dist - EPS, lambda_nm);
-            if (atten <= 0.0) { continue; }
+            if (all(atten <= vec4<f32>(0.0))) { continue; }
             let pdf = 1.0 / max(4.0 * hw * hw, 1e-10);
-            result += light_rgb * ndotl * cos_light * atten / (dist * dist * pdf);
+            result += emission * ndotl * cos_light * atten / (dist * dist * pdf);
         }
     }
     return result;
 }
 
-fn sky_color(dir: vec3<f32>) -> vec3<f32> {
+fn sky_color(dir: vec3<f32>) -> vec4<f32> {
     let t = 0.5 * (dir.y + 1.0);
-    return vec3<f32>(0.0, 0.0, 0.0);
+    return vec4<f32>(0.0);
 }
 
 // ----- Fresnel and reflection/refraction -----
@@ -634,6 +1035,15 @@ fn fr_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
     return (r_parl * r_parl + r_perp * r_perp) * 0.5;
 }
 
+fn fr_dielectric4(cos_theta_i: f32, eta: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(
+        fr_dielectric(cos_theta_i, eta.x),
+        fr_dielectric(cos_theta_i, eta.y),
+        fr_dielectric(cos_theta_i, eta.z),
+        fr_dielectric(cos_theta_i, eta.w),
+    );
+}
+
 fn reflect_dir(wo: vec3<f32>, n: vec3<f32>) -> vec3<f32> {
     return -wo + 2.0 * dot(wo, n) * n;
 }
@@ -722,9 +1132,9 @@ fn sample_cone_toward(origin: vec3<f32>, targ: vec3<f32>, rng: ptr<function, u32
 
 // ----- Photon evaluation -----
 
-fn evaluate_bsdf(wo: vec3<f32>, wi: vec3<f32>, n: vec3<f32>, mat: GpuMaterial, lambda_nm: f32) -> vec3<f32> {
+fn evaluate_bsdf(wo: vec3<f32>, wi: vec3<f32>, n: vec3<f32>, mat: GpuMaterial, lambda_nm: vec4<f32>) -> vec4<f32> {
     if (mat.material_type == 0u) {
-        return oren_nayar_f(wo, wi, n, mat.color.rgb, mat.roughness);
+        return oren_nayar_f(wo, wi, n, albedo_at(mat, lambda_nm), mat.roughness);
     }
     let ndotv = dot(n, wo);
     let ndotl = dot(n, wi);
@@ -733,36 +1143,39 @@ fn evaluate_bsdf(wo: vec3<f32>, wi: vec3<f32>, n: vec3<f32>, mat: GpuMaterial, l
         let alpha = roughness_to_alpha(mat.roughness);
         let eta = cauchy_ior(mat.ior, lambda_nm);
         let wm = normalize(wi + wo);
-        let R = fr_dielectric(dot(wo, wm), eta);
+        let R = fr_dielectric4(dot(wo, wm), eta);
         let D = tr_d(wm, alpha);
         let G = tr_g(wo, wi, alpha);
-        return vec3<f32>(D * G * R / max(4.0 * abs_cos_theta(wi) * abs_cos_theta(wo), 1e-10));
+        return D * G * R / max(4.0 * abs_cos_theta(wi) * abs_cos_theta(wo), 1e-10);
     }
     // Transmission: not evaluated (photons contribute mainly via reflection paths)
-    return vec3<f32>(0.0);
+    return vec4<f32>(0.0);
 }
 
 fn trace_photon(rng: ptr<function, u32>, vis_pos: vec3<f32>, vis_norm: vec3<f32>,
-                vis_wo: vec3<f32>, vis_mat: GpuMaterial, vis_throughput: vec3<f32>,
-                rad: f32, lambda_nm: f32, light: Light) -> vec3<f32> {
-    var contrib = vec3<f32>(0.0);
-    let light_power = light.color.rgb * light.color.w;
+                vis_wo: vec3<f32>, vis_mat: GpuMaterial, vis_throughput: vec4<f32>,
+                vis_hero_only: bool, rad: f32, lambda_nm: vec4<f32>, time: f32, light: Light) -> vec4<f32> {
+    var contrib = vec4<f32>(0.0);
+    let light_power = light_emission(light, lambda_nm);
 
     var rayon: Ray;
-    var throughput: vec3<f32>;
+    var throughput: vec4<f32>;
     if (light.light_type == 0u) {
         let cone_factor = (1.0 - PHOTON_CONE_COS) * 0.5;
         throughput = light_power / f32(K_PHOTONS) * cone_factor;
-        rayon = Ray(light.position.xyz, sample_cone_toward(light.position.xyz, vec3<f32>(0.0, 0.0, 0.0), rng));
+        rayon = Ray(light.position.xyz, sample_cone_toward(light.position.xyz, vec3<f32>(0.0, 0.0, 0.0), rng), time);
     } else {
         let u_emit = rand_2f(rng);
         let lp = sample_square_point(light, u_emit);
         let l_norm = light_normal(light);
         let dir = sample_cosine_hemisphere_dir(l_norm, rng);
         throughput = light_power / f32(K_PHOTONS);
-        rayon = Ray(lp + l_norm * EPS, dir);
+        rayon = Ray(lp + l_norm * EPS, dir, time);
     }
     var ray = rayon;
+    // The camera path may already carry the hero wavelength alone, its
+    // weight scaled up by the collapse
+    var hero_only = vis_hero_only;
 
     for (var bounce: u32 = 0u; bounce < MAX_PHOTON_BOUNCES; bounce = bounce + 1u) {
         var hit = closest_sphere_hit(ray);
@@ -788,13 +1201,14 @@ fn trace_photon(rng: ptr<function, u32>, vis_pos: vec3<f32>, vis_norm: vec3<f32>
             let rn = rand_unit_vec(rng);
             let wi = normalize(normal + rn);
             let pdf = max(dot(normal, wi), 1e-10) * INV_PI;
-            let f_diff = oren_nayar_f(normalize(wo), wi, normal, mat.color.rgb, mat.roughness);
+            let f_diff = oren_nayar_f(normalize(wo), wi, normal, albedo_at(mat, lambda_nm), mat.roughness);
             let cos_term = max(dot(normal, wi), 1e-10);
             throughput *= f_diff * cos_term / max(pdf, 1e-10);
-            ray = Ray(hit.location + normal * EPS, wi);
+            ray = Ray(hit.location + normal * EPS, wi, ray.time);
         } else {
-            // Dielectric
-            let eta = cauchy_ior(mat.ior, lambda_nm);
+            // Dielectric: the hero wavelength picks the direction
+            let etas = cauchy_ior(mat.ior, lambda_nm);
+            let eta = etas.x;
             let alpha = roughness_to_alpha(mat.roughness);
 
             if (effectively_smooth(alpha)) {
@@ -802,13 +1216,15 @@ fn trace_photon(rng: ptr<function, u32>, vis_pos: vec3<f32>, vis_norm: vec3<f32>
                 let R = fr_dielectric(abs(cos_t), eta);
                 if (rand_1f(rng) < R) {
                     let wi = reflect_dir(wo, normal);
-                    ray = Ray(hit.location + normal * EPS, wi);
+                    throughput *= fr_dielectric4(abs(cos_t), etas) / max(R, 1e-10);
+                    ray = Ray(hit.location + normal * EPS, wi, ray.time);
                 } else {
                     let wi = refract_dir(wo, normal, eta);
                     if (length(wi) < 0.5) { break; }
+                    if (!hero_only) { throughput = collapse_to_hero(throughput); hero_only = true; }
                     let etap = select(eta, 1.0 / eta, cos_t < 0.0);
                     throughput /= (etap * etap);
-                    ray = Ray(hit.location - normal * EPS, wi);
+                    ray = Ray(hit.location - normal * EPS, wi, ray.time);
                 }
             } else {
                 // Rough GGX
@@ -827,15 +1243,16 @@ fn trace_photon(rng: ptr<function, u32>, vis_pos: vec3<f32>, vis_norm: vec3<f32>
                     if (!same_hemisphere(wo_l, wi_l)) { break; }
                     let D = tr_d(wm, alpha);
                     let G = tr_g(wo_l, wi_l, alpha);
-                    let bsdf = D * G * R / max(4.0 * abs_cos_theta(wi_l) * abs_cos_theta(wo_l), 1e-10);
+                    let bsdf = D * G * fr_dielectric4(dot_wowm, etas) / max(4.0 * abs_cos_theta(wi_l) * abs_cos_theta(wo_l), 1e-10);
                     let pdf = tr_lambda(wo_l, alpha) + 1.0;
                     let cos_term = abs_cos_theta(wi_l);
                     throughput *= bsdf * cos_term / max(pdf, 1e-10);
                     let wi_w = wi_l.x * T + wi_l.y * B + wi_l.z * normal;
-                    ray = Ray(hit.location + normal * EPS, wi_w);
+                    ray = Ray(hit.location + normal * EPS, wi_w, ray.time);
                 } else {
                     let wi_l = refract_dir(wo_l, wm, eta);
                     if (length(wi_l) < 0.5 || same_hemisphere(wo_l, wi_l)) { break; }
+                    if (!hero_only) { throughput = collapse_to_hero(throughput); hero_only = true; }
                     let D = tr_d(wm, alpha);
                     let G = tr_g(wo_l, wi_l, alpha);
                     let ct_i = abs_cos_theta(wi_l);
@@ -847,12 +1264,12 @@ fn trace_photon(rng: ptr<function, u32>, vis_pos: vec3<f32>, vis_norm: vec3<f32>
                     let etap = select(eta, 1.0 / eta, wo_l.z < 0.0);
                     throughput /= (etap * etap);
                     let wi_w = wi_l.x * T + wi_l.y * B + wi_l.z * normal;
-                    ray = Ray(hit.location - normal * EPS, wi_w);
+                    ray = Ray(hit.location - normal * EPS, wi_w, ray.time);
                 }
             }
         }
 
-        let prob = max(throughput.r, max(throughput.g, throughput.b));
+        let prob = min(max(max(throughput.x, throughput.y), max(throughput.z, throughput.w)), 1.0);
         if (prob < 0.01) { break; }
         if (rand_1f(rng) > prob) { break; }
         throughput /= prob;
@@ -862,13 +1279,24 @@ fn trace_photon(rng: ptr<function, u32>, vis_pos: vec3<f32>, vis_norm: vec3<f32>
 
 // ----- Main trace function -----
 
-fn recursive_trace(r: Ray, rng: ptr<function, u32>, lambda_nm: f32, pixel_idx: u32) -> vec3<f32> {
+// Distance along the view axis, or from the camera for panoramas, which have none
+fn view_depth(p: vec3<f32>) -> f32 {
+    let offset = p - camera.origin.xyz;
+    if (camera.projection.x >= 3u) { return length(offset); }
+    let forward = normalize(cross(camera.vertical.xyz, camera.horizontal.xyz));
+    return dot(offset, forward);
+}
+
+fn recursive_trace(r: Ray, rng: ptr<function, u32>, lambda_nm: vec4<f32>, pixel_idx: u32,
+                   camera_weight: vec4<f32>, aov: ptr<function, Aov>) -> vec4<f32> {
     let max_depth: u32 = params.depth;
 
-    var throughput: vec3<f32> = vec3<f32>(1.0, 1.0, 1.0);
-    var radiance: vec3<f32> = vec3<f32>(0.0, 0.0, 0.0);
+    var throughput: vec4<f32> = camera_weight;
+    var radiance: vec4<f32> = vec4<f32>(0.0);
     var cur_ray: Ray = r;
     var vp_stored = false;
+    // A dispersive lens has already dropped the secondary wavelengths
+    var hero_only = camera.projection.x == 1u && camera.projection.z != 0u;
 
     for (var bounce: u32 = 0u; bounce < max_depth; bounce = bounce + 1u) {
         var best_hit = closest_sphere_hit(cur_ray);
@@ -887,32 +1315,36 @@ fn recursive_trace(r: Ray, rng: ptr<function, u32>, lambda_nm: f32, pixel_idx: u
         let wo = -cur_ray.direction;
 
         if (mat.material_type == 0u) {
-            let mat_color = mat.color.rgb;
+            let albedo = albedo_at(mat, lambda_nm);
 
             // Store vispoint at first diffuse hit
             if (!vp_stored && pixel_idx < params.width * params.height) {
                 vispoints[pixel_idx] = Vispoint(
                     vec4<f32>(best_hit.location, 0.0),
                     vec4<f32>(normal, f32(best_hit.material_id)),
-                    vec4<f32>(wo, 0.0),
-                    vec4<f32>(throughput, 0.0),
+                    vec4<f32>(wo, select(0.0, 1.0, hero_only)),
+                    throughput,
                 );
                 vp_stored = true;
+                *aov = Aov(mat.color.rgb, normal, best_hit.location, view_depth(best_hit.location),
+                           best_hit.material_id, best_hit.object_id, true);
             }
 
-            let direct = sample_direct_lighting(best_hit.location, normal, lambda_nm, rng);
-            radiance += throughput * mat_color * direct;
+            let direct = sample_direct_lighting(best_hit.location, normal, lambda_nm, cur_ray.time, rng);
+            radiance += throughput * albedo * direct;
 
             let rn = rand_unit_vec(rng);
             let wi = normalize(normal + rn);
             let pdf = max(dot(normal, wi), 1e-10) * INV_PI;
-            let f_diff = oren_nayar_f(normalize(wo), wi, normal, mat_color, mat.roughness);
+            let f_diff = oren_nayar_f(normalize(wo), wi, normal, albedo, mat.roughness);
             let cos_term = max(dot(normal, wi), 1e-10);
             throughput *= f_diff * cos_term / max(pdf, 1e-10);
-            cur_ray = Ray(best_hit.location + normal * EPS, wi);
+            cur_ray = Ray(best_hit.location + normal * EPS, wi, cur_ray.time);
 
         } else {
-            let eta = mat.ior;
+            // The hero wavelength picks the direction; secondaries are dropped on refraction
+            let etas = cauchy_ior(mat.ior, lambda_nm);
+            let eta = etas.x;
             let alpha = roughness_to_alpha(mat.roughness);
 
             if (effectively_smooth(alpha)) {
@@ -920,13 +1352,15 @@ fn recursive_trace(r: Ray, rng: ptr<function, u32>, lambda_nm: f32, pixel_idx: u
                 let R = fr_dielectric(abs(cos_theta), eta);
                 if (rand_1f(rng) < R) {
                     let wi = reflect_dir(wo, normal);
-                    cur_ray = Ray(best_hit.location + normal * EPS, wi);
+                    throughput *= fr_dielectric4(abs(cos_theta), etas) / max(R, 1e-10);
+                    cur_ray = Ray(best_hit.location + normal * EPS, wi, cur_ray.time);
                 } else {
                     let wi = refract_dir(wo, normal, eta);
                     if (length(wi) < 0.5) { break; }
+                    if (!hero_only) { throughput = collapse_to_hero(throughput); hero_only = true; }
                     let etap = select(eta, 1.0 / eta, cos_theta < 0.0);
                     throughput /= (etap * etap);
-                    cur_ray = Ray(best_hit.location - normal * EPS, wi);
+                    cur_ray = Ray(best_hit.location - normal * EPS, wi, cur_ray.time);
                 }
             } else {
                 let T = build_tangent_frame(normal);
@@ -945,16 +1379,17 @@ fn recursive_trace(r: Ray, rng: ptr<function, u32>, lambda_nm: f32, pixel_idx: u
                     let G = tr_g(wo_l, wi_l, alpha);
                     let ct_i = abs_cos_theta(wi_l);
                     let ct_o = abs_cos_theta(wo_l);
-                    let bsdf = D * G * R / max(4.0 * ct_i * ct_o, 1e-10);
+                    let bsdf = D * G * fr_dielectric4(dot_wowm, etas) / max(4.0 * ct_i * ct_o, 1e-10);
                     let G1 = 1.0 / (1.0 + tr_lambda(wo_l, alpha));
                     let pdf_wm = (G1 / max(ct_o, 1e-10)) * D * dot_wowm;
                     let pdf = max(pdf_wm / max(4.0 * dot_wowm, 1e-10), 1e-10) * (R / max(R + Tns, 1e-10));
                     throughput *= bsdf * ct_i / max(pdf, 1e-10);
                     let wi_w = wi_l.x * T + wi_l.y * B + wi_l.z * normal;
-                    cur_ray = Ray(best_hit.location + normal * EPS, wi_w);
+                    cur_ray = Ray(best_hit.location + normal * EPS, wi_w, cur_ray.time);
                 } else {
                     let wi_l = refract_dir(wo_l, wm, eta);
                     if (length(wi_l) < 0.5 || same_hemisphere(wo_l, wi_l)) { break; }
+                    if (!hero_only) { throughput = collapse_to_hero(throughput); hero_only = true; }
                     let D = tr_d(wm, alpha);
                     let G = tr_g(wo_l, wi_l, alpha);
                     let ct_i = abs_cos_theta(wi_l);
@@ -968,12 +1403,12 @@ fn recursive_trace(r: Ray, rng: ptr<function, u32>, lambda_nm: f32, pixel_idx: u
                     let etap = select(eta, 1.0 / eta, wo_l.z < 0.0);
                     throughput /= (etap * etap);
                     let wi_w = wi_l.x * T + wi_l.y * B + wi_l.z * normal;
-                    cur_ray = Ray(best_hit.location - normal * EPS, wi_w);
+                    cur_ray = Ray(best_hit.location - normal * EPS, wi_w, cur_ray.time);
                 }
             }
         }
 
-        let prob = max(throughput.r, max(throughput.g, throughput.b));
+        let prob = min(max(max(throughput.x, throughput.y), max(throughput.z, throughput.w)), 1.0);
         if (prob < 0.001) { break; }
         if (rand_1f(rng) > prob) { break; }
         throughput /= prob;
@@ -986,19 +1421,35 @@ fn cs_main(
     @builtin(global_invocation_id) global_id: vec3<u32>,
     @builtin(local_invocation_id) local_id: vec3<u32>
 ) {
-    let pixel_idx = global_id.y * params.width + global_id.x;
-    let pixel_coords = vec2<f32>(global_id.xy) / vec2<f32>(f32(params.width), f32(params.height));
-    var rng: u32 = params.seed + 1203793u * global_id.x + 7u * global_id.y;
-    let rand = rand_2f(&rng);
-    let r = get_ray(pixel_coords.x + rand.x / f32(params.width), pixel_coords.y + rand.y / f32(params.height));
-
-    let lambda = VISIBLE_MIN + rand_1f(&rng) * VISIBLE_RANGE;
-    let cam_radiance = recursive_trace(r, &rng, lambda, pixel_idx);
+    // Each invocation traces the top left pixel of its block, which is the
+    // pixel itself unless the pass is coarse
+    let stride = max(params.pixel_stride, 1u);
+    let anchor = global_id.xy * stride;
+    // Edge workgroups overhang sizes that are not a multiple of the workgroup
+    if (anchor.x >= params.width || anchor.y >= params.height) { return; }
+    let block = min(vec2<u32>(stride), vec2<u32>(params.width, params.height) - anchor);
+    let pixel_idx = anchor.y * params.width + anchor.x;
+    if (stride == 1u && adaptive_sampling() && sample_map.pixels[pixel_idx] == 0u) { return; }
+    let pixel_coords = vec2<f32>(anchor) / vec2<f32>(f32(params.width), f32(params.height));
+    var rng: u32 = params.seed + 1203793u * anchor.x + 7u * anchor.y;
+    let rand = rand_2f(&rng) * vec2<f32>(block);
+    let wavelengths = sample_wavelengths(rand_1f(&rng));
+    var camera_weight: vec4<f32>;
+    let r = get_ray(pixel_coords.x + rand.x / f32(params.width), pixel_coords.y + rand.y / f32(params.height),
+                    wavelengths.lambda, &rng, &camera_weight);
+
+    // Rays blocked inside the lens still count as samples, which darkens the corners
+    let vignetted = all(camera_weight == vec4<f32>(0.0));
+    var cam_radiance = vec4<f32>(0.0);
+    var aov: Aov;
+    if (!vignetted) {
+        cam_radiance = recursive_trace(r, &rng, wavelengths.lambda, pixel_idx, camera_weight, &aov);
+    }
 
     // Photon pass
-    var photon_contrib = vec3<f32>(0.0);
+    var photon_contrib = vec4<f32>(0.0);
     let num_lights = arrayLength(&scene_lights);
-    if (pixel_idx < params.width * params.height && num_lights > 0u) {
+    if (pixel_idx < params.width * params.height && num_lights > 0u && !vignetted) {
         let vp = vispoints[pixel_idx];
         // Check if vispoint was stored (has non-zero position roughly)
         if (length(vp.position.xyz) > 0.001) {
@@ -1008,15 +1459,118 @@ fn cs_main(
                 let li = k % num_lights;
                 let light = scene_lights[li];
                 photon_contrib += trace_photon(&rng, vp.position.xyz, vp.normal.xyz,
-                    vp.wo.xyz, vis_mat, vp.throughput.xyz,
-                    params.photon_radius, lambda, light);
+                    vp.wo.xyz, vis_mat, vp.throughput, vp.wo.w != 0.0,
+                    params.photon_radius, wavelengths.lambda, r.time, light);
             }
         }
     }
 
-    var pixel_color = vec4<f32>(cam_radiance + photon_contrib, 1.0);
+    // The film accumulates CIE XYZ; conversion to an output space happens in the blit
+    let xyz = spectrum_to_xyz(cam_radiance + photon_contrib, wavelengths);
+    for (var y = 0u; y < block.y; y++) {
+        for (var x = 0u; x < block.x; x++) {
+            store_sample(vec2<i32>(anchor + vec2<u32>(x, y)), xyz, aov);
+        }
+    }
+}
+
+fn store_sample(pixel: vec2<i32>, xyz: vec3<f32>, aov: Aov) {
+    let prev = textureLoad(output_tex, pixel);
+    textureStore(output_tex, pixel, prev + vec4<f32>(xyz, 1.0));
+
+    // The first pass of an accumulation starts the moments over
+    var moments = vec4<f32>(0.0);
+    if (params.iteration > 0u) {
+        moments = textureLoad(moments_tex, pixel);
+    }
+    textureStore(moments_tex, pixel, moments + vec4<f32>(xyz.y, xyz.y * xyz.y, 1.0, 0.0));
+
+    store_aov(pixel, aov);
+}
+
+fn store_aov(pixel: vec2<i32>, aov: Aov) {
+    var albedo = vec4<f32>(0.0);
+    var normal = vec4<f32>(0.0);
+    var position = vec4<f32>(0.0);
+    var ids = vec4<f32>(0.0);
+    if (params.iteration > 0u) {
+        albedo = textureLoad(aov_albedo_tex, pixel);
+        normal = textureLoad(aov_normal_tex, pixel);
+        position = textureLoad(aov_position_tex, pixel);
+        ids = textureLoad(aov_id_tex, pixel);
+    }
+    if (aov.found) {
+        albedo += vec4<f32>(aov.albedo, 1.0);
+        normal += vec4<f32>(aov.normal, aov.depth);
+        position += vec4<f32>(aov.position, 0.0);
+        if (ids.x == 0.0) {
+            ids = vec4<f32>(f32(aov.material_id + 1u), f32(aov.object_id), 0.0, 0.0);
+        }
+    }
+    textureStore(aov_albedo_tex, pixel, albedo);
+    textureStore(aov_normal_tex, pixel, normal);
+    textureStore(aov_position_tex, pixel, position);
+    textureStore(aov_id_tex, pixel, ids);
+}
+
+fn adaptive_sampling() -> bool {
+    return params.adaptive_threshold > 0.0 && params.iteration >= params.adaptive_min_samples;
+}
+
+// Relative standard error of a pixel mean, as in goal::relative_error
+fn relative_error(moments: vec4<f32>) -> f32 {
+    let n = moments.z;
+    if (n < 2.0) { return 1e30; }
+    let mean = moments.x / n;
+    let variance = max(moments.y / n - mean * mean, 0.0) * n / (n - 1.0);
+    return sqrt(variance / n) / (abs(mean) + NOISE_FLOOR);
+}
+
+// Keep tracing pixels whose neighbourhood has not converged. Taking the worst
+// error around each pixel guards against estimates that are low by chance.
+@compute @workgroup_size(8, 4, 1)
+fn cs_sample_map(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if (global_id.x >= params.width || global_id.y >= params.height) { return; }
+    let size = vec2<i32>(i32(params.width), i32(params.height));
+    let center = vec2<i32>(global_id.xy);
+    var error = 0.0;
+    for (var dy = -1; dy <= 1; dy++) {
+        for (var dx = -1; dx <= 1; dx++) {
+            let p = clamp(center + vec2<i32>(dx, dy), vec2<i32>(0), size - 1);
+            error = max(error, relative_error(textureLoad(moments_tex, p)));
+        }
+    }
+    let traced = error > params.adaptive_threshold;
+    sample_map.pixels[global_id.y * params.width + global_id.x] = u32(traced);
+    if (traced) {
+        atomicAdd(&sample_map.active_count, 1u);
+    }
+}
+
+// Depth along the view axis of the surface under one pixel, for click-to-focus.
+// The ray goes through the camera's own projection and lens; writes a
+// negative depth if it escapes or is blocked inside the lens.
+@compute @workgroup_size(1, 1, 1)
+fn cs_focus_probe() {
+    let uv = (vec2<f32>(focus_probe.pixel) + 0.5) / vec2<f32>(f32(params.width), f32(params.height));
+    // A fixed seed keeps the probe repeatable; the aperture sample only
+    // moves the hit within the blur around the pixel
+    var rng: u32 = 1u;
+    var weight: vec4<f32>;
+    // Lenses are focused at the helium d line, see lens.rs
+    var ray = get_ray(uv.x, uv.y, vec4<f32>(587.6), &rng, &weight);
+    ray.time = camera.shutter.x;
+    if (all(weight == vec4<f32>(0.0))) {
+        focus_probe.depth = -1.0;
+        return;
+    }
 
-    let prev = textureLoad(output_tex, vec2<i32>(global_id.xy));
-    pixel_color = pixel_color + prev;
-    textureStore(output_tex, vec2<i32>(global_id.xy), pixel_color);
+    var hit = closest_sphere_hit(ray);
+    let triangle_hit = closest_triangle_hit(ray);
+    if (triangle_hit.distance > 0.0 && abs(triangle_hit.distance) < abs(hit.distance)) {
+        hit = triangle_hit;
+    }
+    let forward = normalize(cross(camera.vertical.xyz, camera.horizontal.xyz));
+    let depth = dot(hit.location - camera.origin.xyz, forward);
+    focus_probe.depth = select(-1.0, depth, hit.distance > 0.0 && depth > 0.0);
 }
@@ -0,0 +1,163 @@
+// Temporal reprojection while the camera moves. After a move cs_reproject
+// looks up where each pixel's first hit was seen by the previous camera and
+// carries the image shown there over as a prior, rejecting disocclusions.
+// Every frame cs_resolve blends the prior with the fresh accumulation and
+// keeps the result and its guides as the history for the next move.
+
+struct Camera {
+    origin: vec4<f32>,
+    horizontal: vec4<f32>,
+    vertical: vec4<f32>,
+    lower_left_corner: vec4<f32>,
+    lens: vec4<f32>,
+    aperture: vec4<u32>,
+    projection: vec4<u32>,
+    film: vec4<f32>,
+    view: vec4<f32>,
+    shutter: vec4<f32>,
+};
+
+struct Cameras {
+    previous: Camera,
+    current: Camera,
+};
+
+@group(0) @binding(0) var<uniform> cameras: Cameras;
+@group(0) @binding(1) var aov_albedo_tex: texture_2d<f32>;
+@group(0) @binding(2) var aov_normal_tex: texture_2d<f32>;
+@group(0) @binding(3) var aov_position_tex: texture_2d<f32>;
+@group(0) @binding(4) var history_color_tex: texture_2d<f32>;
+@group(0) @binding(5) var history_position_tex: texture_2d<f32>;
+@group(0) @binding(6) var history_normal_tex: texture_2d<f32>;
+@group(0) @binding(7) var prior_out: texture_storage_2d<rgba32float, write>;
+
+@group(0) @binding(8) var prior_tex: texture_2d<f32>;
+@group(0) @binding(9) var film_tex: texture_2d<f32>;
+@group(0) @binding(10) var output_tex: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(11) var history_color_out: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(12) var history_position_out: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(13) var history_normal_out: texture_storage_2d<rgba32float, write>;
+
+// Samples' worth of weight the history keeps while moving, so each new
+// frame enters the blend with a weight of about 1 / (1 + MAX_HISTORY)
+const MAX_HISTORY: f32 = 8.0;
+// Fresh samples over which the prior fades out once the camera rests
+const FADE_SAMPLES: f32 = 32.0;
+// Largest distance of a history point from the current hit's tangent
+// plane, relative to its distance from the camera
+const PLANE_TOLERANCE: f32 = 0.02;
+const NORMAL_TOLERANCE: f32 = 0.9;
+// Distance standing in for infinity when reprojecting the sky
+const SKY_DISTANCE: f32 = 1e5;
+
+struct Surface {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    hit: bool,
+};
+
+fn current_surface(pixel: vec2<i32>) -> Surface {
+    let hits = textureLoad(aov_albedo_tex, pixel, 0).w;
+    let n = max(hits, 1.0);
+    var normal = textureLoad(aov_normal_tex, pixel, 0).xyz / n;
+    if (dot(normal, normal) > 0.0) { normal = normalize(normal); }
+    return Surface(textureLoad(aov_position_tex, pixel, 0).xyz / n, normal, hits > 0.0);
+}
+
+fn forward_axis(camera: Camera) -> vec3<f32> {
+    return normalize(cross(camera.vertical.xyz, camera.horizontal.xyz));
+}
+
+// Point far along the pixel centre's ray, for pixels that saw only sky
+fn sky_point(camera: Camera, uv: vec2<f32>) -> vec3<f32> {
+    if (camera.projection.x == 2u) {
+        let offset = (camera.horizontal.xyz * (uv.x - 0.5) + camera.vertical.xyz * (uv.y - 0.5)) * camera.lens.y;
+        return camera.origin.xyz + offset + forward_axis(camera) * SKY_DISTANCE;
+    }
+    let direction = (camera.lower_left_corner + camera.horizontal * uv.x + camera.vertical * uv.y - camera.origin).xyz;
+    return camera.origin.xyz + normalize(direction) * SKY_DISTANCE;
+}
+
+// Film coordinates of `p` as seen by a perspective or orthographic camera;
+// negative behind the camera
+fn project(camera: Camera, p: vec3<f32>) -> vec2<f32> {
+    let h = camera.horizontal.xyz;
+    let v = camera.vertical.xyz;
+    let q = p - camera.origin.xyz;
+    let along = dot(q, forward_axis(camera));
+    if (along <= 0.0) { return vec2<f32>(-1.0); }
+    if (camera.projection.x == 2u) {
+        let offset = q - forward_axis(camera) * along;
+        return vec2<f32>(dot(offset, h) / dot(h, h), dot(offset, v) / dot(v, v)) / camera.lens.y + 0.5;
+    }
+    // Scale onto the image plane one unit along the view axis
+    let corner = camera.lower_left_corner.xyz - camera.origin.xyz;
+    let x = q * (dot(corner, forward_axis(camera)) / along) - corner;
+    return vec2<f32>(dot(x, h) / dot(h, h), dot(x, v) / dot(v, v));
+}
+
+fn history_matches(surface: Surface, tap: vec2<i32>) -> bool {
+    let position = textureLoad(history_position_tex, tap, 0);
+    let hit = position.w > 0.0;
+    if (hit != surface.hit) { return false; }
+    if (!hit) { return true; }
+    let normal = textureLoad(history_normal_tex, tap, 0).xyz;
+    let distance = length(surface.position - cameras.previous.origin.xyz);
+    let plane = abs(dot(position.xyz - surface.position, surface.normal));
+    return plane <= PLANE_TOLERANCE * distance && dot(normal, surface.normal) >= NORMAL_TOLERANCE;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_reproject(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(aov_albedo_tex));
+    if (i32(global_id.x) >= size.x || i32(global_id.y) >= size.y) { return; }
+    let pixel = vec2<i32>(global_id.xy);
+    let surface = current_surface(pixel);
+    var p = surface.position;
+    if (!surface.hit) {
+        p = sky_point(cameras.current, (vec2<f32>(pixel) + 0.5) / vec2<f32>(size));
+    }
+
+    // Bilinear lookup over the taps that pass the disocclusion tests
+    let uv = project(cameras.previous, p);
+    let coords = uv * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(coords));
+    let f = coords - floor(coords);
+    var prior = vec4<f32>(0.0);
+    var weight_sum = 0.0;
+    if (all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0))) {
+        for (var i = 0; i < 4; i++) {
+            let offset = vec2<i32>(i & 1, i >> 1u);
+            let tap = base + offset;
+            if (any(tap < vec2<i32>(0)) || any(tap >= size)) { continue; }
+            if (!history_matches(surface, tap)) { continue; }
+            let w = select(1.0 - f.x, f.x, offset.x == 1) * select(1.0 - f.y, f.y, offset.y == 1);
+            prior += w * textureLoad(history_color_tex, tap, 0);
+            weight_sum += w;
+        }
+    }
+    if (weight_sum > 0.0) {
+        prior /= weight_sum;
+        prior.a = min(prior.a, MAX_HISTORY);
+    }
+    textureStore(prior_out, pixel, prior);
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn cs_resolve(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(film_tex));
+    if (i32(global_id.x) >= size.x || i32(global_id.y) >= size.y) { return; }
+    let pixel = vec2<i32>(global_id.xy);
+    let film = textureLoad(film_tex, pixel, 0);
+    let prior = textureLoad(prior_tex, pixel, 0);
+    let prior_weight = prior.a * saturate(1.0 - film.a / FADE_SAMPLES);
+    let weight = prior_weight + film.a;
+    var color = vec3<f32>(0.0);
+    if (weight > 0.0) { color = (prior.rgb * prior_weight + film.rgb) / weight; }
+
+    let surface = current_surface(pixel);
+    textureStore(output_tex, pixel, vec4<f32>(color, 1.0));
+    textureStore(history_color_out, pixel, vec4<f32>(color, weight));
+    textureStore(history_position_out, pixel, vec4<f32>(surface.position, f32(surface.hit)));
+    textureStore(history_normal_out, pixel, vec4<f32>(surface.normal, 0.0));
+}
@@ -0,0 +1,299 @@
+use cgmath::{InnerSpace, Vector3};
+
+// Fraunhofer d, F and C lines in micrometres, used to turn n_d and an Abbe
+// number into Cauchy coefficients.
+const LAMBDA_D: f32 = 0.5876;
+const LAMBDA_F: f32 = 0.4861;
+const LAMBDA_C: f32 = 0.6563;
+
+// Height in metres of the paraxial rays used to find the cardinal points
+const PARAXIAL_HEIGHT: f32 = 3.5e-5;
+
+/// One refracting surface (or the aperture stop) of a lens prescription,
+/// in millimetres as in pbrt's lens files.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LensElement {
+    /// Radius of curvature; zero marks the aperture stop.
+    pub curvature_radius: f32,
+    /// Distance along the axis to the next surface towards the film.
+    pub thickness: f32,
+    /// Refractive index at the d line of the medium behind the surface; zero is air.
+    pub ior: f32,
+    /// Abbe number of that medium; zero disables dispersion.
+    pub abbe: f32,
+    pub aperture_diameter: f32,
+}
+
+impl LensElement {
+    /// Refractive index at the d line, which the paraxial focusing uses.
+    fn n_d(&self) -> f32 {
+        if self.ior == 0.0 {
+            1.0
+        } else {
+            self.ior
+        }
+    }
+
+    /// Cauchy coefficients `(a, b)` with `n(lambda) = a + b / lambda^2`, lambda in µm,
+    /// for the per-wavelength tracing on the GPU.
+    fn cauchy(&self) -> (f32, f32) {
+        if self.ior == 0.0 {
+            return (1.0, 0.0);
+        }
+        if self.abbe <= 0.0 {
+            return (self.ior, 0.0);
+        }
+        let b = (self.ior - 1.0)
+            / self.abbe
+            / (1.0 / (LAMBDA_F * LAMBDA_F) - 1.0 / (LAMBDA_C * LAMBDA_C));
+        (self.ior - b / (LAMBDA_D * LAMBDA_D), b)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuLensElement {
+    /// In metres, like everything else here.
+    pub curvature_radius: f32,
+    pub thickness: f32,
+    pub aperture_radius: f32,
+    pub cauchy_a: f32,
+    pub cauchy_b: f32,
+    _pad: [f32; 3],
+}
+
+/// A sequence of spherical lens elements traced at the sampled wavelength,
+/// after pbrt's RealisticCamera.
+///
+/// Element positions are measured from the film at z = 0 towards the scene
+/// along +z, with the last element's thickness being the distance to the film.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LensSystem {
+    pub elements: Vec<LensElement>,
+}
+
+#[derive(Clone, Copy)]
+struct LensRay {
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+}
+
+impl LensRay {
+    fn at(&self, t: f32) -> Vector3<f32> {
+        self.origin + self.direction * t
+    }
+}
+
+impl LensSystem {
+    /// Parse a prescription with one surface per line, front element first:
+    /// `radius thickness ior aperture_diameter [abbe]`, in millimetres.
+    /// Blank lines and `#` comments are ignored.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut elements = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("line {}: {e}", number + 1))?;
+            if values.len() < 4 {
+                return Err(format!("line {}: expected at least 4 values", number + 1));
+            }
+            elements.push(LensElement {
+                curvature_radius: values[0],
+                thickness: values[1],
+                ior: values[2],
+                aperture_diameter: values[3],
+                abbe: values.get(4).copied().unwrap_or(0.0),
+            });
+        }
+        if elements.is_empty() {
+            return Err("lens prescription has no elements".into());
+        }
+        Ok(Self { elements })
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        self.elements.iter().any(|e| e.cauchy().1 != 0.0)
+    }
+
+    pub fn gpu_elements(&self) -> Vec<GpuLensElement> {
+        self.elements
+            .iter()
+            .map(|e| {
+                let (cauchy_a, cauchy_b) = e.cauchy();
+                GpuLensElement {
+                    curvature_radius: 0.001 * e.curvature_radius,
+                    thickness: 0.001 * e.thickness,
+                    aperture_radius: 0.0005 * e.aperture_diameter,
+                    cauchy_a,
+                    cauchy_b,
+                    _pad: [0.0; 3],
+                }
+            })
+            .collect()
+    }
+
+    /// Move the film so that objects `focus_distance` metres in front of it are
+    /// sharp, using a thick lens approximation of the system.
+    pub fn focus(&mut self, focus_distance: f32) -> Result<(), String> {
+        let ([pz0, fz0], [pz1, _]) = self.thick_lens_approximation()?;
+        let f = fz0 - pz0;
+        let z = -focus_distance;
+        let c = (pz1 - z - pz0) * (pz1 - z - 4.0 * f - pz0);
+        if c <= 0.0 {
+            return Err(format!("cannot focus at {focus_distance}"));
+        }
+        let delta = 0.5 * (pz1 - z + pz0 - c.sqrt());
+        let last = self.elements.last_mut().unwrap();
+        last.thickness = (0.001 * last.thickness + delta).max(0.0) * 1000.0;
+        Ok(())
+    }
+
+    fn front_z(&self) -> f32 {
+        self.elements.iter().map(|e| 0.001 * e.thickness).sum()
+    }
+
+    fn rear_z(&self) -> f32 {
+        0.001 * self.elements.last().unwrap().thickness
+    }
+
+    /// Principal and focal planes `([pz, fz] scene side, [pz, fz] film side)`,
+    /// found by tracing rays parallel to the axis through the system.
+    fn thick_lens_approximation(&self) -> Result<([f32; 2], [f32; 2]), String> {
+        let x = PARAXIAL_HEIGHT;
+        let scene_ray = LensRay {
+            origin: Vector3::new(x, 0.0, self.front_z() + 1.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        };
+        let film_ray = self
+            .trace_from_scene(scene_ray)
+            .ok_or("paraxial ray from the scene was blocked")?;
+        let front = Self::cardinal_points(scene_ray, film_ray);
+
+        let film_ray = LensRay {
+            origin: Vector3::new(x, 0.0, self.rear_z() - 1.0),
+            direction: Vector3::new(0.0, 0.0, 1.0),
+        };
+        let scene_ray = self
+            .trace_from_film(film_ray)
+            .ok_or("paraxial ray from the film was blocked")?;
+        let rear = Self::cardinal_points(film_ray, scene_ray);
+        Ok((front, rear))
+    }
+
+    fn cardinal_points(r_in: LensRay, r_out: LensRay) -> [f32; 2] {
+        let tf = -r_out.origin.x / r_out.direction.x;
+        let tp = (r_in.origin.x - r_out.origin.x) / r_out.direction.x;
+        [-r_out.at(tp).z, -r_out.at(tf).z]
+    }
+
+    fn trace_from_film(&self, ray: LensRay) -> Option<LensRay> {
+        // Lens space mirrors z so the elements lie along -z
+        let mut r = LensRay {
+            origin: mirror_z(ray.origin),
+            direction: mirror_z(ray.direction),
+        };
+        let mut element_z = 0.0;
+        for i in (0..self.elements.len()).rev() {
+            let element = self.elements[i];
+            element_z -= 0.001 * element.thickness;
+            let eta_t = if i > 0 {
+                self.elements[i - 1].n_d()
+            } else {
+                1.0
+            };
+            r = Self::intersect_interface(r, element, element_z, element.n_d() / eta_t)?;
+        }
+        Some(LensRay {
+            origin: mirror_z(r.origin),
+            direction: mirror_z(r.direction),
+        })
+    }
+
+    fn trace_from_scene(&self, ray: LensRay) -> Option<LensRay> {
+        let mut r = LensRay {
+            origin: mirror_z(ray.origin),
+            direction: mirror_z(ray.direction),
+        };
+        let mut element_z = -self.front_z();
+        for i in 0..self.elements.len() {
+            let element = self.elements[i];
+            let eta_i = if i > 0 {
+                self.elements[i - 1].n_d()
+            } else {
+                1.0
+            };
+            r = Self::intersect_interface(r, element, element_z, eta_i / element.n_d())?;
+            element_z += 0.001 * element.thickness;
+        }
+        Some(LensRay {
+            origin: mirror_z(r.origin),
+            direction: mirror_z(r.direction),
+        })
+    }
+
+    /// Intersect and refract through one interface at `element_z` in lens space.
+    fn intersect_interface(
+        r: LensRay,
+        element: LensElement,
+        element_z: f32,
+        eta: f32,
+    ) -> Option<LensRay> {
+        let radius = 0.001 * element.curvature_radius;
+        let is_stop = radius == 0.0;
+        let (t, normal) = if is_stop {
+            ((element_z - r.origin.z) / r.direction.z, Vector3::unit_z())
+        } else {
+            let oc = r.origin - Vector3::new(0.0, 0.0, element_z + radius);
+            let a = r.direction.magnitude2();
+            let b = 2.0 * r.direction.dot(oc);
+            let c = oc.magnitude2() - radius * radius;
+            let disc = b * b - 4.0 * a * c;
+            if disc < 0.0 {
+                return None;
+            }
+            let t0 = (-b - disc.sqrt()) / (2.0 * a);
+            let t1 = (-b + disc.sqrt()) / (2.0 * a);
+            let use_closer = (r.direction.z > 0.0) ^ (radius < 0.0);
+            let t = if use_closer { t0.min(t1) } else { t0.max(t1) };
+            let n = (oc + r.direction * t).normalize();
+            (t, if n.dot(-r.direction) < 0.0 { -n } else { n })
+        };
+        if t < 0.0 {
+            return None;
+        }
+        let hit = r.at(t);
+        let aperture_radius = 0.0005 * element.aperture_diameter;
+        if hit.x * hit.x + hit.y * hit.y > aperture_radius * aperture_radius {
+            return None;
+        }
+        if is_stop {
+            return Some(LensRay {
+                origin: hit,
+                direction: r.direction,
+            });
+        }
+
+        let wi = -r.direction.normalize();
+        let cos_i = normal.dot(wi);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+        if sin2_t >= 1.0 {
+            return None;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some(LensRay {
+            origin: hit,
+            direction: -wi * eta + normal * (eta * cos_i - cos_t),
+        })
+    }
+}
+
+fn mirror_z(v: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(v.x, v.y, -v.z)
+}
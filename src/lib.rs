@@ -1,4 +1,5 @@
 use std::{sync::Arc, time::Instant};
+use cgmath::One;
 use wgpu::{util::DeviceExt, Extent3d};
 use winit::{
     application::ApplicationHandler,
@@ -8,32 +9,137 @@ use winit::{
     window::{CursorGrabMode, Window, WindowAttributes, WindowId},
 };
 
+use animation::CameraPath;
+use args::{Args, CommonArgs};
+use aov::AovImages;
 use blit::RenderPass;
+use bloom::BloomSettings;
+use bookmarks::{Bookmark, Bookmarks};
+use camera::{ApertureShape, FilmFit, Projection};
+use checkpoint::Checkpoint;
+use color::{ColorPipeline, ColorSpace, WhitePoint};
+use denoise::{DenoiseSettings, Denoiser};
+use exposure::{AutoExposure, AutoExposureSettings};
+use frame_budget::FrameBudget;
+use goal::RenderGoal;
+use hdr::{HdrMode, HdrSettings};
 use mega_kernel::ComputePass;
-use instance::{Mesh, BVH};
+use instance::{GpuMeshInstance, Mesh, MotionKey, SceneMotion, BVH};
+use lens::LensSystem;
 use light::GpuLight;
+use lut::Lut;
+use spectrum::Observer;
+use temporal::Temporal;
+use tonemap::{TonemapUniform, Tonemapper};
 
+mod animation;
+mod aov;
+mod args;
 mod blit;
+mod bloom;
+mod bookmarks;
 mod camera;
+mod checkpoint;
+mod color;
+mod denoise;
+mod export;
+mod exposure;
+mod frame_budget;
+mod goal;
+mod hdr;
+pub mod headless;
 mod instance;
+mod lens;
 mod light;
+mod lut;
 mod material;
 mod mega_kernel;
 mod spectrum;
+mod temporal;
 mod tonemap;
 // mod wavefront;
 
-pub async fn run() {
+const SCENE_PATH: &str = "res/glass.obj";
+const SCREENSHOT_DIR: &str = "screenshots";
+// Seconds between checkpoints of a running accumulation
+const CHECKPOINT_INTERVAL: u64 = 60;
+
+pub const VIEWER_USAGE: &str = "\
+usage: wgpu-raytracer [options]
+    --scene <path>      OBJ mesh to build the scene around (default
+                        res/glass.obj); scene description files are not
+                        supported
+    --lut <path>        grade the display through a .cube 3D LUT
+    --lut-log           feed the LUT log encoded scene values instead of
+                        the tonemapped image, for HDR LUTs
+    --hdr <mode>        display output: sdr or extended-linear (default
+                        sdr); falls back to sdr if unsupported. HDR output
+                        skips the tonemapper and saturation. pq falls back
+                        to extended-linear until wgpu can tag the surface
+                        as HDR10
+    --paper-white <nits>
+                        brightness of diffuse white on HDR output (default 203)
+    --peak <nits>       brightest the HDR display shows (default 1000)
+    --resume            continue the render goal saved in the scene's
+                        checkpoint; checkpoints are written while a goal
+                        from res/render_goal.ron is in progress
+or:    wgpu-raytracer render [options]";
+
+/// Options of the interactive viewer.
+#[derive(Debug, Clone, Default)]
+pub struct ViewerArgs {
+    pub common: CommonArgs,
+    pub hdr: Option<HdrMode>,
+    pub paper_white: Option<f32>,
+    pub peak: Option<f32>,
+    pub resume: bool,
+}
+
+impl ViewerArgs {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = Args::new(args);
+        while let Some(flag) = args.next_flag() {
+            if parsed.common.parse_flag(&flag, &mut args)? {
+                continue;
+            }
+            let mut nits = || -> Result<f32, String> {
+                match args.number(&flag)? {
+                    nits if nits > 0.0 => Ok(nits),
+                    nits => Err(format!("invalid value for {flag}: {nits}")),
+                }
+            };
+            match flag.as_str() {
+                "--hdr" => {
+                    let name = args.value(&flag)?;
+                    parsed.hdr =
+                        Some(HdrMode::from_name(&name).ok_or(format!("unknown HDR mode: {name}"))?);
+                }
+                "--paper-white" => parsed.paper_white = Some(nits()?),
+                "--peak" => parsed.peak = Some(nits()?),
+                "--resume" => parsed.resume = true,
+                _ => return Err(format!("unknown option {flag}")),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+pub async fn run(args: ViewerArgs) {
     env_logger::init();
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = App::default();
+    let mut app = App {
+        args,
+        ..Default::default()
+    };
     event_loop.run_app(&mut app).unwrap();
 }
 
 #[derive(Default)]
 struct App {
+    args: ViewerArgs,
     state: Option<State>,
     last_frame: Option<Instant>,
 }
@@ -55,7 +161,7 @@ impl ApplicationHandler for App {
         window.set_cursor_grab(CursorGrabMode::Confined).unwrap();
         window.set_cursor_visible(false);
 
-        self.state = Some(pollster::block_on(State::new(window)));
+        self.state = Some(pollster::block_on(State::new(window, &self.args)));
         self.last_frame = Some(Instant::now());
     }
 
@@ -73,7 +179,10 @@ impl ApplicationHandler for App {
         }
 
         match event {
-            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::CloseRequested => {
+                state.save_checkpoint();
+                event_loop.exit();
+            }
             WindowEvent::RedrawRequested => {
                 let now = Instant::now();
                 let duration = self
@@ -84,7 +193,10 @@ impl ApplicationHandler for App {
                 state.update(duration);
                 state.render();
             }
-            event => state.input_window_event(&event),
+            event => {
+                state.input_window_event(&event);
+                state.window().request_redraw();
+            }
         }
     }
 
@@ -96,11 +208,19 @@ impl ApplicationHandler for App {
     ) {
         if let Some(state) = self.state.as_mut() {
             state.input_device_event(&event);
+            state.window().request_redraw();
         }
     }
 
-    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
-        if let Some(state) = self.state.as_ref() {
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let Some(state) = self.state.as_ref() else {
+            return;
+        };
+        // A finished render waits for input instead of spinning the GPU
+        if state.is_idle() {
+            event_loop.set_control_flow(ControlFlow::Wait);
+        } else {
+            event_loop.set_control_flow(ControlFlow::Poll);
             state.window().request_redraw();
         }
     }
@@ -124,12 +244,53 @@ struct State {
     compute_pass: ComputePass,
     render_pass: RenderPass,
     clear_flag: bool,
-    tonemap_key: f32,
     tonemap_sat: f32,
+    tonemapper: Tonemapper,
+    auto_exposure: AutoExposure,
+    color_pipeline: ColorPipeline,
+    custom_aperture: Option<ApertureShape>,
+    lens_system: Option<LensSystem>,
+    bookmarks: Bookmarks,
+    camera_path: CameraPath,
+    /// Seconds into the camera path while it plays back in the viewer.
+    playback_time: Option<f32>,
+    /// Next frame of the camera path to render to disk.
+    sequence_frame: Option<u32>,
+    modifiers: winit::keyboard::ModifiersState,
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    /// When the current accumulation started, for render metadata.
+    accumulation_start: Instant,
+    checkpoint_path: std::path::PathBuf,
+    last_checkpoint: Instant,
+    /// Checkpoint waiting for the window to reach its size.
+    pending_checkpoint: Option<Checkpoint>,
+    goal: RenderGoal,
+    /// The accumulation met its goal and only the display is refreshed.
+    goal_reached: bool,
+    last_noise_check: Instant,
+    denoiser: Denoiser,
+    denoise: DenoiseSettings,
+    temporal: Temporal,
+    frame_budget: FrameBudget,
+    /// Duration of the last frame, in seconds.
+    frame_seconds: f32,
+    /// Compute passes the last frame ran.
+    last_passes: u32,
+    /// The camera was navigated since the last frame.
+    camera_moved: bool,
+    lut: Option<Lut>,
+    /// File the LUT was loaded from, to find the next one.
+    lut_path: Option<std::path::PathBuf>,
+    /// LUT given on the command line, first in the cycle before those in LUT_DIR.
+    startup_lut: Option<std::path::PathBuf>,
+    lut_log_shaper: bool,
+    hdr: HdrSettings,
+    /// Mesh the scene was built around, which names its sidecar files.
+    scene_path: String,
 }
 
 impl State {
-    async fn new(window: Arc<Window>) -> Self {
+    async fn new(window: Arc<Window>, args: &ViewerArgs) -> Self {
         let size = window.inner_size();
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
@@ -145,30 +306,44 @@ impl State {
             .await
             .unwrap();
 
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
-                        | wgpu::Features::CLEAR_TEXTURE,
-                    required_limits: wgpu::Limits {
-                        max_bind_groups: 7,
-                        max_storage_buffer_binding_size: 512 * 1024 * 1024,
-                        ..Default::default()
-                    },
-                    ..Default::default()
-                },
-            )
-            .await
-            .unwrap();
+        let (device, queue) = request_device(&adapter).await.unwrap();
         // let format = surface.get_preferred_format(&adapter).unwrap();
         let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+        let mut hdr = if std::path::Path::new("res/hdr.ron").exists() {
+            HdrSettings::load("res/hdr.ron")
+                .map_err(|e| eprintln!("Failed to load res/hdr.ron: {e}"))
+                .unwrap_or_default()
+        } else {
+            HdrSettings::default()
+        };
+        hdr.mode = args.hdr.unwrap_or(hdr.mode);
+        hdr.paper_white = args.paper_white.unwrap_or(hdr.paper_white);
+        hdr.peak = args.peak.unwrap_or(hdr.peak);
+        if !hdr.mode.is_taggable() {
+            eprintln!(
+                "{} output cannot be tagged on the surface yet, falling back to extended-linear",
+                hdr.mode.name()
+            );
+            hdr.mode = HdrMode::ExtendedLinear;
+        }
+        let surface_format = match hdr.mode.surface_format() {
+            Some(format) if surface_caps.formats.contains(&format) => format,
+            _ => {
+                if hdr.mode != HdrMode::Sdr {
+                    eprintln!(
+                        "The surface does not support {} output, falling back to sdr",
+                        hdr.mode.name()
+                    );
+                    hdr.mode = HdrMode::Sdr;
+                }
+                surface_caps
+                    .formats
+                    .iter()
+                    .copied()
+                    .find(|f| f.is_srgb())
+                    .unwrap_or(surface_caps.formats[0])
+            }
+        };
         let present_mode = if surface_caps
             .present_modes
             .contains(&wgpu::PresentMode::Immediate)
@@ -200,7 +375,10 @@ impl State {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba32Float,
-            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
         });
 
@@ -211,12 +389,1346 @@ impl State {
             (0.0, 0.0, 1.0).into(),
             cgmath::Vector3::unit_y(),
             75.0,
-            16.0 / 9.0,
+            size.width.max(1) as f32 / size.height.max(1) as f32,
         );
 
+        let custom_aperture = if std::path::Path::new("res/aperture.pgm").exists() {
+            ApertureShape::from_pgm("res/aperture.pgm")
+                .map_err(|e| eprintln!("Failed to load res/aperture.pgm: {e}"))
+                .ok()
+        } else {
+            None
+        };
+        let lens_system = if std::path::Path::new("res/lens.dat").exists() {
+            LensSystem::load("res/lens.dat")
+                .map_err(|e| eprintln!("Failed to load res/lens.dat: {e}"))
+                .ok()
+        } else {
+            None
+        };
+
         let camera_uniform = camera.get_uniform();
-        let camera_controller = camera::CameraController::new(5e-6);
+        let controls = if std::path::Path::new("res/controls.ron").exists() {
+            camera::Controls::load("res/controls.ron")
+                .map_err(|e| eprintln!("Failed to load res/controls.ron: {e}"))
+                .unwrap_or_default()
+        } else {
+            camera::Controls::default()
+        };
+        let hotkeys: Vec<KeyCode> = HOTKEYS.iter().map(|(key, _)| *key).collect();
+        for (action, key) in controls.conflicts(&hotkeys) {
+            eprintln!("res/controls.ron binds {key:?} to {action}, which is also a viewer hotkey");
+        }
+        let camera_controller = camera::CameraController::new(controls);
+        let goal = if std::path::Path::new("res/render_goal.ron").exists() {
+            RenderGoal::load("res/render_goal.ron")
+                .map_err(|e| eprintln!("Failed to load res/render_goal.ron: {e}"))
+                .unwrap_or_default()
+        } else {
+            RenderGoal::default()
+        };
+
+        let frame_budget = if std::path::Path::new("res/frame_budget.ron").exists() {
+            FrameBudget::load("res/frame_budget.ron")
+                .map_err(|e| eprintln!("Failed to load res/frame_budget.ron: {e}"))
+                .unwrap_or_default()
+        } else {
+            FrameBudget::default()
+        };
+
+        let scene_path = args.common.scene.clone();
+        let scene = Scene::new(&device, &size, &scene_path).await;
+
+        // The adopted white follows the camera's temperature only once it is set
+        let color_pipeline = ColorPipeline {
+            adopted_white: camera.adopted_white(),
+            ..Default::default()
+        };
+        let mut compute_pass = ComputePass::new(
+            &device,
+            &size,
+            &compute_view,
+            &camera,
+            &scene,
+            color_pipeline.observer,
+        );
+        compute_pass.set_adaptive(goal.adaptive_threshold);
+        let auto_exposure_settings = if std::path::Path::new("res/auto_exposure.ron").exists() {
+            AutoExposureSettings::load("res/auto_exposure.ron")
+                .map_err(|e| eprintln!("Failed to load res/auto_exposure.ron: {e}"))
+                .unwrap_or_default()
+        } else {
+            AutoExposureSettings::default()
+        };
+        let auto_exposure = AutoExposure::new(
+            &device,
+            &compute_view,
+            compute_texture.size(),
+            auto_exposure_settings,
+        );
+        let bloom_settings = if std::path::Path::new("res/bloom.ron").exists() {
+            BloomSettings::load("res/bloom.ron")
+                .map_err(|e| eprintln!("Failed to load res/bloom.ron: {e}"))
+                .unwrap_or_default()
+        } else {
+            BloomSettings::default()
+        };
+        let render_pass = RenderPass::new(
+            &device,
+            surface_format,
+            &compute_view,
+            compute_texture.size(),
+            &compute_pass.moments_texture.create_view(&Default::default()),
+            &compute_pass.aov_textures.views(),
+            camera.exposure(),
+            &auto_exposure.state_buffer,
+            &color_pipeline.get_uniform(),
+            bloom_settings,
+        );
+        let denoise = DenoiseSettings::default();
+        let mut denoiser = Denoiser::new(
+            &device,
+            &compute_view,
+            &compute_pass.moments_texture,
+            &compute_pass.aov_textures,
+            &denoise,
+        );
+        denoiser.update(&queue, &denoise);
+        let temporal = Temporal::new(&device, &compute_view, &compute_pass.aov_textures);
+        let clear_flag = false;
+
+        let mut state = Self {
+            instance,
+            surface,
+            device,
+            queue,
+            config,
+            size,
+            window,
+            window_focused: true,
+            compute_texture,
+            compute_view,
+            camera,
+            camera_uniform,
+            camera_controller,
+            scene,
+            compute_pass,
+            render_pass,
+            clear_flag,
+            tonemap_sat: 1.0,
+            tonemapper: Tonemapper::default(),
+            auto_exposure,
+            color_pipeline,
+            custom_aperture,
+            lens_system,
+            bookmarks: Bookmarks::load(&scene_path),
+            camera_path: CameraPath::load(&scene_path),
+            playback_time: None,
+            sequence_frame: None,
+            modifiers: Default::default(),
+            cursor_position: Default::default(),
+            accumulation_start: Instant::now(),
+            checkpoint_path: Checkpoint::path_for(&scene_path),
+            last_checkpoint: Instant::now(),
+            pending_checkpoint: None,
+            goal,
+            goal_reached: false,
+            last_noise_check: Instant::now(),
+            denoiser,
+            denoise,
+            temporal,
+            frame_budget,
+            frame_seconds: 0.0,
+            last_passes: 0,
+            camera_moved: false,
+            lut: None,
+            lut_path: None,
+            startup_lut: args.common.lut.as_ref().map(std::path::PathBuf::from),
+            lut_log_shaper: args.common.lut_log,
+            hdr,
+            scene_path,
+        };
+        if let Some(path) = state.startup_lut.clone() {
+            state.load_lut(Some(path));
+        }
+        state.bind_display();
+        state.update_tonemap();
+        state.update_hdr();
+        if state.hdr.mode != HdrMode::Sdr {
+            state.print_hdr();
+        }
+        if args.resume {
+            match Checkpoint::load(&state.checkpoint_path) {
+                Ok(checkpoint) => state.resume(checkpoint),
+                Err(e) => eprintln!("Failed to load {}: {e}", state.checkpoint_path.display()),
+            }
+        }
+        state
+    }
+
+    fn window(&self) -> &Window {
+        &self.window
+    }
+
+    fn render(&mut self) {
+        let frame = match self.surface.get_current_texture() {
+            wgpu::CurrentSurfaceTexture::Success(frame)
+            | wgpu::CurrentSurfaceTexture::Suboptimal(frame) => frame,
+            wgpu::CurrentSurfaceTexture::Timeout | wgpu::CurrentSurfaceTexture::Occluded => return,
+            wgpu::CurrentSurfaceTexture::Outdated => {
+                self.surface.configure(&self.device, &self.config);
+                return;
+            }
+            wgpu::CurrentSurfaceTexture::Lost => {
+                self.recreate_surface();
+                return;
+            }
+            wgpu::CurrentSurfaceTexture::Validation => {
+                eprintln!("Surface validation error while acquiring the next frame");
+                return;
+            }
+        };
+
+        // Coarse blocks while navigating, full resolution once the camera rests
+        let moving = std::mem::take(&mut self.camera_moved);
+        if self.last_passes > 0 {
+            self.frame_budget.update(
+                self.frame_seconds,
+                self.compute_pass.pixel_stride,
+                self.last_passes,
+            );
+        }
+        let stride = if moving { self.frame_budget.stride() } else { 1 };
+        if stride == 1 && self.compute_pass.pixel_stride > 1 {
+            // Start over rather than keep the coarse samples in the image
+            self.compute_pass.reset();
+            self.clear_flag = true;
+        }
+        self.compute_pass.pixel_stride = stride;
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        if self.clear_flag {
+            encoder.clear_texture(
+                &self.compute_texture,
+                &wgpu::ImageSubresourceRange {
+                    aspect: wgpu::TextureAspect::All,
+                    base_mip_level: 0,
+                    mip_level_count: None,
+                    base_array_layer: 0,
+                    array_layer_count: None,
+                },
+            );
+            self.clear_flag = false;
+        }
+
+        if self.compute_pass.iteration == 0 {
+            self.accumulation_start = Instant::now();
+            self.last_noise_check = Instant::now();
+            self.goal_reached = false;
+        }
+        self.last_passes = 0;
+        if !self.goal_reached {
+            let mut passes = if moving { 1 } else { self.frame_budget.passes() };
+            let samples = match self.sequence_frame {
+                Some(_) => Some(self.camera_path.samples_per_frame),
+                None => self.goal.samples,
+            };
+            if let Some(samples) = samples {
+                passes = passes.min(samples.saturating_sub(self.compute_pass.iteration)).max(1);
+            }
+            for _ in 0..passes {
+                self.compute_pass
+                    .render(&self.device, &mut encoder, &self.size, &self.scene);
+            }
+            self.last_passes = passes;
+        }
+        if self.temporal.enabled {
+            self.temporal.run(&mut encoder);
+        }
+        if self.denoise.enabled {
+            self.denoiser.run(&mut encoder);
+        }
+        if self.auto_exposure.settings.enabled {
+            self.auto_exposure
+                .run(&self.queue, &mut encoder, self.frame_seconds);
+        }
+        if self.render_pass.bloom.settings.enabled {
+            self.render_pass.bloom.run(&self.queue, &mut encoder);
+        }
+        let error_threshold = self
+            .compute_pass
+            .adaptive_threshold
+            .or(self.goal.noise_threshold)
+            .unwrap_or(goal::DEFAULT_ADAPTIVE_THRESHOLD);
+        self.render_pass
+            .update_view(&self.queue, error_threshold, self.compute_pass.iteration);
+
+        self.render_pass.render(
+            &mut encoder,
+            &frame.texture.create_view(&Default::default()),
+        );
+
+        self.queue.submit(Some(encoder.finish()));
+        frame.present();
+
+        let adaptive = self.compute_pass.adaptive_threshold.is_some();
+        if self.sequence_frame.is_some() {
+            self.finish_sequence_frame();
+        } else if !self.goal_reached && (self.goal.is_set() || adaptive) {
+            self.check_goal();
+        }
+        if self.last_checkpoint.elapsed() >= std::time::Duration::from_secs(CHECKPOINT_INTERVAL) {
+            self.save_checkpoint();
+        }
+    }
+
+    /// Chain the film through the enabled temporal and denoising passes and
+    /// point the blit and the exposure meter at the end of the chain.
+    fn bind_display(&mut self) {
+        let temporal_view = self.temporal.output_view();
+        let film_view = if self.temporal.enabled {
+            &temporal_view
+        } else {
+            &self.compute_view
+        };
+        self.denoiser.resize(
+            &self.device,
+            film_view,
+            &self.compute_pass.moments_texture,
+            &self.compute_pass.aov_textures,
+        );
+        let source_view = if self.denoise.enabled {
+            &self.denoiser.output_view
+        } else {
+            film_view
+        };
+        self.auto_exposure
+            .resize(&self.device, source_view, self.compute_texture.size());
+        self.render_pass.resize(
+            &self.device,
+            source_view,
+            self.compute_texture.size(),
+            &self.compute_pass.moments_texture.create_view(&Default::default()),
+            &self.compute_pass.aov_textures.views(),
+        );
+    }
+
+    fn update_denoise(&mut self) {
+        self.denoiser.update(&self.queue, &self.denoise);
+        println!(
+            "Denoiser: {} passes, luminance stop at {:.1} sigma",
+            self.denoise.iterations, self.denoise.sigma_luminance
+        );
+    }
+
+    fn recreate_surface(&mut self) {
+        self.surface = self.instance.create_surface(self.window.clone()).unwrap();
+        self.surface.configure(&self.device, &self.config);
+    }
+
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+        if new_size.height > 0 && new_size.width > 0 {
+            self.size = new_size;
+            self.config.height = new_size.height;
+            self.config.width = new_size.width;
+            self.surface.configure(&self.device, &self.config);
+
+            // We need to recreate the comput and output texture on resize
+            self.compute_texture = self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("compute_texture"),
+                size: Extent3d {
+                    width: new_size.width,
+                    height: new_size.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+
+            self.compute_view = self.compute_texture.create_view(&Default::default());
+            self.scene.vispoint_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("vispoint_buffer"),
+                size: (new_size.width * new_size.height) as u64 * 64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+
+            self.compute_pass
+                .resize(&self.device, &new_size, &self.compute_view, &self.scene.vispoint_buffer);
+            self.temporal
+                .resize(&self.device, &self.compute_view, &self.compute_pass.aov_textures);
+            self.bind_display();
+
+            self.camera.set_aspect_ratio(new_size.width as f32 / new_size.height as f32);
+            self.update_camera();
+            if let Some(checkpoint) = self.pending_checkpoint.take() {
+                let header = &checkpoint.header;
+                if (header.width, header.height) == (new_size.width, new_size.height) {
+                    self.resume(checkpoint);
+                } else {
+                    println!("The window was not resized to the checkpoint's size");
+                }
+            }
+        }
+    }
+
+    fn input_device_event(&mut self, event: &DeviceEvent) -> bool {
+        if self.window_focused {
+            self.camera_controller.process_events(event);
+        }
+        false
+    }
+
+    fn input_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state: ElementState::Pressed,
+                        physical_key: PhysicalKey::Code(code),
+                        repeat,
+                        ..
+                    },
+                ..
+            } => {
+                if let Some(&(_, hotkey)) = HOTKEYS.iter().find(|(key, _)| key == code) {
+                    if !*repeat || hotkey.repeats() {
+                        self.hotkey(hotkey, *code);
+                    }
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let scroll = match delta {
+                    winit::event::MouseScrollDelta::LineDelta(_, y) => *y,
+                    winit::event::MouseScrollDelta::PixelDelta(pos) => pos.y as f32 * 0.01,
+                };
+                if self.camera.projection.is_fisheye() {
+                    self.camera.fisheye_fov =
+                        (self.camera.fisheye_fov - scroll * 2.0).clamp(30.0, 360.0);
+                } else {
+                    let mut new_vfov = self.camera.vfov - scroll * 0.5;
+                    new_vfov = new_vfov.clamp(10.0, 170.0);
+                    self.camera.set_vfov(new_vfov);
+                    println!("Focal length: {:.1}mm", self.camera.focal_length());
+                }
+                self.update_camera();
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+            }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Right,
+                ..
+            } => {
+                let pixel = self.cursor_pixel();
+                if let Some(depth) =
+                    self.compute_pass
+                        .probe_depth(&self.device, &self.queue, &self.scene, pixel)
+                {
+                    println!("Focus distance: {depth:.3}");
+                    self.camera.focus_distance = depth;
+                    self.update_lens();
+                    self.update_camera();
+                }
+            }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Middle,
+                ..
+            } => {
+                // Orbit around the surface under the cursor
+                let pixel = self.cursor_pixel();
+                if let Some(depth) =
+                    self.compute_pass
+                        .probe_depth(&self.device, &self.queue, &self.scene, pixel)
+                {
+                    let uv = [
+                        (pixel[0] as f32 + 0.5) / self.size.width as f32,
+                        (pixel[1] as f32 + 0.5) / self.size.height as f32,
+                    ];
+                    let pivot = self.camera.point_at(uv, depth);
+                    println!("Pivot: ({:.3}, {:.3}, {:.3})", pivot.x, pivot.y, pivot.z);
+                    self.camera_controller.pivot = Some(pivot);
+                }
+            }
+            WindowEvent::MouseInput {
+                state: winit::event::ElementState::Pressed,
+                button: winit::event::MouseButton::Left,
+                ..
+            } => {
+                self.window_focused = true;
+                self.window
+                    .set_cursor_grab(CursorGrabMode::Confined)
+                    .unwrap();
+                self.window.set_cursor_visible(false);
+            }
+            WindowEvent::Resized(physical_size) => {
+                self.resize(*physical_size);
+            }
+            WindowEvent::ScaleFactorChanged { .. } => {
+                self.resize(self.window.inner_size());
+            }
+            _ => {}
 
+        }
+    }
+
+    /// Act on a key of `HOTKEYS`; `code` tells apart the keys of paired actions.
+    fn hotkey(&mut self, hotkey: Hotkey, code: KeyCode) {
+        match hotkey {
+            Hotkey::ReleaseCursor => {
+                self.window_focused = false;
+                self.window
+                    .set_cursor_grab(CursorGrabMode::None)
+                    .unwrap();
+                self.window.set_cursor_visible(true);
+            }
+            Hotkey::Brighten => {
+                // Brighten by a third of a stop
+                if self.auto_exposure.settings.enabled {
+                    self.auto_exposure.settings.compensation += 1.0 / 3.0;
+                    self.update_auto_exposure();
+                } else {
+                    self.camera.shutter_time *= 2f32.powf(1.0 / 3.0);
+                    self.update_exposure();
+                }
+            }
+            Hotkey::Darken => {
+                if self.auto_exposure.settings.enabled {
+                    self.auto_exposure.settings.compensation -= 1.0 / 3.0;
+                    self.update_auto_exposure();
+                } else {
+                    self.camera.shutter_time /= 2f32.powf(1.0 / 3.0);
+                    self.update_exposure();
+                }
+            }
+            Hotkey::Iso => {
+                let stop = if code == KeyCode::PageUp { 1.0 / 3.0 } else { -1.0 / 3.0 };
+                self.camera.iso = (self.camera.iso * 2f32.powf(stop)).clamp(25.0, 409600.0);
+                self.update_exposure();
+            }
+            Hotkey::FNumber => {
+                // f-numbers go up by sqrt(2) per stop; Home opens the aperture
+                let stop = if code == KeyCode::Home { -1.0 / 3.0 } else { 1.0 / 3.0 };
+                self.camera.f_number = (self.camera.f_number * 2f32.powf(stop / 2.0)).clamp(0.5, 64.0);
+                self.update_exposure();
+            }
+            Hotkey::MoreSaturation => {
+                self.tonemap_sat = (self.tonemap_sat * 20.0 + 1.0) / 20.0;
+                if self.tonemap_sat > 3.0 { self.tonemap_sat = 3.0; }
+                self.update_tonemap();
+            }
+            Hotkey::LessSaturation => {
+                self.tonemap_sat = (self.tonemap_sat * 20.0 - 1.0) / 20.0;
+                if self.tonemap_sat < 0.0 { self.tonemap_sat = 0.0; }
+                self.update_tonemap();
+            }
+            Hotkey::Observer => {
+                let observer = color::next(&Observer::ALL, self.color_pipeline.observer);
+                self.color_pipeline.observer = observer;
+                println!("Observer: {}", observer.name());
+                self.compute_pass.set_observer(&self.queue, observer);
+                self.temporal.invalidate();
+                self.clear_flag = true;
+            }
+            Hotkey::OutputSpace => {
+                let output = color::next(&ColorSpace::ALL, self.color_pipeline.output);
+                self.color_pipeline.output = output;
+                println!("Output colour space: {}", output.name());
+                self.render_pass
+                    .update_color(&self.queue, &self.color_pipeline.get_uniform());
+                self.update_hdr();
+            }
+            Hotkey::AdoptedWhite => {
+                // Shift toggles stereo, otherwise V cycles the adopted white presets
+                if self.modifiers.shift_key() {
+                    self.camera.stereo = !self.camera.stereo;
+                    println!(
+                        "Stereo: {}",
+                        if self.camera.stereo { "side by side" } else { "off" }
+                    );
+                    self.update_camera();
+                } else {
+                    let white = color::next(&WhitePoint::ALL, self.camera.adopted_white());
+                    self.camera.white_preset = Some(white);
+                    println!("Adopted white: {}", white.name);
+                    self.update_adopted_white();
+                }
+            }
+            Hotkey::WhiteBalance => {
+                let step = if code == KeyCode::Period { 100.0 } else { -100.0 };
+                self.camera.white_balance = (self.camera.white_balance + step).clamp(1000.0, 15000.0);
+                self.update_white_balance();
+            }
+            Hotkey::Tint => {
+                let step = if code == KeyCode::Quote { 0.001 } else { -0.001 };
+                self.camera.tint = (self.camera.tint + step).clamp(-0.05, 0.05);
+                self.update_white_balance();
+            }
+            Hotkey::ApertureRadius => {
+                // Half a stop per press; small apertures snap back to a pinhole
+                let radius = if code == KeyCode::KeyM {
+                    (self.camera.aperture_radius * std::f32::consts::SQRT_2).max(0.005)
+                } else {
+                    self.camera.aperture_radius / std::f32::consts::SQRT_2
+                };
+                self.camera.aperture_radius = if radius < 0.005 { 0.0 } else { radius.min(2.0) };
+                println!("Aperture radius: {:.3}", self.camera.aperture_radius);
+                self.update_camera();
+            }
+            Hotkey::ApertureShape => {
+                let next = match &self.camera.aperture {
+                    ApertureShape::Circular => ApertureShape::Polygon(5),
+                    ApertureShape::Polygon(5) => ApertureShape::Polygon(6),
+                    ApertureShape::Polygon(6) => ApertureShape::Polygon(8),
+                    ApertureShape::Polygon(_) => self
+                        .custom_aperture
+                        .clone()
+                        .unwrap_or(ApertureShape::Circular),
+                    ApertureShape::Custom { .. } => ApertureShape::Circular,
+                };
+                match &next {
+                    ApertureShape::Circular => println!("Aperture: circular"),
+                    ApertureShape::Polygon(blades) => println!("Aperture: {blades} blades"),
+                    ApertureShape::Custom { .. } => println!("Aperture: res/aperture.pgm"),
+                }
+                self.compute_pass
+                    .update_aperture(&self.device, next.samples());
+                self.camera.aperture = next;
+                self.update_camera();
+            }
+            Hotkey::RealisticLens => {
+                self.camera.projection = match (&self.camera.projection, &self.lens_system) {
+                    (Projection::Realistic(_), _) => {
+                        println!("Projection: perspective");
+                        Projection::Perspective
+                    }
+                    (_, Some(lens)) => {
+                        println!("Projection: realistic lens (res/lens.dat)");
+                        Projection::Realistic(lens.clone())
+                    }
+                    (_, None) => {
+                        println!("No lens prescription at res/lens.dat");
+                        return;
+                    }
+                };
+                self.update_lens();
+                self.update_camera();
+            }
+            Hotkey::Projection => {
+                self.camera.projection = self.camera.projection.next();
+                println!("Projection: {}", self.camera.projection.name());
+                self.update_lens();
+                self.update_camera();
+            }
+            Hotkey::FilmFit => {
+                self.camera.set_fit(color::next(&FilmFit::ALL, self.camera.fit));
+                println!("Film fit: {}", self.camera.fit.name());
+                self.update_camera();
+            }
+            Hotkey::MotionBlur => {
+                // Toggle a 180 degree shutter at the camera path's frame rate
+                self.camera.shutter_interval = if self.camera.shutter_interval[1] > 0.0 {
+                    [0.0, 0.0]
+                } else {
+                    [0.0, 0.5 / self.camera_path.fps]
+                };
+                let [open, close] = self.camera.shutter_interval;
+                println!(
+                    "Motion blur shutter: {:.4}s to {:.4}s at t = {:.2}s",
+                    open, close, self.camera.time
+                );
+                self.update_camera();
+            }
+            Hotkey::AdaptiveSampling => {
+                let threshold = match self.compute_pass.adaptive_threshold {
+                    Some(_) => None,
+                    None => Some(
+                        self.goal
+                            .adaptive_threshold
+                            .unwrap_or(goal::DEFAULT_ADAPTIVE_THRESHOLD),
+                    ),
+                };
+                self.compute_pass.set_adaptive(threshold);
+                // A render stopped only by convergence picks up where it left off
+                if !self.goal.is_set() {
+                    self.goal_reached = false;
+                }
+                match threshold {
+                    Some(threshold) => println!(
+                        "Adaptive sampling on, stopping pixels at {:.1}% error",
+                        threshold * 100.0
+                    ),
+                    None => println!("Adaptive sampling off"),
+                }
+            }
+            Hotkey::View => {
+                self.render_pass.view = self.render_pass.view.next();
+                println!("Showing the {}", self.render_pass.view.name());
+            }
+            Hotkey::Temporal => {
+                self.temporal.enabled = !self.temporal.enabled;
+                self.temporal.invalidate();
+                self.bind_display();
+                println!(
+                    "Temporal reprojection {}",
+                    if self.temporal.enabled { "on" } else { "off" }
+                );
+            }
+            Hotkey::Denoiser => {
+                self.denoise.enabled = !self.denoise.enabled;
+                self.bind_display();
+                println!("Denoiser {}", if self.denoise.enabled { "on" } else { "off" });
+            }
+            Hotkey::Tonemapper => {
+                self.tonemapper = self.tonemapper.next();
+                self.update_tonemap();
+                println!("Tonemapper: {}", self.tonemapper.name());
+            }
+            Hotkey::Lut => {
+                if self.modifiers.shift_key() {
+                    self.lut_log_shaper = !self.lut_log_shaper;
+                    self.load_lut(self.lut_path.clone());
+                } else {
+                    self.load_lut(self.next_lut());
+                }
+            }
+            Hotkey::AutoExposure => {
+                self.auto_exposure.settings.enabled = !self.auto_exposure.settings.enabled;
+                self.auto_exposure.reset();
+                self.update_tonemap();
+                self.update_auto_exposure();
+            }
+            Hotkey::Bloom => {
+                let bloom = &mut self.render_pass.bloom.settings;
+                bloom.enabled = !bloom.enabled;
+                self.update_bloom();
+            }
+            Hotkey::BloomLevels => {
+                // Shift changes the threshold, otherwise the strength
+                let raise = code == KeyCode::F3;
+                let bloom = &mut self.render_pass.bloom.settings;
+                if self.modifiers.shift_key() {
+                    bloom.threshold = if raise {
+                        (bloom.threshold * std::f32::consts::SQRT_2).max(0.5)
+                    } else if bloom.threshold < 0.25 {
+                        0.0
+                    } else {
+                        bloom.threshold / std::f32::consts::SQRT_2
+                    };
+                } else {
+                    let strength = if raise {
+                        bloom.strength * std::f32::consts::SQRT_2
+                    } else {
+                        bloom.strength / std::f32::consts::SQRT_2
+                    };
+                    bloom.strength = strength.clamp(0.005, 1.0);
+                }
+                self.update_bloom();
+            }
+            Hotkey::HdrLevels => {
+                // Thirds of a stop; shift changes the peak, otherwise paper white
+                let step = if code == KeyCode::F6 { 2f32.cbrt() } else { 1.0 / 2f32.cbrt() };
+                if self.hdr.mode == HdrMode::Sdr {
+                    println!("Paper white and peak only apply to HDR output");
+                } else {
+                    if self.modifiers.shift_key() {
+                        self.hdr.peak = (self.hdr.peak * step).clamp(100.0, 10000.0);
+                    } else {
+                        self.hdr.paper_white = (self.hdr.paper_white * step).clamp(10.0, 1000.0);
+                    }
+                    self.update_hdr();
+                    self.print_hdr();
+                }
+            }
+            Hotkey::DenoiseStrength => {
+                // Shift changes the filter radius, otherwise its strength
+                let stronger = code == KeyCode::KeyI;
+                if self.modifiers.shift_key() {
+                    self.denoise.iterations = if stronger {
+                        (self.denoise.iterations + 1).min(denoise::MAX_ITERATIONS)
+                    } else {
+                        self.denoise.iterations.saturating_sub(1).max(1)
+                    };
+                } else {
+                    let sigma = if stronger {
+                        self.denoise.sigma_luminance * std::f32::consts::SQRT_2
+                    } else {
+                        self.denoise.sigma_luminance / std::f32::consts::SQRT_2
+                    };
+                    self.denoise.sigma_luminance = sigma.clamp(0.25, 64.0);
+                }
+                self.update_denoise();
+            }
+            Hotkey::Screenshot => self.save_screenshot(),
+            Hotkey::Bookmark(slot) => {
+                // Alt+digit stores the view, the digit alone recalls it
+                if self.modifiers.alt_key() {
+                    match self.bookmarks.set(slot, Bookmark::from_camera(&self.camera)) {
+                        Ok(()) => println!(
+                            "Stored bookmark {slot} in {}",
+                            self.bookmarks.path().display()
+                        ),
+                        Err(e) => eprintln!("Failed to save bookmarks: {e}"),
+                    }
+                } else if let Some(bookmark) = self.bookmarks.get(slot).copied() {
+                    println!("Recalled bookmark {slot}");
+                    self.apply_view(&bookmark);
+                    self.update_camera();
+                } else {
+                    println!("Bookmark {slot} is empty");
+                }
+            }
+            Hotkey::Keyframe => match self.camera_path.push(Bookmark::from_camera(&self.camera)) {
+                Ok(time) => println!(
+                    "Keyframe at {time:.1}s saved to {}",
+                    self.camera_path.path().display()
+                ),
+                Err(e) => eprintln!("Failed to save camera path: {e}"),
+            },
+            Hotkey::Playback => {
+                self.playback_time = match self.playback_time {
+                    None if self.camera_path.keyframes.len() > 1 => {
+                        println!("Playing camera path ({:.1}s)", self.camera_path.duration());
+                        Some(0.0)
+                    }
+                    None => {
+                        println!("The camera path needs at least two keyframes");
+                        None
+                    }
+                    Some(_) => {
+                        println!("Stopped camera path");
+                        None
+                    }
+                };
+            }
+            Hotkey::Sequence => {
+                if self.sequence_frame.take().is_some() {
+                    println!("Cancelled sequence render");
+                } else if self.camera_path.keyframes.is_empty() {
+                    println!("The camera path has no keyframes");
+                } else if let Err(e) = std::fs::create_dir_all(&self.camera_path.output_dir) {
+                    eprintln!("Failed to create {}: {e}", self.camera_path.output_dir);
+                } else {
+                    println!(
+                        "Rendering {} frames at {} spp to {}",
+                        self.camera_path.frame_count(),
+                        self.camera_path.samples_per_frame,
+                        self.camera_path.output_dir
+                    );
+                    self.playback_time = None;
+                    self.start_sequence_frame(0);
+                }
+            }
+            // Only read as a modifier of the bookmark digits
+            Hotkey::StoreBookmark => {}
+        }
+    }
+
+    fn update_exposure(&mut self) {
+        println!(
+            "Exposure: ISO {:.0}, {:.4}s, f/{:.1} (EV100 {:.2})",
+            self.camera.iso,
+            self.camera.shutter_time,
+            self.camera.f_number,
+            self.camera.ev100()
+        );
+        self.update_tonemap();
+    }
+
+    /// The LUT after the current one: the one given on the command line,
+    /// then those in LUT_DIR, then none.
+    fn next_lut(&self) -> Option<std::path::PathBuf> {
+        let mut paths: Vec<_> = self.startup_lut.iter().cloned().collect();
+        paths.extend(
+            Lut::find_all()
+                .into_iter()
+                .filter(|path| Some(path) != self.startup_lut.as_ref()),
+        );
+        let next = match &self.lut_path {
+            Some(current) => paths.iter().position(|path| path == current).map_or(0, |i| i + 1),
+            None => 0,
+        };
+        paths.get(next).cloned()
+    }
+
+    /// Grade through the LUT at `path`, reading it afresh so edits show up,
+    /// or stop grading.
+    fn load_lut(&mut self, path: Option<std::path::PathBuf>) {
+        self.lut = path.as_ref().and_then(|path| {
+            Lut::load(path)
+                .map_err(|e| eprintln!("Failed to load {}: {e}", path.display()))
+                .ok()
+        });
+        if let Some(lut) = &mut self.lut {
+            lut.log_shaper = self.lut_log_shaper;
+        }
+        self.lut_path = self.lut.is_some().then_some(path).flatten();
+        self.render_pass
+            .set_lut(&self.device, &self.queue, self.lut.as_ref());
+        self.bind_display();
+        match &self.lut_path {
+            Some(path) => println!(
+                "LUT: {}{}",
+                path.display(),
+                if self.lut_log_shaper { " (log shaper)" } else { "" }
+            ),
+            None => println!("LUT off"),
+        }
+    }
+
+    fn update_auto_exposure(&self) {
+        let settings = &self.auto_exposure.settings;
+        if settings.enabled {
+            println!("Auto exposure on, compensation {:+.2} EV", settings.compensation);
+        } else {
+            println!("Auto exposure off");
+        }
+    }
+
+    fn update_hdr(&self) {
+        self.render_pass
+            .update_hdr(&self.queue, &self.hdr.uniform(self.color_pipeline.output));
+    }
+
+    fn print_hdr(&self) {
+        println!(
+            "HDR output: {}, paper white {:.0} nits, peak {:.0} nits, \
+             without the tonemapper and saturation",
+            self.hdr.mode.name(),
+            self.hdr.paper_white,
+            self.hdr.peak
+        );
+    }
+
+    fn update_bloom(&self) {
+        let settings = &self.render_pass.bloom.settings;
+        if settings.enabled {
+            println!(
+                "Bloom on, strength {:.3}, threshold {:.2}",
+                settings.strength, settings.threshold
+            );
+        } else {
+            println!("Bloom off");
+        }
+    }
+
+    fn tonemap_uniform(&self) -> TonemapUniform {
+        let mut uniform = TonemapUniform::new(
+            self.tonemapper,
+            self.camera.exposure(),
+            self.tonemap_sat,
+            tonemap::DEFAULT_WHITE,
+        );
+        uniform.auto_exposure = self.auto_exposure.settings.enabled as u32;
+        uniform
+    }
+
+    fn update_tonemap(&mut self) {
+        self.render_pass
+            .update_tonemap(&self.queue, self.tonemap_uniform());
+    }
+
+    fn update_white_balance(&mut self) {
+        println!(
+            "White balance: {:.0}K, tint {:+.3}",
+            self.camera.white_balance, self.camera.tint
+        );
+        self.camera.white_preset = None;
+        self.update_adopted_white();
+    }
+
+    fn update_adopted_white(&mut self) {
+        self.color_pipeline.adopted_white = self.camera.adopted_white();
+        self.render_pass
+            .update_color(&self.queue, &self.color_pipeline.get_uniform());
+    }
+
+    /// Restore a bookmark or camera path view, along with the lens, aperture,
+    /// exposure and white it carries, and drop any motion in progress.
+    fn apply_view(&mut self, view: &Bookmark) {
+        view.apply(
+            &mut self.camera,
+            self.lens_system.as_ref(),
+            self.custom_aperture.as_ref(),
+        );
+        self.camera_controller.reset();
+        self.compute_pass
+            .update_aperture(&self.device, self.camera.aperture.samples());
+        self.update_lens();
+        self.update_tonemap();
+        self.update_adopted_white();
+    }
+
+    fn update(&mut self, duration: u128) {
+        self.frame_seconds = duration as f32 * 1e-6;
+        if self.sequence_frame.is_some() {
+            // The frames are accumulated by `render` like any other image
+            return;
+        }
+        if let Some(time) = self.playback_time {
+            // Loop the path until playback is stopped
+            let time = (time + duration as f32 * 1e-6) % self.camera_path.duration().max(1e-3);
+            self.playback_time = Some(time);
+            if let Some(view) = self.camera_path.sample(time) {
+                self.apply_view(&view);
+                self.camera.time = time;
+                self.move_camera();
+            }
+            return;
+        }
+        let was_updated = self
+            .camera_controller
+            .update_camera(&mut self.camera, duration);
+        if was_updated {
+            self.move_camera();
+        }
+    }
+
+    /// Point the camera at `frame` of the camera path and start accumulating
+    /// it, or end the sequence after the last frame.
+    fn start_sequence_frame(&mut self, frame: u32) {
+        if frame >= self.camera_path.frame_count() {
+            println!("Sequence finished");
+            self.sequence_frame = None;
+            return;
+        }
+        let time = frame as f32 / self.camera_path.fps;
+        let Some(view) = self.camera_path.sample(time) else {
+            self.sequence_frame = None;
+            return;
+        };
+        self.sequence_frame = Some(frame);
+        self.apply_view(&view);
+        self.camera.time = time;
+        self.update_camera();
+        // Every sample counts towards the frame, so skip the preview
+        self.compute_pass.preview_next_frame = false;
+    }
+
+    /// Once the sequence frame has the path's sample count, write it to the
+    /// output directory as linear radiance and move on to the next one.
+    fn finish_sequence_frame(&mut self) {
+        let Some(frame) = self.sequence_frame else {
+            return;
+        };
+        let path = &self.camera_path;
+        if self.compute_pass.iteration < path.samples_per_frame {
+            return;
+        }
+        let frame_count = path.frame_count();
+        let file = format!("{}/frame_{:04}.pfm", path.output_dir, frame + 1);
+
+        let Some(pixels) = export::read_texture(&self.device, &self.queue, &self.compute_texture)
+        else {
+            eprintln!("Failed to read back frame {}", frame + 1);
+            self.sequence_frame = None;
+            return;
+        };
+        let rgb = export::resolve(&pixels, self.color_pipeline.xyz_to_output());
+        match export::write_pfm(&file, self.size.width, self.size.height, &rgb) {
+            Ok(()) => println!("Frame {}/{frame_count}: {file}", frame + 1),
+            Err(e) => eprintln!("Failed to write {file}: {e}"),
+        }
+        self.start_sequence_frame(frame + 1);
+    }
+
+    /// Save the accumulation of a render goal in progress, so that a restart
+    /// with `--resume` can pick it up.
+    fn save_checkpoint(&mut self) {
+        self.last_checkpoint = Instant::now();
+        // Sequence frames are short and not worth resuming
+        if self.compute_pass.iteration == 0
+            || self.sequence_frame.is_some()
+            || !self.goal.is_set()
+            || self.goal_reached
+        {
+            return;
+        }
+        let saved = Checkpoint::capture(
+            &self.device,
+            &self.queue,
+            &self.compute_pass,
+            &self.compute_texture,
+            &self.scene.vispoint_buffer,
+            &self.camera,
+            &self.color_pipeline,
+        )
+        .ok_or_else(|| "failed to read back the render".to_string())
+        .and_then(|checkpoint| checkpoint.save(&self.checkpoint_path));
+        match saved {
+            Ok(()) => println!(
+                "Checkpoint at {} spp saved to {}",
+                self.compute_pass.iteration,
+                self.checkpoint_path.display()
+            ),
+            Err(e) => eprintln!("Failed to save {}: {e}", self.checkpoint_path.display()),
+        }
+    }
+
+    /// Continue the accumulation in `checkpoint`, first resizing the window
+    /// to match it if needed.
+    fn resume(&mut self, checkpoint: Checkpoint) {
+        let header = &checkpoint.header;
+        if let Err(e) = header.check(&self.color_pipeline) {
+            eprintln!("Cannot resume {}: {e}", self.checkpoint_path.display());
+            return;
+        }
+        if (header.width, header.height) != (self.size.width, self.size.height) {
+            let size = winit::dpi::PhysicalSize::new(header.width, header.height);
+            match self.window.request_inner_size(size) {
+                // Resized immediately, without a later event
+                Some(actual) if actual == size => {
+                    self.pending_checkpoint = Some(checkpoint);
+                    self.resize(actual);
+                }
+                Some(_) => println!(
+                    "The window cannot be resized to the checkpoint's {}x{}",
+                    size.width, size.height
+                ),
+                None => self.pending_checkpoint = Some(checkpoint),
+            }
+            return;
+        }
+        checkpoint.restore(
+            &self.device,
+            &self.queue,
+            &mut self.compute_pass,
+            &self.compute_texture,
+            &self.scene.vispoint_buffer,
+            &mut self.camera,
+        );
+        self.camera_controller.reset();
+        self.update_tonemap();
+        self.update_adopted_white();
+        self.camera_uniform = self.camera.get_uniform();
+        self.clear_flag = false;
+        println!(
+            "Resumed {} at {} spp",
+            self.checkpoint_path.display(),
+            header.iteration
+        );
+    }
+
+    /// Stop accumulating and export once the render goal is met.
+    fn check_goal(&mut self) {
+        let mut noise = None;
+        let mut active_pixels = None;
+        if self.last_noise_check.elapsed().as_secs_f32() >= self.goal.noise_check_interval {
+            if self.goal.noise_threshold.is_some() {
+                noise = self.compute_pass.noise_estimate(&self.device, &self.queue);
+            }
+            active_pixels = self.compute_pass.active_pixels(&self.device, &self.queue);
+            self.last_noise_check = Instant::now();
+        }
+        let seconds = self.accumulation_start.elapsed().as_secs_f32();
+        let Some(reason) =
+            self.goal
+                .reached(self.compute_pass.iteration, seconds, noise, active_pixels)
+        else {
+            return;
+        };
+        println!("Render goal reached: {reason}");
+        self.goal_reached = true;
+        // Adaptive sampling toggled on by hand only stops, without exporting
+        if !self.goal.is_set() {
+            return;
+        }
+        let paths = self
+            .goal
+            .output_paths(&self.scene_path, self.compute_pass.iteration);
+        self.export_images(&paths);
+    }
+
+    /// Write the current accumulation as a linear EXR and a tonemapped PNG.
+    fn save_screenshot(&self) {
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let paths =
+            ["exr", "png"].map(|extension| format!("{SCREENSHOT_DIR}/render_{stamp}.{extension}"));
+        self.export_images(&paths);
+    }
+
+    /// Write the current accumulation to each of `paths`, the extension
+    /// picking the format.
+    fn export_images(&self, paths: &[String]) {
+        let source = if self.denoise.enabled {
+            &self.denoiser.output
+        } else {
+            &self.compute_texture
+        };
+        let Some(pixels) = export::read_texture(&self.device, &self.queue, source) else {
+            eprintln!("Failed to read back the render");
+            return;
+        };
+        let rgb = export::resolve(&pixels, self.color_pipeline.xyz_to_output());
+        let mut metadata = export::render_metadata(
+            &self.camera,
+            self.color_pipeline.adopted_white,
+            self.compute_pass.iteration,
+            self.accumulation_start.elapsed().as_secs_f32(),
+        );
+        if self.denoise.enabled {
+            metadata.push(self.denoise.metadata());
+        }
+        let mut tonemap = self.tonemap_uniform();
+        if self.auto_exposure.settings.enabled {
+            match self.auto_exposure.key(&self.device, &self.queue) {
+                Some(key) => tonemap.key = key,
+                None => eprintln!("Failed to read back the auto exposure"),
+            }
+        }
+        metadata.push(("tonemapper".into(), self.tonemapper.name().into()));
+        if let Some(path) = &self.lut_path {
+            metadata.push(("lut".into(), path.display().to_string()));
+        }
+        let bloom = &self.render_pass.bloom;
+        let glare = if bloom.settings.enabled {
+            let glare = export::read_texture(&self.device, &self.queue, bloom.glare());
+            if glare.is_none() {
+                eprintln!("Failed to read back the bloom glare");
+            }
+            glare.map(|pixels| pixels.iter().map(|p| [p[0], p[1], p[2]]).collect::<Vec<_>>())
+        } else {
+            None
+        };
+        if glare.is_some() {
+            metadata.push((
+                "bloom".into(),
+                format!(
+                    "strength {} threshold {}",
+                    bloom.settings.strength, bloom.settings.threshold
+                ),
+            ));
+        }
+        let settings = export::ExportSettings {
+            tonemap,
+            lut: self.lut.clone(),
+            glare,
+            color: self.color_pipeline,
+            png_bits: 8,
+            metadata,
+            // Only EXRs have room for the AOV layers
+            aovs: paths
+                .iter()
+                .any(|path| export::ImageFormat::from_path(path) == Some(export::ImageFormat::Exr))
+                .then(|| {
+                    AovImages::read(
+                        &self.device,
+                        &self.queue,
+                        &self.compute_pass.aov_textures,
+                        &self.compute_pass.moments_texture,
+                    )
+                })
+                .flatten(),
+        };
+        for file in paths {
+            let dir = std::path::Path::new(file).parent();
+            if let Some(Err(e)) = dir.map(std::fs::create_dir_all) {
+                eprintln!("Failed to create the directory for {file}: {e}");
+                continue;
+            }
+            match export::write_image(file, self.size.width, self.size.height, &rgb, &settings) {
+                Ok(()) => println!("Saved {file} ({} spp)", self.compute_pass.iteration),
+                Err(e) => eprintln!("Failed to write {file}: {e}"),
+            }
+        }
+    }
+
+    /// Whether nothing on screen can change until the next input, so the
+    /// event loop may sleep.
+    fn is_idle(&self) -> bool {
+        self.goal_reached
+            && !self.clear_flag
+            && self.playback_time.is_none()
+            && self.sequence_frame.is_none()
+    }
+
+    /// Pixel under the cursor, or the centre of the view while flying.
+    fn cursor_pixel(&self) -> [u32; 2] {
+        if self.window_focused {
+            [self.size.width / 2, self.size.height / 2]
+        } else {
+            [
+                (self.cursor_position.x.max(0.0) as u32).min(self.size.width - 1),
+                (self.cursor_position.y.max(0.0) as u32).min(self.size.height - 1),
+            ]
+        }
+    }
+
+    /// Focus the realistic lens on the camera's focus distance and upload it.
+    fn update_lens(&mut self) {
+        if let Projection::Realistic(lens) = &mut self.camera.projection {
+            if let Err(e) = lens.focus(self.camera.focus_distance) {
+                eprintln!("Failed to focus lens: {e}");
+            }
+        }
+        self.compute_pass
+            .update_lens(&self.device, &self.camera.projection.lens_elements());
+    }
+
+    fn update_camera(&mut self) {
+        self.temporal.invalidate();
+        self.clear_flag = true;
+        self.camera_uniform = self.camera.get_uniform();
+        self.compute_pass.update(&self.queue, self.camera_uniform);
+    }
+
+    /// Like `update_camera`, but for navigation: the last image is
+    /// reprojected into the new view rather than starting from nothing.
+    fn move_camera(&mut self) {
+        self.camera_moved = true;
+        if !self.temporal.enabled || !self.camera.supports_reprojection() {
+            self.update_camera();
+            return;
+        }
+        let previous = self.camera_uniform;
+        self.clear_flag = true;
+        self.camera_uniform = self.camera.get_uniform();
+        self.temporal
+            .camera_moved(&self.queue, previous, self.camera_uniform);
+        self.compute_pass.update(&self.queue, self.camera_uniform);
+        // The history stands in for the preview, so trace at full depth
+        self.compute_pass.preview_next_frame = false;
+    }
+}
+
+pub struct Scene {
+    sphere_bind_group_layout: wgpu::BindGroupLayout,
+    sphere_bind_group: wgpu::BindGroup,
+    mesh_bind_group_layout: wgpu::BindGroupLayout,
+    mesh_bind_group: wgpu::BindGroup,
+    material_bind_group_layout: wgpu::BindGroupLayout,
+    material_bind_group: wgpu::BindGroup,
+    bvh_bind_group_layout: wgpu::BindGroupLayout,
+    bvh_bind_group: wgpu::BindGroup,
+    pub light_bind_group_layout: wgpu::BindGroupLayout,
+    pub light_bind_group: wgpu::BindGroup,
+    pub vispoint_buffer: wgpu::Buffer,
+}
+
+impl Scene {
+    /// Build the demo scene around the mesh at `mesh_path`.
+    async fn new(
+        device: &wgpu::Device,
+        size: &winit::dpi::PhysicalSize<u32>,
+        mesh_path: &str,
+    ) -> Self {
         let mat0 = material::GpuMaterial::diffuse([0.8, 0.8, 0.8]);
         let mat1 = material::GpuMaterial::diffuse([0.2, 0.85, 0.2]);
         let mat2 = material::GpuMaterial::dielectric(1.5, 0.01);
@@ -234,41 +1746,94 @@ impl State {
             instance::Sphere::new(0, 1000.0, cgmath::vec3(0.0, -1000.0, 0.0), cgmath::Deg(0.0));
         let sphere3 = instance::Sphere::new(2, 1.0, cgmath::vec3(0.0, 1.0, 1.0), cgmath::Deg(0.0));
 
+        // Keys of all moving instances, from the motion file next to the mesh
+        let motion = SceneMotion::load(mesh_path);
+        let mut motion_keys = vec![];
+        let mut spheres = [sphere1, sphere2, sphere3];
+        for (&index, keyframes) in &motion.spheres {
+            let Some(sphere) = spheres.get_mut(index) else {
+                eprintln!("The scene has no sphere {index} to move");
+                continue;
+            };
+            let keys = SceneMotion::keys(keyframes);
+            sphere.set_motion(motion_keys.len() as u32, keys.len() as u32);
+            motion_keys.extend(keys);
+        }
+
         let sphere_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("sphere_buffer"),
-            contents: bytemuck::cast_slice(&[sphere1, sphere2, sphere3]),
+            contents: bytemuck::cast_slice(&spheres),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
 
         let sphere_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("sphere_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
-            });
-
-        let sphere_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("sphere_bind_group"),
-            layout: &sphere_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: sphere_buffer.as_entire_binding(),
-            }],
-        });
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+            });
 
         let mut obj_model = Mesh::new();
         obj_model.material_id = 3;
         obj_model.translation = cgmath::vec3(0.0, 3.0, 5.0);
         obj_model.scale = 0.5;
-        obj_model.load_obj("res/glass.obj").await;
+        obj_model.load_obj(mesh_path).await;
+        obj_model.motion = SceneMotion::keys(&motion.mesh);
+
+        let bvh = BVH::build(&obj_model, 2);
+        let mesh_instance = GpuMeshInstance::new(&obj_model, &bvh, motion_keys.len() as u32);
+        motion_keys.extend_from_slice(&obj_model.motion);
+        if motion_keys.is_empty() {
+            // Storage buffers cannot be empty; no instance reads this key
+            let rest = cgmath::Quaternion::one();
+            motion_keys.push(MotionKey::new(0.0, cgmath::vec3(0.0, 0.0, 0.0), rest));
+        }
+
+        let motion_key_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("motion_key_buffer"),
+            contents: bytemuck::cast_slice(&motion_keys),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let mesh_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("mesh_instance_buffer"),
+            contents: bytemuck::cast_slice(&[mesh_instance]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sphere_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("sphere_bind_group"),
+            layout: &sphere_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: sphere_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: motion_key_buffer.as_entire_binding(),
+                },
+            ],
+        });
 
         let position_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("position_buffer"),
@@ -305,6 +1870,16 @@ impl State {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
@@ -320,6 +1895,10 @@ impl State {
                     binding: 1,
                     resource: index_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: mesh_instance_buffer.as_entire_binding(),
+                },
             ],
         });
 
@@ -347,8 +1926,6 @@ impl State {
             }],
         });
 
-        let bvh = BVH::build(&obj_model, 2);
-
         let bvh_node_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("bvh_node_buffer"),
             contents: bytemuck::cast_slice(&bvh.nodes),
@@ -442,11 +2019,13 @@ impl State {
         let vispoint_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("vispoint_buffer"),
             size: (size.width * size.height) as u64 * 64,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
 
-        let scene = Scene {
+        Scene {
             sphere_bind_group_layout,
             sphere_bind_group,
             mesh_bind_group_layout,
@@ -458,255 +2037,156 @@ impl State {
             light_bind_group_layout,
             light_bind_group,
             vispoint_buffer,
-        };
-
-        let compute_pass = ComputePass::new(&device, &size, &compute_view, &camera_uniform, &scene);
-        let render_pass = RenderPass::new(&device, surface_format, &compute_view);
-        let clear_flag = false;
-
-        Self {
-            instance,
-            surface,
-            device,
-            queue,
-            config,
-            size,
-            window,
-            window_focused: true,
-            compute_texture,
-            compute_view,
-            camera,
-            camera_uniform,
-            camera_controller,
-            scene,
-            compute_pass,
-            render_pass,
-            clear_flag,
-            tonemap_key: 0.8,
-            tonemap_sat: 1.0,
-        }
-    }
-
-    fn window(&self) -> &Window {
-        &self.window
-    }
-
-    fn render(&mut self) {
-        let frame = match self.surface.get_current_texture() {
-            wgpu::CurrentSurfaceTexture::Success(frame)
-            | wgpu::CurrentSurfaceTexture::Suboptimal(frame) => frame,
-            wgpu::CurrentSurfaceTexture::Timeout | wgpu::CurrentSurfaceTexture::Occluded => return,
-            wgpu::CurrentSurfaceTexture::Outdated => {
-                self.surface.configure(&self.device, &self.config);
-                return;
-            }
-            wgpu::CurrentSurfaceTexture::Lost => {
-                self.recreate_surface();
-                return;
-            }
-            wgpu::CurrentSurfaceTexture::Validation => {
-                eprintln!("Surface validation error while acquiring the next frame");
-                return;
-            }
-        };
-
-        let mut encoder = self.device.create_command_encoder(&Default::default());
-        if self.clear_flag {
-            encoder.clear_texture(
-                &self.compute_texture,
-                &wgpu::ImageSubresourceRange {
-                    aspect: wgpu::TextureAspect::All,
-                    base_mip_level: 0,
-                    mip_level_count: None,
-                    base_array_layer: 0,
-                    array_layer_count: None,
-                },
-            );
-            self.clear_flag = false;
-        }
-
-        self.compute_pass
-            .render(&self.device, &mut encoder, &self.size, &self.scene);
-
-        self.render_pass.render(
-            &mut encoder,
-            &frame.texture.create_view(&Default::default()),
-        );
-
-        self.queue.submit(Some(encoder.finish()));
-        frame.present();
-    }
-
-    fn recreate_surface(&mut self) {
-        self.surface = self.instance.create_surface(self.window.clone()).unwrap();
-        self.surface.configure(&self.device, &self.config);
-    }
-
-    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        if new_size.height > 0 && new_size.width > 0 {
-            self.size = new_size;
-            self.config.height = new_size.height;
-            self.config.width = new_size.width;
-            self.surface.configure(&self.device, &self.config);
-
-            // We need to recreate the comput and output texture on resize
-            self.compute_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-                label: Some("compute_texture"),
-                size: Extent3d {
-                    width: new_size.width,
-                    height: new_size.height,
-                    depth_or_array_layers: 1,
-                },
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba32Float,
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-                    | wgpu::TextureUsages::STORAGE_BINDING
-                    | wgpu::TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-            });
-
-            self.compute_view = self.compute_texture.create_view(&Default::default());
-
-            self.compute_pass
-                .resize(&self.device, &new_size, &self.compute_view, &self.scene.vispoint_buffer);
-            self.render_pass.resize(&self.device, &self.compute_view);
         }
     }
+}
 
-    fn input_device_event(&mut self, event: &DeviceEvent) -> bool {
-        if self.window_focused {
-            self.camera_controller.process_events(event);
-        }
-        false
-    }
+/// Open the device with the features and limits the kernels need, within
+/// what the adapter offers.
+async fn request_device(
+    adapter: &wgpu::Adapter,
+) -> Result<(wgpu::Device, wgpu::Queue), wgpu::RequestDeviceError> {
+    let supported = adapter.limits();
+    adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                | wgpu::Features::CLEAR_TEXTURE,
+            required_limits: wgpu::Limits {
+                max_bind_groups: 7,
+                max_storage_buffers_per_shader_stage: supported
+                    .max_storage_buffers_per_shader_stage,
+                // The film, moments and AOVs are all storage textures
+                max_storage_textures_per_shader_stage: supported
+                    .max_storage_textures_per_shader_stage,
+                max_storage_buffer_binding_size: supported
+                    .max_storage_buffer_binding_size
+                    .min(512 * 1024 * 1024),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await
+}
 
-    fn input_window_event(&mut self, event: &WindowEvent) {
-        match event {
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state: ElementState::Pressed,
-                        physical_key: PhysicalKey::Code(KeyCode::Escape),
-                        ..
-                    },
-                ..
-            } => {
-                self.window_focused = false;
-                self.window
-                    .set_cursor_grab(CursorGrabMode::None)
-                    .unwrap();
-                self.window.set_cursor_visible(true);
-            }
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state: ElementState::Pressed,
-                        physical_key: PhysicalKey::Code(KeyCode::Equal),
-                        ..
-                    },
-                ..
-            } => {
-                self.tonemap_key = (self.tonemap_key * 20.0 + 1.0) / 20.0;
-                self.render_pass.update_tonemap(&self.queue, self.tonemap_key, self.tonemap_sat);
-            }
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state: ElementState::Pressed,
-                        physical_key: PhysicalKey::Code(KeyCode::Minus),
-                        ..
-                    },
-                ..
-            } => {
-                self.tonemap_key = (self.tonemap_key * 20.0 - 1.0) / 20.0;
-                if self.tonemap_key < 0.05 {
-                    self.tonemap_key = 0.05;
-                }
-                self.render_pass.update_tonemap(&self.queue, self.tonemap_key, self.tonemap_sat);
-            }
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state: ElementState::Pressed,
-                        physical_key: PhysicalKey::Code(KeyCode::BracketRight),
-                        ..
-                    },
-                ..
-            } => {
-                self.tonemap_sat = (self.tonemap_sat * 20.0 + 1.0) / 20.0;
-                if self.tonemap_sat > 3.0 { self.tonemap_sat = 3.0; }
-                self.render_pass.update_tonemap(&self.queue, self.tonemap_key, self.tonemap_sat);
-            }
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state: ElementState::Pressed,
-                        physical_key: PhysicalKey::Code(KeyCode::BracketLeft),
-                        ..
-                    },
-                ..
-            } => {
-                self.tonemap_sat = (self.tonemap_sat * 20.0 - 1.0) / 20.0;
-                if self.tonemap_sat < 0.0 { self.tonemap_sat = 0.0; }
-                self.render_pass.update_tonemap(&self.queue, self.tonemap_key, self.tonemap_sat);
-            }
-            WindowEvent::MouseWheel { delta, .. } => {
-                let scroll = match delta {
-                    winit::event::MouseScrollDelta::LineDelta(_, y) => *y,
-                    winit::event::MouseScrollDelta::PixelDelta(pos) => pos.y as f32 * 0.01,
-                };
-                let mut new_vfov = self.camera.vfov - scroll * 0.5;
-                new_vfov = new_vfov.clamp(10.0, 170.0);
-                self.camera.set_vfov(new_vfov);
-                self.clear_flag = true;
-                self.camera_uniform = self.camera.get_uniform();
-                self.compute_pass.update(&self.queue, self.camera_uniform);
-            }
-            WindowEvent::MouseInput {
-                state: winit::event::ElementState::Pressed,
-                button: winit::event::MouseButton::Left,
-                ..
-            } => {
-                self.window_focused = true;
-                self.window
-                    .set_cursor_grab(CursorGrabMode::Confined)
-                    .unwrap();
-                self.window.set_cursor_visible(false);
-            }
-            WindowEvent::Resized(physical_size) => {
-                self.resize(*physical_size);
-            }
-            WindowEvent::ScaleFactorChanged { .. } => {
-                self.resize(self.window.inner_size());
-            }
-            _ => {}
-        }
-    }
+/// Actions of the viewer's own keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Hotkey {
+    ReleaseCursor,
+    Brighten,
+    Darken,
+    Iso,
+    FNumber,
+    MoreSaturation,
+    LessSaturation,
+    Observer,
+    OutputSpace,
+    AdoptedWhite,
+    WhiteBalance,
+    Tint,
+    ApertureRadius,
+    ApertureShape,
+    RealisticLens,
+    Projection,
+    FilmFit,
+    MotionBlur,
+    AdaptiveSampling,
+    View,
+    Temporal,
+    Denoiser,
+    Tonemapper,
+    Lut,
+    AutoExposure,
+    Bloom,
+    BloomLevels,
+    HdrLevels,
+    DenoiseStrength,
+    Screenshot,
+    /// Recall the numbered view, or store it while Alt is held.
+    Bookmark(u8),
+    Keyframe,
+    Playback,
+    Sequence,
+    /// Held with a digit to store a bookmark.
+    StoreBookmark,
+}
 
-    fn update(&mut self, duration: u128) {
-        let was_updated = self
-            .camera_controller
-            .update_camera(&mut self.camera, duration);
-        if was_updated {
-            self.clear_flag = true;
-            self.camera_uniform = self.camera.get_uniform();
-            self.compute_pass.update(&self.queue, self.camera_uniform);
-        }
+impl Hotkey {
+    /// Whether holding the key down repeats the action.
+    fn repeats(self) -> bool {
+        !matches!(
+            self,
+            Hotkey::MotionBlur
+                | Hotkey::AdaptiveSampling
+                | Hotkey::View
+                | Hotkey::Temporal
+                | Hotkey::Denoiser
+                | Hotkey::Tonemapper
+                | Hotkey::Lut
+                | Hotkey::AutoExposure
+                | Hotkey::Bloom
+                | Hotkey::Screenshot
+                | Hotkey::Bookmark(_)
+                | Hotkey::Keyframe
+                | Hotkey::Playback
+                | Hotkey::Sequence
+        )
     }
 }
 
-pub struct Scene {
-    sphere_bind_group_layout: wgpu::BindGroupLayout,
-    sphere_bind_group: wgpu::BindGroup,
-    mesh_bind_group_layout: wgpu::BindGroupLayout,
-    mesh_bind_group: wgpu::BindGroup,
-    material_bind_group_layout: wgpu::BindGroupLayout,
-    material_bind_group: wgpu::BindGroup,
-    bvh_bind_group_layout: wgpu::BindGroupLayout,
-    bvh_bind_group: wgpu::BindGroup,
-    pub light_bind_group_layout: wgpu::BindGroupLayout,
-    pub light_bind_group: wgpu::BindGroup,
-    pub vispoint_buffer: wgpu::Buffer,
-}
+/// Keys the viewer handles itself, which dispatch the key events and which
+/// the movement bindings of res/controls.ron are checked against.
+const HOTKEYS: &[(KeyCode, Hotkey)] = &[
+    (KeyCode::Escape, Hotkey::ReleaseCursor),
+    (KeyCode::Equal, Hotkey::Brighten),
+    (KeyCode::Minus, Hotkey::Darken),
+    (KeyCode::PageUp, Hotkey::Iso),
+    (KeyCode::PageDown, Hotkey::Iso),
+    (KeyCode::Home, Hotkey::FNumber),
+    (KeyCode::End, Hotkey::FNumber),
+    (KeyCode::BracketRight, Hotkey::MoreSaturation),
+    (KeyCode::BracketLeft, Hotkey::LessSaturation),
+    (KeyCode::KeyO, Hotkey::Observer),
+    (KeyCode::KeyC, Hotkey::OutputSpace),
+    (KeyCode::KeyV, Hotkey::AdoptedWhite),
+    (KeyCode::Comma, Hotkey::WhiteBalance),
+    (KeyCode::Period, Hotkey::WhiteBalance),
+    (KeyCode::Semicolon, Hotkey::Tint),
+    (KeyCode::Quote, Hotkey::Tint),
+    (KeyCode::KeyN, Hotkey::ApertureRadius),
+    (KeyCode::KeyM, Hotkey::ApertureRadius),
+    (KeyCode::KeyB, Hotkey::ApertureShape),
+    (KeyCode::KeyL, Hotkey::RealisticLens),
+    (KeyCode::KeyP, Hotkey::Projection),
+    (KeyCode::KeyF, Hotkey::FilmFit),
+    (KeyCode::KeyT, Hotkey::MotionBlur),
+    (KeyCode::KeyG, Hotkey::AdaptiveSampling),
+    (KeyCode::KeyH, Hotkey::View),
+    (KeyCode::KeyE, Hotkey::Temporal),
+    (KeyCode::KeyX, Hotkey::Denoiser),
+    (KeyCode::KeyY, Hotkey::Tonemapper),
+    (KeyCode::KeyQ, Hotkey::Lut),
+    (KeyCode::KeyZ, Hotkey::AutoExposure),
+    (KeyCode::F1, Hotkey::Bloom),
+    (KeyCode::F2, Hotkey::BloomLevels),
+    (KeyCode::F3, Hotkey::BloomLevels),
+    (KeyCode::F5, Hotkey::HdrLevels),
+    (KeyCode::F6, Hotkey::HdrLevels),
+    (KeyCode::KeyU, Hotkey::DenoiseStrength),
+    (KeyCode::KeyI, Hotkey::DenoiseStrength),
+    (KeyCode::F12, Hotkey::Screenshot),
+    (KeyCode::Digit1, Hotkey::Bookmark(1)),
+    (KeyCode::Digit2, Hotkey::Bookmark(2)),
+    (KeyCode::Digit3, Hotkey::Bookmark(3)),
+    (KeyCode::Digit4, Hotkey::Bookmark(4)),
+    (KeyCode::Digit5, Hotkey::Bookmark(5)),
+    (KeyCode::Digit6, Hotkey::Bookmark(6)),
+    (KeyCode::Digit7, Hotkey::Bookmark(7)),
+    (KeyCode::Digit8, Hotkey::Bookmark(8)),
+    (KeyCode::Digit9, Hotkey::Bookmark(9)),
+    (KeyCode::KeyK, Hotkey::Keyframe),
+    (KeyCode::KeyJ, Hotkey::Playback),
+    (KeyCode::KeyR, Hotkey::Sequence),
+    (KeyCode::AltLeft, Hotkey::StoreBookmark),
+    (KeyCode::AltRight, Hotkey::StoreBookmark),
+];
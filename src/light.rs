@@ -1,8 +1,12 @@
+use crate::spectrum::rgb_to_unbounded_spectrum;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct GpuLight {
     pub position: [f32; 4],
     pub color: [f32; 4],
+    /// Sigmoid-polynomial coefficients of `color`, with its scale in the last lane.
+    pub spectrum: [f32; 4],
     pub color_temp: f32,
     pub light_type: u32,
     pub normal_x: f32,
@@ -15,6 +19,7 @@ impl GpuLight {
         Self {
             position: [position[0], position[1], position[2], 0.0],
             color: [color[0], color[1], color[2], intensity],
+            spectrum: rgb_to_unbounded_spectrum(color),
             color_temp,
             light_type: 0,
             normal_x: 0.0,
@@ -41,6 +46,7 @@ impl GpuLight {
         Self {
             position: [center[0], center[1], center[2], half_width],
             color: [color[0], color[1], color[2], intensity],
+            spectrum: rgb_to_unbounded_spectrum(color),
             color_temp,
             light_type: 1,
             normal_x: nx,
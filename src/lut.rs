@@ -0,0 +1,314 @@
+//! 3D colour grading LUTs in the .cube format of Resolve and Adobe, applied
+//! by `grade` in blit.wgsl.
+
+use std::path::{Path, PathBuf};
+
+use crate::{export::srgb_encode, tonemap::TonemapUniform};
+
+/// Folder searched for LUTs to cycle through in the viewer.
+pub const LUT_DIR: &str = "res/luts";
+
+// Stops either side of middle grey the log shaper spans, as in Blender's Filmic
+const SHAPER_MIN_EV: f32 = -10.0;
+const SHAPER_MAX_EV: f32 = 6.5;
+const MIDDLE_GREY: f32 = 0.18;
+
+// Matches the LUT_ constants in blit.wgsl
+const MODE_NONE: u32 = 0;
+const MODE_DISPLAY: u32 = 1;
+const MODE_LOG: u32 = 2;
+
+#[derive(Debug, Clone)]
+pub struct Lut {
+    pub title: String,
+    /// Entries along each axis.
+    pub size: u32,
+    /// Output values, red varying fastest and blue slowest.
+    pub table: Vec<[f32; 3]>,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    /// Feed the LUT log encoded scene values instead of the tonemapped
+    /// image, letting it take the tonemapper's place as HDR LUTs expect.
+    pub log_shaper: bool,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LutUniform {
+    pub mode: u32,
+    pub size: u32,
+    pub shaper_min_ev: f32,
+    pub shaper_max_ev: f32,
+    pub domain_min: [f32; 4],
+    pub domain_max: [f32; 4],
+}
+
+impl LutUniform {
+    pub fn none() -> Self {
+        Self {
+            mode: MODE_NONE,
+            size: 1,
+            shaper_min_ev: SHAPER_MIN_EV,
+            shaper_max_ev: SHAPER_MAX_EV,
+            domain_min: [0.0; 4],
+            domain_max: [1.0; 4],
+        }
+    }
+}
+
+impl Lut {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lut = Self {
+            title: String::new(),
+            size: 0,
+            table: Vec::new(),
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            log_shaper: false,
+        };
+        let triple = |words: &[&str]| -> Result<[f32; 3], String> {
+            if words.len() != 3 {
+                return Err(format!("expected three values, got {}", words.len()));
+            }
+            let mut values = [0.0; 3];
+            for (value, word) in values.iter_mut().zip(words) {
+                *value = word
+                    .parse()
+                    .map_err(|_| format!("invalid number: {word}"))?;
+            }
+            Ok(values)
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let result = match words[0] {
+                "TITLE" => {
+                    lut.title = line["TITLE".len()..].trim().trim_matches('"').to_string();
+                    Ok(())
+                }
+                "LUT_3D_SIZE" => match words.get(1).and_then(|w| w.parse().ok()) {
+                    Some(size) if (2..=256).contains(&size) => {
+                        lut.size = size;
+                        Ok(())
+                    }
+                    _ => Err("LUT_3D_SIZE must be between 2 and 256".to_string()),
+                },
+                "LUT_1D_SIZE" => Err("1D LUTs are not supported".to_string()),
+                "DOMAIN_MIN" => triple(&words[1..]).map(|v| lut.domain_min = v),
+                "DOMAIN_MAX" => triple(&words[1..]).map(|v| lut.domain_max = v),
+                // Resolve's form of the domain, the same on every channel
+                "LUT_3D_INPUT_RANGE" => match words[1..] {
+                    [min, max] => match (min.parse(), max.parse()) {
+                        (Ok(min), Ok(max)) => {
+                            lut.domain_min = [min; 3];
+                            lut.domain_max = [max; 3];
+                            Ok(())
+                        }
+                        _ => Err("invalid LUT_3D_INPUT_RANGE".to_string()),
+                    },
+                    _ => Err("LUT_3D_INPUT_RANGE takes two values".to_string()),
+                },
+                word if word.starts_with(|c: char| c.is_ascii_alphabetic()) => Ok(()),
+                _ => triple(&words).map(|v| lut.table.push(v)),
+            };
+            result.map_err(|e| format!("line {}: {e}", number + 1))?;
+        }
+
+        if lut.size == 0 {
+            return Err("missing LUT_3D_SIZE".to_string());
+        }
+        let entries = lut.size.pow(3) as usize;
+        if lut.table.len() != entries {
+            return Err(format!(
+                "expected {entries} entries for size {}, found {}",
+                lut.size,
+                lut.table.len()
+            ));
+        }
+        if (0..3).any(|i| lut.domain_max[i] <= lut.domain_min[i]) {
+            return Err("empty domain".to_string());
+        }
+        Ok(lut)
+    }
+
+    /// Every .cube file in `LUT_DIR`, by name.
+    pub fn find_all() -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(LUT_DIR)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("cube"))
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    pub fn uniform(&self) -> LutUniform {
+        let pad = |v: [f32; 3]| [v[0], v[1], v[2], 0.0];
+        LutUniform {
+            mode: if self.log_shaper {
+                MODE_LOG
+            } else {
+                MODE_DISPLAY
+            },
+            size: self.size,
+            domain_min: pad(self.domain_min),
+            domain_max: pad(self.domain_max),
+            ..LutUniform::none()
+        }
+    }
+
+    /// The table as texels of an `Rgba32Float` 3D texture.
+    pub fn texels(&self) -> Vec<[f32; 4]> {
+        self.table.iter().map(|v| [v[0], v[1], v[2], 1.0]).collect()
+    }
+
+    /// The grade of `grade` in blit.wgsl, for display images written on the
+    /// CPU: tonemap or shape `rgb`, then look it up.
+    pub fn grade(&self, tonemap: &TonemapUniform, rgb: [f32; 3], luminance: [f32; 3]) -> [f32; 3] {
+        let input = if self.log_shaper {
+            rgb.map(|v| log_shape(v.max(0.0) * tonemap.key))
+        } else {
+            tonemap.apply(rgb, luminance).map(srgb_encode)
+        };
+        // The output is display encoded, like the input of a display LUT
+        self.lookup(input).map(srgb_decode)
+    }
+
+    /// Trilinear lookup over the domain, clamped at its edges.
+    fn lookup(&self, input: [f32; 3]) -> [f32; 3] {
+        let n = self.size as usize;
+        let coords: [f32; 3] = std::array::from_fn(|i| {
+            let t = (input[i] - self.domain_min[i]) / (self.domain_max[i] - self.domain_min[i]);
+            t.clamp(0.0, 1.0) * (n - 1) as f32
+        });
+        let base = coords.map(|c| (c.floor() as usize).min(n - 2));
+        let f: [f32; 3] = std::array::from_fn(|i| coords[i] - base[i] as f32);
+        let mut out = [0.0; 3];
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, corner >> 2];
+            let weight: f32 = (0..3)
+                .map(|i| if offset[i] == 1 { f[i] } else { 1.0 - f[i] })
+                .product();
+            let [r, g, b]: [usize; 3] = std::array::from_fn(|i| base[i] + offset[i]);
+            let entry = self.table[r + n * (g + n * b)];
+            for i in 0..3 {
+                out[i] += weight * entry[i];
+            }
+        }
+        out
+    }
+}
+
+/// Exposed scene value to the LUT input of the log shaper.
+fn log_shape(v: f32) -> f32 {
+    let stops = (v.max(1e-10) / MIDDLE_GREY).log2();
+    ((stops - SHAPER_MIN_EV) / (SHAPER_MAX_EV - SHAPER_MIN_EV)).clamp(0.0, 1.0)
+}
+
+fn srgb_decode(v: f32) -> f32 {
+    let v = v.clamp(0.0, 1.0);
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tonemap::{Tonemapper, DEFAULT_WHITE};
+
+    /// A size 2 cube whose corners hold `corner(r, g, b)`, red varying fastest.
+    fn cube(header: &str, corner: impl Fn(f32, f32, f32) -> [f32; 3]) -> String {
+        let mut text = format!("{header}\nLUT_3D_SIZE 2\n");
+        for b in [0.0, 1.0] {
+            for g in [0.0, 1.0] {
+                for r in [0.0, 1.0] {
+                    let [x, y, z] = corner(r, g, b);
+                    text += &format!("{x} {y} {z}\n");
+                }
+            }
+        }
+        text
+    }
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a - e).abs() < 1e-4,
+                "expected {expected:?}, got {actual:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_a_small_lut() {
+        let lut = Lut::parse(&cube("TITLE \"Swap\"\n# comment", |r, g, b| [g, r, b])).unwrap();
+        assert_eq!(lut.title, "Swap");
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.table.len(), 8);
+        assert_eq!(lut.table[1], [0.0, 1.0, 0.0]);
+        assert_eq!(lut.domain_min, [0.0; 3]);
+        assert_eq!(lut.domain_max, [1.0; 3]);
+        assert_close(lut.lookup([0.25, 0.5, 0.75]), [0.5, 0.25, 0.75]);
+    }
+
+    #[test]
+    fn identity_grade_keeps_the_tonemapped_image() {
+        let lut = Lut::parse(&cube("", |r, g, b| [r, g, b])).unwrap();
+        let tonemap = TonemapUniform::new(Tonemapper::Linear, 1.0, 1.0, DEFAULT_WHITE);
+        let luminance = [0.2126, 0.7152, 0.0722];
+        // Interpolating in display encoding is exact for an identity table
+        assert_close(
+            lut.grade(&tonemap, [0.25, 0.5, 0.75], luminance),
+            [0.25, 0.5, 0.75],
+        );
+    }
+
+    #[test]
+    fn rejects_bad_sizes() {
+        assert!(Lut::parse("LUT_3D_SIZE 1\n0 0 0\n").is_err());
+        assert!(Lut::parse("LUT_3D_SIZE 257\n").is_err());
+        assert!(Lut::parse("LUT_3D_SIZE two\n").is_err());
+        assert!(Lut::parse("LUT_1D_SIZE 16\n").is_err());
+        assert!(Lut::parse("0 0 0\n").is_err());
+        let short = cube("", |r, g, b| [r, g, b]).replacen("1 1 1\n", "", 1);
+        assert!(Lut::parse(&short).is_err());
+        let ragged = cube("", |r, g, b| [r, g, b]).replacen("0 0 0\n", "0 0\n", 1);
+        assert!(Lut::parse(&ragged).is_err());
+    }
+
+    #[test]
+    fn domain_remaps_the_input() {
+        let header = "DOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2";
+        let lut = Lut::parse(&cube(header, |r, g, b| [2.0 * r, 2.0 * g, 2.0 * b])).unwrap();
+        assert_eq!(lut.domain_max, [2.0; 3]);
+        assert_close(lut.lookup([0.5, 1.0, 1.5]), [0.5, 1.0, 1.5]);
+        // Inputs outside the domain are clamped to its edges
+        assert_close(lut.lookup([-1.0, 3.0, 1.0]), [0.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn input_range_sets_every_channel() {
+        let lut = Lut::parse(&cube("LUT_3D_INPUT_RANGE -1 1", |r, g, b| [r, g, b])).unwrap();
+        assert_eq!(lut.domain_min, [-1.0; 3]);
+        assert_eq!(lut.domain_max, [1.0; 3]);
+        assert_close(lut.lookup([0.0, -1.0, 0.5]), [0.5, 0.0, 0.75]);
+        assert!(Lut::parse(&cube("LUT_3D_INPUT_RANGE 0", |r, g, b| [r, g, b])).is_err());
+        assert!(Lut::parse(&cube("LUT_3D_INPUT_RANGE 1 0", |r, g, b| [r, g, b])).is_err());
+    }
+}
@@ -1,5 +1,27 @@
-use wgpu_raytracer::run;
+use wgpu_raytracer::{headless, run, ViewerArgs, VIEWER_USAGE};
 
 fn main() {
-    pollster::block_on(run());
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "render").is_some() {
+        let args = match headless::RenderArgs::parse(args) {
+            Ok(args) => args,
+            Err(e) => {
+                eprintln!("{e}\n{}", headless::USAGE);
+                std::process::exit(2);
+            }
+        };
+        if let Err(e) = pollster::block_on(headless::render(&args)) {
+            eprintln!("Render failed: {e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    let args = match ViewerArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{VIEWER_USAGE}");
+            std::process::exit(2);
+        }
+    };
+    pollster::block_on(run(args));
 }
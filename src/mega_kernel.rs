@@ -2,28 +2,68 @@ use std::mem;
 
 use wgpu::{util::DeviceExt, BufferUsages};
 
-use crate::{camera::CameraUniform, Scene};
+use crate::{
+    aov::AovTextures,
+    camera::{Camera, CameraUniform},
+    export, goal,
+    lens::GpuLensElement,
+    spectrum::{self, Observer},
+    Scene,
+};
 
 const CONFIG_SIZE: u64 = mem::size_of::<ConfigData>() as u64;
 
-const _: () = assert!(CONFIG_SIZE == 32);
+const _: () = assert!(CONFIG_SIZE == 48);
+
+// Pixel to probe (two u32s) followed by the depth written back
+const FOCUS_PROBE_SIZE: u64 = 16;
 
 pub const DEFAULT_DEPTH: u32 = 30;
 pub const PHOTON_RADIUS_INIT: f32 = 2.0;
+// Compute passes per submission when accumulating a fixed sample count
+const ACCUMULATE_BATCH: u32 = 16;
+/// Samples every pixel takes before adaptive sampling may skip it.
+pub const ADAPTIVE_MIN_SAMPLES: u32 = 16;
+// Passes between rebuilds of the adaptive sample map
+const SAMPLE_MAP_INTERVAL: u32 = 8;
+// Count of traced pixels ahead of the per-pixel flags
+const SAMPLE_MAP_HEADER: u64 = 4;
+// Binding of the first AOV texture in group 0, the rest following in order
+const AOV_FIRST_BINDING: u32 = 7;
 
 pub struct ComputePass {
     pub config_buffer: wgpu::Buffer,
     pub config_data: ConfigData,
     pub pipeline: wgpu::ComputePipeline,
+    pub focus_pipeline: wgpu::ComputePipeline,
+    pub sample_map_pipeline: wgpu::ComputePipeline,
     pub bind_group: wgpu::BindGroup,
     pub bind_group_layout: wgpu::BindGroupLayout,
     pub camera_buffer: wgpu::Buffer,
     pub camera_bind_group: wgpu::BindGroup,
     #[allow(dead_code)]
     pub camera_bind_group_layout: wgpu::BindGroupLayout,
+    pub aperture_buffer: wgpu::Buffer,
+    pub lens_buffer: wgpu::Buffer,
+    pub cie_buffer: wgpu::Buffer,
+    pub focus_probe_buffer: wgpu::Buffer,
+    pub focus_readback_buffer: wgpu::Buffer,
+    /// Luminance moments per pixel; see `goal::relative_error`.
+    pub moments_texture: wgpu::Texture,
+    /// Which pixels adaptive sampling still traces.
+    pub sample_map_buffer: wgpu::Buffer,
+    pub aov_textures: AovTextures,
+    /// Relative error at which a pixel stops being traced, if sampling adapts.
+    pub adaptive_threshold: Option<f32>,
+    /// The sample map predates the current accumulation or settings.
+    sample_map_stale: bool,
     pub iteration: u32,
     pub photon_radius: f32,
+    /// Base of the per-pass seeds, so an accumulation can be replayed or resumed.
+    pub seed: u32,
     pub preview_next_frame: bool,
+    /// Side of the pixel blocks traced as one, 1 for full resolution.
+    pub pixel_stride: u32,
 }
 
 impl ComputePass {
@@ -31,9 +71,11 @@ impl ComputePass {
         device: &wgpu::Device,
         size: &winit::dpi::PhysicalSize<u32>,
         output_view: &wgpu::TextureView,
-        camera_uniform: &CameraUniform,
+        camera: &Camera,
         scene: &Scene,
+        observer: Observer,
     ) -> Self {
+        let camera_uniform = camera.get_uniform();
         let seed = rand::random();
         let config_data = ConfigData {
             width: size.width,
@@ -42,8 +84,10 @@ impl ComputePass {
             seed,
             photon_radius: PHOTON_RADIUS_INIT,
             iteration: 0,
-            _pad0: 0.0,
-            _pad1: 0.0,
+            adaptive_threshold: 0.0,
+            adaptive_min_samples: ADAPTIVE_MIN_SAMPLES,
+            pixel_stride: 1,
+            _pad: [0; 3],
         };
         let config_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Config Buffer"),
@@ -52,6 +96,25 @@ impl ComputePass {
             mapped_at_creation: false,
         });
 
+        let cie_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("cie_buffer"),
+            contents: bytemuck::cast_slice(&spectrum::generate_cie_xyz_table(observer)),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+
+        let focus_probe_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("focus_probe_buffer"),
+            size: FOCUS_PROBE_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let focus_readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("focus_readback_buffer"),
+            size: FOCUS_PROBE_SIZE,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
         let cs_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Compute Shader"),
             source: wgpu::ShaderSource::Wgsl(include_str!("kernels/mega_kernel.wgsl").into()),
@@ -90,39 +153,116 @@ impl ComputePass {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::ReadWrite,
+                        format: wgpu::TextureFormat::Rgba32Float,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                aov_layout_entry(AOV_FIRST_BINDING),
+                aov_layout_entry(AOV_FIRST_BINDING + 1),
+                aov_layout_entry(AOV_FIRST_BINDING + 2),
+                aov_layout_entry(AOV_FIRST_BINDING + 3),
             ],
         });
 
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("camera_buffer"),
-            contents: bytemuck::cast_slice(&[*camera_uniform]),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: Some("camera_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
             });
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("camera_bind_group"),
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
+        let aperture_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("aperture_buffer"),
+            contents: bytemuck::cast_slice(camera.aperture.samples()),
+            usage: wgpu::BufferUsages::STORAGE,
         });
 
+        let lens_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("lens_buffer"),
+            contents: bytemuck::cast_slice(&camera.projection.lens_elements()),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let camera_bind_group = Self::create_camera_bind_group(
+            device,
+            &camera_bind_group_layout,
+            &camera_buffer,
+            &aperture_buffer,
+            &lens_buffer,
+        );
+
         let compute_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: None,
@@ -147,6 +287,28 @@ impl ComputePass {
             cache: None,
         });
 
+        let focus_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Focus Probe Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &cs_module,
+            entry_point: Some("cs_focus_probe"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let sample_map_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Sample Map Pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &cs_module,
+            entry_point: Some("cs_sample_map"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let moments_texture = Self::create_moments_texture(device, size);
+        let sample_map_buffer = Self::create_sample_map_buffer(device, size);
+        let aov_textures = AovTextures::new(device, size);
+        let aov_views = aov_textures.views();
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &bind_group_layout,
@@ -163,6 +325,28 @@ impl ComputePass {
                     binding: 2,
                     resource: scene.vispoint_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: cie_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: focus_probe_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(
+                        &moments_texture.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: sample_map_buffer.as_entire_binding(),
+                },
+                aov_entry(&aov_views, 0),
+                aov_entry(&aov_views, 1),
+                aov_entry(&aov_views, 2),
+                aov_entry(&aov_views, 3),
             ],
         });
 
@@ -170,14 +354,28 @@ impl ComputePass {
             config_buffer,
             config_data,
             pipeline,
+            focus_pipeline,
+            sample_map_pipeline,
             bind_group,
             bind_group_layout,
             camera_buffer,
             camera_bind_group,
             camera_bind_group_layout,
+            aperture_buffer,
+            lens_buffer,
+            cie_buffer,
+            focus_probe_buffer,
+            focus_readback_buffer,
+            moments_texture,
+            sample_map_buffer,
+            aov_textures,
+            adaptive_threshold: None,
+            sample_map_stale: true,
             iteration: 0,
             photon_radius: PHOTON_RADIUS_INIT,
+            seed,
             preview_next_frame: false,
+            pixel_stride: 1,
         }
     }
 
@@ -188,10 +386,17 @@ impl ComputePass {
         size: &winit::dpi::PhysicalSize<u32>,
         scene: &Scene,
     ) {
-        self.config_data.seed = rand::random();
+        self.config_data.seed = frame_seed(self.seed, self.iteration);
         self.config_data.depth = DEFAULT_DEPTH;
         self.config_data.photon_radius = self.photon_radius;
         self.config_data.iteration = self.iteration;
+        self.config_data.adaptive_threshold = self.adaptive_threshold.unwrap_or(0.0);
+        self.config_data.pixel_stride = self.pixel_stride;
+        let rebuild_sample_map = self.adaptive_threshold.is_some()
+            && self.pixel_stride == 1
+            && self.iteration >= ADAPTIVE_MIN_SAMPLES
+            && (self.sample_map_stale
+                || (self.iteration - ADAPTIVE_MIN_SAMPLES).is_multiple_of(SAMPLE_MAP_INTERVAL));
         self.iteration += 1;
         // Progressive radius reduction: R *= sqrt((k+alpha)/(k+1))
         let k = self.iteration as f32;
@@ -207,9 +412,12 @@ impl ComputePass {
             usage: wgpu::BufferUsages::COPY_SRC,
         });
         encoder.copy_buffer_to_buffer(&config_host, 0, &self.config_buffer, 0, CONFIG_SIZE);
+        if rebuild_sample_map {
+            encoder.clear_buffer(&self.sample_map_buffer, 0, Some(SAMPLE_MAP_HEADER));
+            self.sample_map_stale = false;
+        }
 
         let mut compute_pass = encoder.begin_compute_pass(&Default::default());
-        compute_pass.set_pipeline(&self.pipeline);
         compute_pass.set_bind_group(0, &self.bind_group, &[]);
         compute_pass.set_bind_group(1, &self.camera_bind_group, &[]);
         compute_pass.set_bind_group(2, &scene.sphere_bind_group, &[]);
@@ -218,7 +426,18 @@ impl ComputePass {
         compute_pass.set_bind_group(5, &scene.bvh_bind_group, &[]);
         compute_pass.set_bind_group(6, &scene.light_bind_group, &[]);
 
-        compute_pass.dispatch_workgroups(size.width / 8, size.height / 4, 1);
+        let workgroups = (size.width.div_ceil(8), size.height.div_ceil(4));
+        if rebuild_sample_map {
+            compute_pass.set_pipeline(&self.sample_map_pipeline);
+            compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        }
+        // One invocation per block of pixels
+        let blocks = (
+            size.width.div_ceil(self.pixel_stride),
+            size.height.div_ceil(self.pixel_stride),
+        );
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.dispatch_workgroups(blocks.0.div_ceil(8), blocks.1.div_ceil(4), 1);
     }
 
     pub fn resize(
@@ -231,6 +450,11 @@ impl ComputePass {
         self.preview_next_frame = true;
         self.iteration = 0;
         self.photon_radius = PHOTON_RADIUS_INIT;
+        self.moments_texture = Self::create_moments_texture(device, new_size);
+        self.sample_map_buffer = Self::create_sample_map_buffer(device, new_size);
+        self.sample_map_stale = true;
+        self.aov_textures = AovTextures::new(device, new_size);
+        let aov_views = self.aov_textures.views();
         self.config_data = ConfigData {
             width: new_size.width,
             height: new_size.height,
@@ -238,8 +462,10 @@ impl ComputePass {
             seed: rand::random(),
             photon_radius: PHOTON_RADIUS_INIT,
             iteration: 0,
-            _pad0: 0.0,
-            _pad1: 0.0,
+            adaptive_threshold: 0.0,
+            adaptive_min_samples: ADAPTIVE_MIN_SAMPLES,
+            pixel_stride: 1,
+            _pad: [0; 3],
         };
         self.bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
@@ -257,11 +483,263 @@ impl ComputePass {
                     binding: 2,
                     resource: vispoint_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.cie_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.focus_probe_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self.moments_texture.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.sample_map_buffer.as_entire_binding(),
+                },
+                aov_entry(&aov_views, 0),
+                aov_entry(&aov_views, 1),
+                aov_entry(&aov_views, 2),
+                aov_entry(&aov_views, 3),
+            ],
+        });
+    }
+
+    fn create_moments_texture(
+        device: &wgpu::Device,
+        size: &winit::dpi::PhysicalSize<u32>,
+    ) -> wgpu::Texture {
+        device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("moments_texture"),
+            size: wgpu::Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::STORAGE_BINDING
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_SRC
+                | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        })
+    }
+
+    fn create_sample_map_buffer(
+        device: &wgpu::Device,
+        size: &winit::dpi::PhysicalSize<u32>,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("sample_map_buffer"),
+            size: SAMPLE_MAP_HEADER + 4 * size.width as u64 * size.height as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_camera_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        camera_buffer: &wgpu::Buffer,
+        aperture_buffer: &wgpu::Buffer,
+        lens_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("camera_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: aperture_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: lens_buffer.as_entire_binding(),
+                },
             ],
+        })
+    }
+
+    /// Upload the points of a custom aperture mask.
+    pub fn update_aperture(&mut self, device: &wgpu::Device, aperture_samples: &[[f32; 2]]) {
+        self.aperture_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("aperture_buffer"),
+            contents: bytemuck::cast_slice(aperture_samples),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        self.camera_bind_group = Self::create_camera_bind_group(
+            device,
+            &self.camera_bind_group_layout,
+            &self.camera_buffer,
+            &self.aperture_buffer,
+            &self.lens_buffer,
+        );
+    }
+
+    /// Upload the elements of a lens prescription.
+    pub fn update_lens(&mut self, device: &wgpu::Device, lens_elements: &[GpuLensElement]) {
+        self.lens_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("lens_buffer"),
+            contents: bytemuck::cast_slice(lens_elements),
+            usage: wgpu::BufferUsages::STORAGE,
         });
+        self.camera_bind_group = Self::create_camera_bind_group(
+            device,
+            &self.camera_bind_group_layout,
+            &self.camera_buffer,
+            &self.aperture_buffer,
+            &self.lens_buffer,
+        );
+    }
+
+    /// Trace a pinhole ray through `pixel` and read back the depth of the first
+    /// surface along the view axis. Blocks until the GPU has finished.
+    pub fn probe_depth(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        scene: &Scene,
+        pixel: [u32; 2],
+    ) -> Option<f32> {
+        queue.write_buffer(&self.focus_probe_buffer, 0, bytemuck::cast_slice(&pixel));
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            compute_pass.set_pipeline(&self.focus_pipeline);
+            compute_pass.set_bind_group(0, &self.bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+            compute_pass.set_bind_group(2, &scene.sphere_bind_group, &[]);
+            compute_pass.set_bind_group(3, &scene.mesh_bind_group, &[]);
+            compute_pass.set_bind_group(4, &scene.material_bind_group, &[]);
+            compute_pass.set_bind_group(5, &scene.bvh_bind_group, &[]);
+            compute_pass.set_bind_group(6, &scene.light_bind_group, &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.focus_probe_buffer,
+            0,
+            &self.focus_readback_buffer,
+            0,
+            FOCUS_PROBE_SIZE,
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        self.focus_readback_buffer
+            .map_async(wgpu::MapMode::Read, .., move |result| {
+                let _ = sender.send(result);
+            });
+        device.poll(wgpu::PollType::wait_indefinitely()).ok()?;
+        if let Err(e) = receiver.recv().ok()? {
+            eprintln!("Failed to read the focus probe: {e}");
+            return None;
+        }
+        let depth = {
+            let view = self.focus_readback_buffer.get_mapped_range(..);
+            bytemuck::cast_slice::<u8, f32>(&view)[2]
+        };
+        self.focus_readback_buffer.unmap();
+        (depth > 0.0).then_some(depth)
+    }
+
+    /// Mean relative error of the pixels, read back from the moments.
+    pub fn noise_estimate(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Option<f32> {
+        let moments = export::read_texture(device, queue, &self.moments_texture)?;
+        Some(goal::relative_error(&moments))
+    }
+
+    /// Pixels adaptive sampling still traces, or None while every pixel is.
+    pub fn active_pixels(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Option<u32> {
+        if self.adaptive_threshold.is_none() || self.sample_map_stale {
+            return None;
+        }
+        let data = export::read_buffer(device, queue, &self.sample_map_buffer)?;
+        Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+    }
+
+    /// Turn adaptive sampling on with a relative error threshold, or off.
+    pub fn set_adaptive(&mut self, threshold: Option<f32>) {
+        self.adaptive_threshold = threshold;
+        self.sample_map_stale = true;
+    }
+
+    /// Restart progressive photon mapping, for when the film is cleared.
+    pub fn reset(&mut self) {
+        self.iteration = 0;
+        self.sample_map_stale = true;
+        self.photon_radius = PHOTON_RADIUS_INIT;
+        self.seed = rand::random();
+    }
+
+    /// Swap the colour matching functions. The film must be cleared afterwards.
+    pub fn set_observer(&mut self, queue: &wgpu::Queue, observer: Observer) {
+        self.reset();
+        queue.write_buffer(
+            &self.cie_buffer,
+            0,
+            bytemuck::cast_slice(&spectrum::generate_cie_xyz_table(observer)),
+        );
+    }
+
+    /// Render full passes until `samples` have accumulated, clearing the
+    /// film first unless an accumulation is already in progress. `progress`
+    /// is called after each submission and can stop early by returning false.
+    #[allow(clippy::too_many_arguments)]
+    pub fn accumulate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        output: &wgpu::Texture,
+        size: &winit::dpi::PhysicalSize<u32>,
+        scene: &Scene,
+        samples: u32,
+        mut progress: impl FnMut(&ComputePass) -> bool,
+    ) {
+        // Every sample counts towards the image, so skip the previews
+        self.preview_next_frame = false;
+        self.pixel_stride = 1;
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        if self.iteration == 0 {
+            encoder.clear_texture(
+                output,
+                &wgpu::ImageSubresourceRange {
+                    aspect: wgpu::TextureAspect::All,
+                    base_mip_level: 0,
+                    mip_level_count: None,
+                    base_array_layer: 0,
+                    array_layer_count: None,
+                },
+            );
+        }
+        while self.iteration < samples {
+            self.render(device, &mut encoder, size, scene);
+            // Submit in batches to keep individual submissions short
+            if self.iteration.is_multiple_of(ACCUMULATE_BATCH) || self.iteration == samples {
+                queue.submit(Some(encoder.finish()));
+                encoder = device.create_command_encoder(&Default::default());
+                if !progress(self) {
+                    break;
+                }
+            }
+        }
+        queue.submit(Some(encoder.finish()));
     }
 
     pub fn update(&mut self, queue: &wgpu::Queue, camera_uniform: CameraUniform) {
+        self.reset();
         self.preview_next_frame = true;
         queue.write_buffer(
             &self.camera_buffer,
@@ -271,6 +749,36 @@ impl ComputePass {
     }
 }
 
+fn aov_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::ReadWrite,
+            format: wgpu::TextureFormat::Rgba32Float,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
+
+fn aov_entry(views: &[wgpu::TextureView; 4], index: usize) -> wgpu::BindGroupEntry<'_> {
+    wgpu::BindGroupEntry {
+        binding: AOV_FIRST_BINDING + index as u32,
+        resource: wgpu::BindingResource::TextureView(&views[index]),
+    }
+}
+
+/// Seed of pass `iteration`, hashed so that neighbouring passes decorrelate.
+fn frame_seed(seed: u32, iteration: u32) -> u32 {
+    // PCG output permutation
+    let state = (seed ^ iteration.wrapping_mul(0x9e37_79b9))
+        .wrapping_mul(747_796_405)
+        .wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ConfigData {
@@ -280,6 +788,8 @@ pub struct ConfigData {
     pub seed: u32,
     pub photon_radius: f32,
     pub iteration: u32,
-    _pad0: f32,
-    _pad1: f32,
+    pub adaptive_threshold: f32,
+    pub adaptive_min_samples: u32,
+    pub pixel_stride: u32,
+    pub _pad: [u32; 3],
 }
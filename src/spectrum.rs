@@ -1,6 +1,28 @@
 #![allow(dead_code)]
 #![allow(clippy::excessive_precision)]
 
+use crate::color::ColorSpace;
+
+/// CIE standard colorimetric observer used to turn spectra into XYZ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Observer {
+    /// CIE 1931 2° observer.
+    Cie1931,
+    /// CIE 1964 10° supplementary observer.
+    Cie1964,
+}
+
+impl Observer {
+    pub const ALL: [Observer; 2] = [Observer::Cie1931, Observer::Cie1964];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Observer::Cie1931 => "CIE 1931 2°",
+            Observer::Cie1964 => "CIE 1964 10°",
+        }
+    }
+}
+
 // CIE 1931 2° standard observer, 380-780nm at 5nm steps
 // From pbrt-v4 CIE data (360-830nm 1nm, subsampled every 5nm)
 const CIE_X: [f32; 81] = [
@@ -255,30 +277,31 @@ const CIE_Z: [f32; 81] = [
     0.000000000000,
 ];
 
-// sRGB to XYZ matrix (D65 white point)
-// XYZ to sRGB linear conversion (D65 white point)
-const XYZ_TO_SRGB: [[f32; 3]; 3] = [
-    [3.2404542, -1.5371385, -0.4985314],
-    [-0.9692660, 1.8760108, 0.0415560],
-    [0.0556434, -0.2040259, 1.0572252],
-];
+/// Generate the colour matching function lookup table for `observer`.
+/// Returns 81 entries, each [x, y, z, 0], mapping a unit spectral radiance
+/// at wavelength (380 + i*5) nm to CIE XYZ.
+/// The wavelength sampling pdf is divided out in the shader.
+pub fn generate_cie_xyz_table(observer: Observer) -> Vec<[f32; 4]> {
+    (0..81)
+        .map(|i| match observer {
+            Observer::Cie1931 => [CIE_X[i], CIE_Y[i], CIE_Z[i], 0.0],
+            Observer::Cie1964 => {
+                let [x, y, z] = cie1964_xyz(380.0 + 5.0 * i as f32);
+                [x, y, z, 0.0]
+            }
+        })
+        .collect()
+}
 
-/// Generate the CIE→sRGB lookup table.
-/// Returns 81 entries, each [r, g, b, 0], mapping a unit spectral radiance
-/// at wavelength (380 + i*5) nm to linear sRGB.
-/// The 400x Monte Carlo PDF correction is applied in the shader.
-pub fn generate_cie_to_rgb_table() -> Vec<[f32; 4]> {
-    let mut table = Vec::with_capacity(81);
-    for i in 0..81 {
-        let x = CIE_X[i];
-        let y = CIE_Y[i];
-        let z = CIE_Z[i];
-        let r = XYZ_TO_SRGB[0][0] * x + XYZ_TO_SRGB[0][1] * y + XYZ_TO_SRGB[0][2] * z;
-        let g = XYZ_TO_SRGB[1][0] * x + XYZ_TO_SRGB[1][1] * y + XYZ_TO_SRGB[1][2] * z;
-        let b = XYZ_TO_SRGB[2][0] * x + XYZ_TO_SRGB[2][1] * y + XYZ_TO_SRGB[2][2] * z;
-        table.push([r, g, b, 0.0]);
-    }
-    table
+/// CIE 1964 10° colour matching functions from the analytic fit of
+/// Wyman, Sloan and Shirley (2013), accurate to within a few percent.
+fn cie1964_xyz(lambda_nm: f32) -> [f32; 3] {
+    let l = lambda_nm;
+    let x = 0.398 * (-1250.0 * ((l + 570.1) / 1014.0).ln().powi(2)).exp()
+        + 1.132 * (-234.0 * ((1338.0 - l) / 743.5).ln().powi(2)).exp();
+    let y = 1.011 * (-0.5 * ((l - 556.1) / 46.14).powi(2)).exp();
+    let z = 2.060 * (-32.0 * ((l - 265.8) / 180.4).ln().powi(2)).exp();
+    [x, y, z]
 }
 
 /// Blackbody spectral radiance (Planck's law), normalized for rendering.
@@ -293,3 +316,157 @@ pub fn blackbody(lambda_nm: f32, temp: f32) -> f32 {
     let l = lambda_nm * 1e-9;
     c1 / (l.powi(5) * ((c2 / (l * temp)).exp() - 1.0)) * 1e-14
 }
+
+/// Sigmoid-polynomial reflectance spectrum (Jakob & Hanika 2019).
+///
+/// The spectrum is `s(x) = sigmoid(c0 * x^2 + c1 * x + c2)` where `x` is the
+/// wavelength normalised to [0, 1] over 380-780nm, so it is smooth and
+/// bounded in (0, 1) like a real reflectance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RgbSigmoidPolynomial {
+    pub c0: f32,
+    pub c1: f32,
+    pub c2: f32,
+}
+
+// Continuation steps from mid grey to the target colour, and Gauss-Newton
+// iterations per step.
+const SIGMOID_FIT_STEPS: usize = 16;
+const SIGMOID_FIT_ITERATIONS: usize = 16;
+
+impl RgbSigmoidPolynomial {
+    /// Fit a spectrum whose linear sRGB colour matches `rgb` (each channel in [0, 1]).
+    ///
+    /// Colours are integrated under an equal-energy illuminant and divided by the
+    /// colour of a flat unit spectrum, so greys map to flat spectra.
+    pub fn from_rgb(rgb: [f32; 3]) -> Self {
+        let target = rgb.map(|c| (c as f64).clamp(1e-4, 1.0 - 1e-4));
+
+        // Greys have an exact closed-form solution
+        if target[0] == target[1] && target[1] == target[2] {
+            let v = target[0];
+            return Self {
+                c0: 0.0,
+                c1: 0.0,
+                c2: ((v - 0.5) / (v * (1.0 - v)).sqrt()) as f32,
+            };
+        }
+
+        // Walk the target in from mid grey (c = 0) so each solve starts close by
+        let mut c = [0.0f64; 3];
+        for step in 1..=SIGMOID_FIT_STEPS {
+            let t = step as f64 / SIGMOID_FIT_STEPS as f64;
+            let goal = target.map(|v| 0.5 + (v - 0.5) * t);
+            for _ in 0..SIGMOID_FIT_ITERATIONS {
+                let (rgb, jacobian) = sigmoid_rgb_and_jacobian(c);
+                let residual = [rgb[0] - goal[0], rgb[1] - goal[1], rgb[2] - goal[2]];
+                if residual.iter().map(|r| r * r).sum::<f64>() < 1e-12 {
+                    break;
+                }
+                let Some(delta) = solve_3x3(jacobian, residual) else {
+                    break;
+                };
+                for i in 0..3 {
+                    c[i] -= delta[i];
+                }
+            }
+        }
+
+        Self {
+            c0: c[0] as f32,
+            c1: c[1] as f32,
+            c2: c[2] as f32,
+        }
+    }
+
+    pub fn evaluate(&self, lambda_nm: f32) -> f32 {
+        let x = (lambda_nm - 380.0) / 400.0;
+        sigmoid(((self.c0 * x + self.c1) * x + self.c2) as f64) as f32
+    }
+
+    /// Coefficients packed for upload, with `scale` in the last lane.
+    pub fn to_gpu(self, scale: f32) -> [f32; 4] {
+        [self.c0, self.c1, self.c2, scale]
+    }
+}
+
+/// Uplift an RGB colour that may exceed 1 (e.g. an emitter tint).
+/// Returns packed coefficients with the brightness scale in the last lane.
+pub fn rgb_to_unbounded_spectrum(rgb: [f32; 3]) -> [f32; 4] {
+    let m = rgb[0].max(rgb[1]).max(rgb[2]);
+    if m <= 0.0 {
+        return [0.0, 0.0, 0.0, 0.0];
+    }
+    let scale = 2.0 * m;
+    RgbSigmoidPolynomial::from_rgb(rgb.map(|c| c / scale)).to_gpu(scale)
+}
+
+fn sigmoid(z: f64) -> f64 {
+    if z.is_infinite() {
+        return if z > 0.0 { 1.0 } else { 0.0 };
+    }
+    0.5 + z / (2.0 * (1.0 + z * z).sqrt())
+}
+
+/// Linear sRGB of the spectrum `c`, relative to a flat unit spectrum, together
+/// with its Jacobian with respect to the coefficients (`jacobian[channel][coeff]`).
+fn sigmoid_rgb_and_jacobian(c: [f64; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut xyz = [0.0f64; 3];
+    let mut dxyz = [[0.0f64; 3]; 3];
+    let mut white = [0.0f64; 3];
+    for i in 0..81 {
+        let x = i as f64 / 80.0;
+        let z = (c[0] * x + c[1]) * x + c[2];
+        let s = sigmoid(z);
+        let ds = 0.5 / (1.0 + z * z).powf(1.5);
+        let cmf = [CIE_X[i] as f64, CIE_Y[i] as f64, CIE_Z[i] as f64];
+        let powers = [x * x, x, 1.0];
+        for ch in 0..3 {
+            xyz[ch] += s * cmf[ch];
+            white[ch] += cmf[ch];
+            for k in 0..3 {
+                dxyz[ch][k] += ds * powers[k] * cmf[ch];
+            }
+        }
+    }
+
+    let xyz_to_srgb = ColorSpace::Srgb.xyz_to_rgb();
+    let to_rgb = |v: [f64; 3]| -> [f64; 3] {
+        [0, 1, 2].map(|r| (0..3).map(|k| xyz_to_srgb[k][r] as f64 * v[k]).sum())
+    };
+    let white_rgb = to_rgb(white);
+    let rgb = to_rgb(xyz);
+    let mut jacobian = [[0.0f64; 3]; 3];
+    for k in 0..3 {
+        let col = to_rgb([dxyz[0][k], dxyz[1][k], dxyz[2][k]]);
+        for ch in 0..3 {
+            jacobian[ch][k] = col[ch] / white_rgb[ch];
+        }
+    }
+    (
+        [0, 1, 2].map(|ch| rgb[ch] / white_rgb[ch]),
+        jacobian,
+    )
+}
+
+fn solve_3x3(a: [[f64; 3]; 3], b: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: [[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+    let d = det(a);
+    if d.abs() < 1e-15 {
+        return None;
+    }
+    // Cramer's rule
+    let mut x = [0.0f64; 3];
+    for (col, xi) in x.iter_mut().enumerate() {
+        let mut m = a;
+        for row in 0..3 {
+            m[row][col] = b[row];
+        }
+        *xi = det(m) / d;
+    }
+    Some(x)
+}
@@ -0,0 +1,301 @@
+//! Temporal reprojection of the image while the camera moves, so navigation
+//! shows a steadily refining picture rather than a fresh sample per frame.
+//! See temporal.wgsl.
+
+use crate::{aov::AovTextures, camera::CameraUniform};
+
+pub struct Temporal {
+    pub enabled: bool,
+    reproject_pipeline: wgpu::ComputePipeline,
+    resolve_pipeline: wgpu::ComputePipeline,
+    reproject_layout: wgpu::BindGroupLayout,
+    resolve_layout: wgpu::BindGroupLayout,
+    /// Camera the history was seen from, then the current one.
+    camera_buffer: wgpu::Buffer,
+    textures: TemporalTextures,
+    reproject_bind_group: wgpu::BindGroup,
+    resolve_bind_group: wgpu::BindGroup,
+    /// The camera moved since the last frame and the history awaits reprojection.
+    pending: bool,
+    /// The history shows the scene as it is now, if from another viewpoint.
+    history_valid: bool,
+}
+
+struct TemporalTextures {
+    /// Reprojected history, with its weight in samples in alpha.
+    prior: wgpu::Texture,
+    /// Image last shown, with its weight in samples in alpha.
+    history_color: wgpu::Texture,
+    /// First hit position of the history, with 1 in w for a hit.
+    history_position: wgpu::Texture,
+    history_normal: wgpu::Texture,
+    /// Blend of prior and film, with a sample count of 1 in alpha.
+    output: wgpu::Texture,
+}
+
+impl Temporal {
+    pub fn new(device: &wgpu::Device, film_view: &wgpu::TextureView, aovs: &AovTextures) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Temporal Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("kernels/temporal.wgsl").into()),
+        });
+
+        let reproject_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("reproject_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                texture_layout_entry(1),
+                texture_layout_entry(2),
+                texture_layout_entry(3),
+                texture_layout_entry(4),
+                texture_layout_entry(5),
+                texture_layout_entry(6),
+                storage_layout_entry(7),
+            ],
+        });
+        let resolve_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("resolve_bind_group_layout"),
+            entries: &[
+                texture_layout_entry(1),
+                texture_layout_entry(2),
+                texture_layout_entry(3),
+                texture_layout_entry(8),
+                texture_layout_entry(9),
+                storage_layout_entry(10),
+                storage_layout_entry(11),
+                storage_layout_entry(12),
+                storage_layout_entry(13),
+            ],
+        });
+
+        let pipeline = |layout: &wgpu::BindGroupLayout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("temporal_pipeline_layout"),
+                bind_group_layouts: &[Some(layout)],
+                immediate_size: 0,
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("temporal_camera_buffer"),
+            size: 2 * std::mem::size_of::<CameraUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let textures = TemporalTextures::new(device, aovs.albedo.size());
+        let (reproject_bind_group, resolve_bind_group) = create_bind_groups(
+            device,
+            (&reproject_layout, &resolve_layout),
+            &camera_buffer,
+            &textures,
+            film_view,
+            aovs,
+        );
+        Self {
+            enabled: true,
+            reproject_pipeline: pipeline(&reproject_layout, "cs_reproject"),
+            resolve_pipeline: pipeline(&resolve_layout, "cs_resolve"),
+            reproject_layout,
+            resolve_layout,
+            camera_buffer,
+            textures,
+            reproject_bind_group,
+            resolve_bind_group,
+            pending: false,
+            history_valid: false,
+        }
+    }
+
+    /// View of the blended image, to display in place of the film.
+    pub fn output_view(&self) -> wgpu::TextureView {
+        self.textures.output.create_view(&Default::default())
+    }
+
+    /// Rebind to the film and AOVs of a resized accumulation.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        film_view: &wgpu::TextureView,
+        aovs: &AovTextures,
+    ) {
+        self.textures = TemporalTextures::new(device, aovs.albedo.size());
+        (self.reproject_bind_group, self.resolve_bind_group) = create_bind_groups(
+            device,
+            (&self.reproject_layout, &self.resolve_layout),
+            &self.camera_buffer,
+            &self.textures,
+            film_view,
+            aovs,
+        );
+        self.invalidate();
+    }
+
+    /// Carry the history over from `previous` to `current` on the next frame.
+    /// Moves before that frame keep the viewpoint the history was seen from.
+    pub fn camera_moved(
+        &mut self,
+        queue: &wgpu::Queue,
+        previous: CameraUniform,
+        current: CameraUniform,
+    ) {
+        let size = std::mem::size_of::<CameraUniform>() as u64;
+        if !self.pending {
+            queue.write_buffer(&self.camera_buffer, 0, bytemuck::bytes_of(&previous));
+        }
+        queue.write_buffer(&self.camera_buffer, size, bytemuck::bytes_of(&current));
+        self.pending = true;
+    }
+
+    /// Drop the history, for changes that reprojection cannot follow.
+    pub fn invalidate(&mut self) {
+        self.pending = false;
+        self.history_valid = false;
+    }
+
+    /// Blend the history into the current accumulation, reprojecting it
+    /// first if the camera moved. Runs after the compute pass.
+    pub fn run(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let size = self.textures.output.size();
+        let workgroups = (size.width.div_ceil(8), size.height.div_ceil(8));
+        if self.pending && self.history_valid {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            compute_pass.set_pipeline(&self.reproject_pipeline);
+            compute_pass.set_bind_group(0, &self.reproject_bind_group, &[]);
+            compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+        } else if !self.history_valid {
+            encoder.clear_texture(&self.textures.prior, &Default::default());
+        }
+        self.pending = false;
+        self.history_valid = true;
+
+        let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+        compute_pass.set_pipeline(&self.resolve_pipeline);
+        compute_pass.set_bind_group(0, &self.resolve_bind_group, &[]);
+        compute_pass.dispatch_workgroups(workgroups.0, workgroups.1, 1);
+    }
+}
+
+impl TemporalTextures {
+    fn new(device: &wgpu::Device, size: wgpu::Extent3d) -> Self {
+        let create = |label| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::STORAGE_BINDING
+                    | wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            })
+        };
+        Self {
+            prior: create("temporal_prior_texture"),
+            history_color: create("temporal_history_color_texture"),
+            history_position: create("temporal_history_position_texture"),
+            history_normal: create("temporal_history_normal_texture"),
+            output: create("temporal_output_texture"),
+        }
+    }
+}
+
+fn create_bind_groups(
+    device: &wgpu::Device,
+    (reproject_layout, resolve_layout): (&wgpu::BindGroupLayout, &wgpu::BindGroupLayout),
+    camera_buffer: &wgpu::Buffer,
+    textures: &TemporalTextures,
+    film_view: &wgpu::TextureView,
+    aovs: &AovTextures,
+) -> (wgpu::BindGroup, wgpu::BindGroup) {
+    let view = |texture: &wgpu::Texture| texture.create_view(&Default::default());
+    let [albedo, normal, position, _] = aovs.views();
+    let (prior, output) = (view(&textures.prior), view(&textures.output));
+    let history_color = view(&textures.history_color);
+    let history_position = view(&textures.history_position);
+    let history_normal = view(&textures.history_normal);
+    let entry = |binding, view| wgpu::BindGroupEntry {
+        binding,
+        resource: wgpu::BindingResource::TextureView(view),
+    };
+
+    let reproject = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("reproject_bind_group"),
+        layout: reproject_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: camera_buffer.as_entire_binding(),
+            },
+            entry(1, &albedo),
+            entry(2, &normal),
+            entry(3, &position),
+            entry(4, &history_color),
+            entry(5, &history_position),
+            entry(6, &history_normal),
+            entry(7, &prior),
+        ],
+    });
+    let resolve = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("resolve_bind_group"),
+        layout: resolve_layout,
+        entries: &[
+            entry(1, &albedo),
+            entry(2, &normal),
+            entry(3, &position),
+            entry(8, &prior),
+            entry(9, film_view),
+            entry(10, &output),
+            entry(11, &history_color),
+            entry(12, &history_position),
+            entry(13, &history_normal),
+        ],
+    });
+    (reproject, resolve)
+}
+
+fn texture_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+fn storage_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: wgpu::TextureFormat::Rgba32Float,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
@@ -1,6 +1,219 @@
+#![allow(clippy::excessive_precision)]
+
+/// Curve mapping exposed scene values to the display range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemapper {
+    /// `c / (1 + c)`.
+    #[default]
+    Reinhard,
+    /// Reinhard reaching 1 at the white point rather than at infinity.
+    ReinhardExtended,
+    /// Stephen Hill's fit of the ACES RRT and sRGB ODT.
+    Aces,
+    /// Troy Sobotka's AgX, with the polynomial fit of its contrast curve.
+    Agx,
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable,
+    /// Exposure only, clipped at 1.
+    Linear,
+}
+
+impl Tonemapper {
+    pub const ALL: [Self; 6] = [
+        Self::Reinhard,
+        Self::ReinhardExtended,
+        Self::Aces,
+        Self::Agx,
+        Self::Hable,
+        Self::Linear,
+    ];
+
+    pub fn next(self) -> Self {
+        Self::ALL[(self as usize + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Reinhard => "reinhard",
+            Self::ReinhardExtended => "reinhard-extended",
+            Self::Aces => "aces",
+            Self::Agx => "agx",
+            Self::Hable => "hable",
+            Self::Linear => "linear",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+}
+
+/// White point of the extended Reinhard curve unless chosen otherwise.
+pub const DEFAULT_WHITE: f32 = 4.0;
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct TonemapUniform {
+    /// Exposure scale from the camera, applied before the curve.
     pub key: f32,
     pub saturation: f32,
+    /// A `Tonemapper` as u32.
+    pub tonemapper: u32,
+    /// Exposed value mapped to 1 by the extended Reinhard curve.
+    pub white: f32,
+    /// Nonzero to take the key from the auto exposure state instead.
+    pub auto_exposure: u32,
+    pub _pad: [u32; 3],
+}
+
+impl TonemapUniform {
+    pub fn new(tonemapper: Tonemapper, key: f32, saturation: f32, white: f32) -> Self {
+        Self {
+            key,
+            saturation,
+            tonemapper: tonemapper as u32,
+            white,
+            auto_exposure: 0,
+            _pad: [0; 3],
+        }
+    }
+
+    /// The curve of `tonemap` in blit.wgsl, for display images written on the CPU.
+    pub fn apply(&self, rgb: [f32; 3], luminance: [f32; 3]) -> [f32; 3] {
+        let v = rgb.map(|v| v.max(0.0) * self.key);
+        let c = match Tonemapper::ALL.get(self.tonemapper as usize) {
+            Some(Tonemapper::ReinhardExtended) => {
+                let w2 = self.white * self.white;
+                v.map(|v| (v * (1.0 + v / w2) / (1.0 + v)).min(1.0))
+            }
+            Some(Tonemapper::Aces) => aces(v),
+            Some(Tonemapper::Agx) => agx(v),
+            Some(Tonemapper::Hable) => {
+                let white = hable_partial(HABLE_WHITE);
+                v.map(|v| hable_partial(v * HABLE_EXPOSURE_BIAS) / white)
+            }
+            Some(Tonemapper::Linear) => v.map(|v| v.min(1.0)),
+            _ => v.map(|v| v / (1.0 + v)),
+        };
+        let lum = c[0] * luminance[0] + c[1] * luminance[1] + c[2] * luminance[2];
+        c.map(|v| lum + (v - lum) * self.saturation)
+    }
+}
+
+// Matrices below are stored by columns, as the mat3x3 constants in blit.wgsl
+
+const ACES_INPUT: [[f32; 3]; 3] = [
+    [0.59719, 0.07600, 0.02840],
+    [0.35458, 0.90834, 0.13383],
+    [0.04823, 0.01566, 0.83777],
+];
+const ACES_OUTPUT: [[f32; 3]; 3] = [
+    [1.60475, -0.10208, -0.00327],
+    [-0.53108, 1.10813, -0.07276],
+    [-0.07367, -0.00605, 1.07602],
+];
+
+const AGX_INSET: [[f32; 3]; 3] = [
+    [0.842479062253094, 0.0423282422610123, 0.0423756549057051],
+    [0.0784335999999992, 0.878468636469772, 0.0784336],
+    [0.0792237451477643, 0.0791661274605434, 0.879142973793104],
+];
+const AGX_OUTSET: [[f32; 3]; 3] = [
+    [1.19687900512017, -0.0528968517574562, -0.0529716355144438],
+    [-0.0980208811401368, 1.15190312990417, -0.0980434501171241],
+    [-0.0990297440797205, -0.0989611768448433, 1.15107367264116],
+];
+const AGX_MIN_EV: f32 = -12.47393;
+const AGX_MAX_EV: f32 = 4.026069;
+
+const HABLE_EXPOSURE_BIAS: f32 = 2.0;
+const HABLE_WHITE: f32 = 11.2;
+
+fn transform(columns: &[[f32; 3]; 3], v: [f32; 3]) -> [f32; 3] {
+    std::array::from_fn(|i| columns[0][i] * v[0] + columns[1][i] * v[1] + columns[2][i] * v[2])
+}
+
+fn aces(v: [f32; 3]) -> [f32; 3] {
+    let v = transform(&ACES_INPUT, v).map(|v| {
+        let a = v * (v + 0.0245786) - 0.000090537;
+        let b = v * (0.983729 * v + 0.432951) + 0.238081;
+        a / b
+    });
+    transform(&ACES_OUTPUT, v).map(|v| v.clamp(0.0, 1.0))
+}
+
+fn agx(v: [f32; 3]) -> [f32; 3] {
+    let v = transform(&AGX_INSET, v).map(|v| {
+        let x = (v.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV) - AGX_MIN_EV)
+            / (AGX_MAX_EV - AGX_MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    });
+    // The curve ends in display encoding; undo its 2.2 gamma for the output
+    transform(&AGX_OUTSET, v).map(|v| v.max(0.0).powf(2.2))
+}
+
+fn hable_partial(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REC709_LUMINANCE: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+    fn grey(tonemapper: Tonemapper, key: f32, v: f32) -> f32 {
+        let uniform = TonemapUniform::new(tonemapper, key, 1.0, DEFAULT_WHITE);
+        uniform.apply([v; 3], REC709_LUMINANCE)[1]
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn curves_match_the_blit_shader() {
+        assert_close(grey(Tonemapper::Reinhard, 1.0, 1.0), 0.5);
+        assert_close(grey(Tonemapper::ReinhardExtended, 1.0, DEFAULT_WHITE), 1.0);
+        assert_close(grey(Tonemapper::Linear, 1.0, 0.25), 0.25);
+        assert_close(grey(Tonemapper::Linear, 1.0, 2.0), 1.0);
+        assert_close(grey(Tonemapper::Aces, 1.0, 0.18), 0.10559);
+        assert_close(grey(Tonemapper::Aces, 1.0, 1.0), 0.61911);
+        assert_close(grey(Tonemapper::Agx, 1.0, 0.18), 0.21453);
+        assert_close(grey(Tonemapper::Agx, 1.0, 1.0), 0.59021);
+        assert_close(grey(Tonemapper::Hable, 1.0, 0.18), 0.12834);
+        assert_close(
+            grey(Tonemapper::Hable, 1.0, HABLE_WHITE / HABLE_EXPOSURE_BIAS),
+            1.0,
+        );
+    }
+
+    #[test]
+    fn key_scales_before_the_curve() {
+        assert_close(grey(Tonemapper::Reinhard, 4.0, 0.25), 0.5);
+        assert_close(
+            grey(Tonemapper::ReinhardExtended, 2.0, DEFAULT_WHITE / 2.0),
+            1.0,
+        );
+    }
+
+    #[test]
+    fn saturation_mixes_with_luminance() {
+        let rgb = [1.0, 0.0, 0.0];
+        let grey = TonemapUniform::new(Tonemapper::Linear, 1.0, 0.0, DEFAULT_WHITE)
+            .apply(rgb, REC709_LUMINANCE);
+        for channel in grey {
+            assert_close(channel, 0.2126);
+        }
+        let full = TonemapUniform::new(Tonemapper::Linear, 1.0, 1.0, DEFAULT_WHITE)
+            .apply(rgb, REC709_LUMINANCE);
+        assert_eq!(full, rgb);
+    }
 }